@@ -0,0 +1,157 @@
+//! Benchmarks for the controller's dispatch hot path.
+//!
+//! Every scenario is a synthetic snapshot of the controller's state for a building with
+//! 10, 100 or 1000 floors and 1, 10 or 50 elevators:
+//!
+//! - Elevators are spread evenly over the shaft. Even elevators travel up and odd ones
+//!   travel down, each with a handful of stops ahead of them.
+//! - Every tenth floor has a pending call in each direction.
+//!
+//! `find_best_elevator_match` looks up a single call, `process_waiting_list` assigns all
+//! pending calls, and `handle_event` runs a full pass for one new call, including the
+//! waiting list.
+
+use std::collections::{BTreeSet, HashSet};
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use elevator::building::{BuildingCommand, BuildingEvent, Direction, FloorId};
+use elevator::controller::{
+    find_best_elevator_match, handle_event, process_waiting_list, ControllerConfig,
+    ElevatorButtonsInfo, TieBreak,
+};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+
+const FLOOR_COUNTS: [usize; 3] = [10, 100, 1000];
+const ELEVATOR_COUNTS: [usize; 3] = [1, 10, 50];
+const STOPS_PER_ELEVATOR: usize = 5;
+
+type Calls = HashSet<(FloorId, Direction)>;
+
+fn synthetic_elevators(floors_count: usize, elevator_count: usize) -> Vec<ElevatorButtonsInfo> {
+    (0..elevator_count)
+        .map(|id| {
+            let position = id * floors_count / elevator_count;
+            let stride = (floors_count / STOPS_PER_ELEVATOR / 2).max(1);
+            let (direction, should_visit): (_, BTreeSet<FloorId>) = if id % 2 == 0 {
+                let stops = (1..=STOPS_PER_ELEVATOR).map(|i| position + i * stride);
+                (
+                    Direction::Up,
+                    stops.filter(|&fl| fl < floors_count).collect(),
+                )
+            } else {
+                let stops =
+                    (1..=STOPS_PER_ELEVATOR).filter_map(|i| position.checked_sub(i * stride));
+                (Direction::Down, stops.collect())
+            };
+            ElevatorButtonsInfo::new(position, Some(direction), should_visit)
+        })
+        .collect()
+}
+
+fn synthetic_calls(floors_count: usize) -> Calls {
+    (0..floors_count)
+        .step_by(10)
+        .flat_map(|floor| [(floor, Direction::Up), (floor, Direction::Down)])
+        .collect()
+}
+
+/// A sender whose buffer is large enough to take every command a benchmark iteration sends.
+fn command_channel() -> (
+    mpsc::Sender<BuildingCommand>,
+    mpsc::Receiver<BuildingCommand>,
+) {
+    mpsc::channel(10_000)
+}
+
+fn scenarios() -> impl Iterator<Item = (usize, usize)> {
+    FLOOR_COUNTS
+        .into_iter()
+        .flat_map(|floors| ELEVATOR_COUNTS.into_iter().map(move |cars| (floors, cars)))
+}
+
+fn bench_find_best_elevator_match(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find_best_elevator_match");
+    for (floors_count, elevator_count) in scenarios() {
+        let elevators = synthetic_elevators(floors_count, elevator_count);
+        let floor = floors_count / 2;
+        group.bench_function(
+            BenchmarkId::from_parameter(format!("{floors_count}_floors/{elevator_count}_cars")),
+            |b| {
+                b.iter(|| {
+                    find_best_elevator_match(floor, Direction::Up, &elevators, TieBreak::LowestId)
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_process_waiting_list(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let config = ControllerConfig::default();
+    let mut group = c.benchmark_group("process_waiting_list");
+    for (floors_count, elevator_count) in scenarios() {
+        let elevators = synthetic_elevators(floors_count, elevator_count);
+        let calls = synthetic_calls(floors_count);
+        group.bench_function(
+            BenchmarkId::from_parameter(format!("{floors_count}_floors/{elevator_count}_cars")),
+            |b| {
+                b.iter_batched(
+                    || (elevators.clone(), calls.clone(), command_channel()),
+                    |(mut elevators, mut calls, (tx, _rx))| {
+                        rt.block_on(process_waiting_list(
+                            floors_count,
+                            &mut elevators,
+                            &mut calls,
+                            &tx,
+                            &config,
+                        ))
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_handle_event(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let config = ControllerConfig::default();
+    let mut group = c.benchmark_group("handle_event");
+    for (floors_count, elevator_count) in scenarios() {
+        let elevators = synthetic_elevators(floors_count, elevator_count);
+        let calls = synthetic_calls(floors_count);
+        let evt = BuildingEvent::CallButtonPressed(floors_count - 1, Direction::Down, 0);
+        group.bench_function(
+            BenchmarkId::from_parameter(format!("{floors_count}_floors/{elevator_count}_cars")),
+            |b| {
+                b.iter_batched(
+                    || (elevators.clone(), calls.clone(), command_channel()),
+                    |(mut elevators, mut calls, (tx, _rx))| {
+                        rt.block_on(handle_event(
+                            evt.clone(),
+                            floors_count,
+                            &mut elevators,
+                            &mut calls,
+                            &mut HashSet::new(),
+                            &tx,
+                            &config,
+                        ))
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_find_best_elevator_match,
+    bench_process_waiting_list,
+    bench_handle_event
+);
+criterion_main!(benches);
@@ -0,0 +1,463 @@
+//! Checks recorded building events for consistency, so that a run that goes wrong can be
+//! caught, for example in CI.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use tracing::warn;
+
+use crate::building::{BuildingEvent, Direction, ElevatorId, FloorId, PassengerId};
+use crate::event_stream::{EventStream, StreamItem};
+
+/// TimedEvent is a building event, or events missed by the recording, along with when it
+/// happened, in milliseconds of simulated time since the building started as of the last
+/// `BuildingEvent::Clock`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedEvent {
+    pub at_ms: u64,
+    pub item: StreamItem,
+}
+
+/// Violation is an inconsistency found by `validate_log`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The recording missed events, so the log can't be checked as a whole.
+    Lagged { at_ms: u64, missed: u64 },
+    /// The event happened earlier than the one before it.
+    OutOfOrder { at_ms: u64, previous_ms: u64 },
+    /// The elevator arrived at a floor more than one floor away from the last one it reported.
+    Jumped {
+        at_ms: u64,
+        elevator: ElevatorId,
+        from: FloorId,
+        to: FloorId,
+    },
+    /// The elevator opened its doors at a floor other than the one it was at.
+    DoorsOpenedAway {
+        at_ms: u64,
+        elevator: ElevatorId,
+        floor: FloorId,
+        position: FloorId,
+    },
+    /// A passenger boarded the elevator at a floor where its doors weren't open.
+    BoardedWithDoorsClosed {
+        at_ms: u64,
+        elevator: ElevatorId,
+        floor: FloorId,
+    },
+    /// A passenger was delivered when everyone who had boarded had been delivered already.
+    DeliveredWithoutBoarding { at_ms: u64, floor: FloorId },
+    /// Nobody opened the doors at the floor after the call, by the end of the log.
+    UnansweredCall {
+        floor: FloorId,
+        direction: Direction,
+        since_ms: u64,
+    },
+    /// The passengers were aboard when the elevator last closed its doors, and it never
+    /// opened them again to let them off.
+    StillAboard {
+        elevator: ElevatorId,
+        passengers: Vec<PassengerId>,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Lagged { at_ms, missed } => {
+                write!(f, "recording missed {} events at {}ms", missed, at_ms)
+            }
+            Violation::OutOfOrder { at_ms, previous_ms } => write!(
+                f,
+                "event at {}ms comes after an event at {}ms",
+                at_ms, previous_ms
+            ),
+            Violation::Jumped {
+                at_ms,
+                elevator,
+                from,
+                to,
+            } => write!(
+                f,
+                "elevator {} went from floor {} to floor {} at {}ms",
+                elevator, from, to, at_ms
+            ),
+            Violation::DoorsOpenedAway {
+                at_ms,
+                elevator,
+                floor,
+                position,
+            } => write!(
+                f,
+                "elevator {} opened its doors at floor {} while at floor {} at {}ms",
+                elevator, floor, position, at_ms
+            ),
+            Violation::BoardedWithDoorsClosed {
+                at_ms,
+                elevator,
+                floor,
+            } => write!(
+                f,
+                "passenger boarded elevator {} at floor {} with its doors closed at {}ms",
+                elevator, floor, at_ms
+            ),
+            Violation::DeliveredWithoutBoarding { at_ms, floor } => write!(
+                f,
+                "passenger delivered to floor {} without boarding at {}ms",
+                floor, at_ms
+            ),
+            Violation::UnansweredCall {
+                floor,
+                direction,
+                since_ms,
+            } => write!(
+                f,
+                "call {} at floor {} unanswered since {}ms",
+                direction, floor, since_ms
+            ),
+            Violation::StillAboard {
+                elevator,
+                passengers,
+            } => write!(
+                f,
+                "passengers {:?} never got off elevator {}",
+                passengers, elevator
+            ),
+        }
+    }
+}
+
+/// Check that the events of a whole run, in the order they were sent, are consistent: that
+/// elevators move one floor at a time and open their doors where they are, that passengers
+/// only board through open doors and are delivered after boarding, and that by the end every
+/// call has been answered and everyone aboard has been let off. A log that missed events fails
+/// validation. Returns every violation found, in the order they were found.
+pub fn validate_log(events: &[TimedEvent]) -> Result<(), Vec<Violation>> {
+    let mut violations = Vec::new();
+    let mut previous_ms = 0;
+    let mut positions: HashMap<ElevatorId, FloorId> = HashMap::new();
+    // Floor each elevator has its doors open at, if they are open.
+    let mut doors_open: HashMap<ElevatorId, FloorId> = HashMap::new();
+    // Passengers aboard each elevator when it last closed its doors, until it opens them.
+    let mut aboard: BTreeMap<ElevatorId, Vec<PassengerId>> = BTreeMap::new();
+    // Calls waiting for an elevator, with when they were made.
+    let mut calls: HashMap<(FloorId, Direction), u64> = HashMap::new();
+    let mut boarded = 0;
+    let mut delivered = 0;
+
+    for &TimedEvent { at_ms, ref item } in events {
+        if at_ms < previous_ms {
+            violations.push(Violation::OutOfOrder { at_ms, previous_ms });
+        }
+        previous_ms = previous_ms.max(at_ms);
+        let event = match *item {
+            StreamItem::Event(ref event) => event,
+            StreamItem::Lagged(missed) => {
+                violations.push(Violation::Lagged { at_ms, missed });
+                continue;
+            }
+        };
+        match *event {
+            BuildingEvent::AtFloor(el, fl) => {
+                if let Some(from) = positions.insert(el, fl) {
+                    if from.abs_diff(fl) > 1 {
+                        violations.push(Violation::Jumped {
+                            at_ms,
+                            elevator: el,
+                            from,
+                            to: fl,
+                        });
+                    }
+                }
+                doors_open.remove(&el);
+            }
+            BuildingEvent::DoorsOpened(el, fl) => {
+                if let Some(&position) = positions.get(&el) {
+                    if position != fl {
+                        violations.push(Violation::DoorsOpenedAway {
+                            at_ms,
+                            elevator: el,
+                            floor: fl,
+                            position,
+                        });
+                    }
+                }
+                positions.insert(el, fl);
+                doors_open.insert(el, fl);
+                aboard.remove(&el);
+                // Everyone waiting at the floor may board, whichever way they are going.
+                calls.retain(|&(at, _), _| at != fl);
+            }
+            BuildingEvent::DoorsClosed(el, _, ref manifest) => {
+                doors_open.remove(&el);
+                aboard.insert(el, manifest.clone());
+            }
+            BuildingEvent::CallButtonPressed(fl, dir, _)
+            | BuildingEvent::AccessibleCallButtonPressed(fl, dir) => {
+                calls.entry((fl, dir)).or_insert(at_ms);
+            }
+            BuildingEvent::PassengerBoarded(el, fl) => {
+                boarded += 1;
+                if doors_open.get(&el) != Some(&fl) {
+                    violations.push(Violation::BoardedWithDoorsClosed {
+                        at_ms,
+                        elevator: el,
+                        floor: fl,
+                    });
+                }
+            }
+            // A delivery without a boarding isn't counted, so that it is only reported once.
+            BuildingEvent::PassengerDelivered(fl) if delivered == boarded => {
+                violations.push(Violation::DeliveredWithoutBoarding { at_ms, floor: fl });
+            }
+            BuildingEvent::PassengerDelivered(_) => delivered += 1,
+            _ => {}
+        }
+    }
+
+    let mut calls: Vec<_> = calls.into_iter().collect();
+    calls.sort_by_key(|&((floor, _), since_ms)| (since_ms, floor));
+    for ((floor, direction), since_ms) in calls {
+        violations.push(Violation::UnansweredCall {
+            floor,
+            direction,
+            since_ms,
+        });
+    }
+    for (elevator, passengers) in aboard {
+        if !passengers.is_empty() {
+            violations.push(Violation::StillAboard {
+                elevator,
+                passengers,
+            });
+        }
+    }
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+/// Length of the window handling capacity is measured over, in milliseconds.
+const HANDLING_CAPACITY_WINDOW_MS: u64 = 5 * 60 * 1000;
+
+/// The five-minute handling capacity of a recorded run: the percentage of the building's
+/// `population` delivered in the busiest five minutes of simulated time, as elevator systems
+/// are sized by. A run shorter than five minutes counts every delivery.
+pub fn handling_capacity(events: &[TimedEvent], population: usize) -> f64 {
+    let mut deliveries: Vec<u64> = events
+        .iter()
+        .filter(|evt| {
+            matches!(
+                evt.item,
+                StreamItem::Event(BuildingEvent::PassengerDelivered(_))
+            )
+        })
+        .map(|evt| evt.at_ms)
+        .collect();
+    deliveries.sort_unstable();
+    // The busiest window starts with a delivery, so only those windows are counted.
+    let mut busiest = 0;
+    let mut end = 0;
+    for (start, &start_ms) in deliveries.iter().enumerate() {
+        while end < deliveries.len() && deliveries[end] < start_ms + HANDLING_CAPACITY_WINDOW_MS {
+            end += 1;
+        }
+        busiest = busiest.max(end - start);
+    }
+    busiest as f64 * 100.0 / population.max(1) as f64
+}
+
+/// Record every event until the building stops, timing them by the building's
+/// `BuildingEvent::Clock`, so events are only as precise as its interval, see
+/// `Building::with_clock_interval`. Events missed because the recording fell behind are kept in
+/// the log, and make it fail validation. Record from a subscription with `Backpressure::Block`
+/// to miss none, see `Building::subscribe`.
+pub async fn record(events: impl Into<EventStream>) -> Vec<TimedEvent> {
+    let mut events = events.into();
+    let mut log = Vec::new();
+    let mut at_ms = 0;
+    while let Some(item) = events.next().await {
+        match item {
+            StreamItem::Event(BuildingEvent::Clock(ms)) => at_ms = ms,
+            StreamItem::Lagged(count) => warn!(missed = count, "event recording missed events"),
+            _ => {}
+        }
+        log.push(TimedEvent { at_ms, item });
+    }
+    log
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use BuildingEvent::*;
+
+    /// The events, one every 100 milliseconds.
+    fn timed(events: Vec<BuildingEvent>) -> Vec<TimedEvent> {
+        events
+            .into_iter()
+            .enumerate()
+            .map(|(i, event)| TimedEvent {
+                at_ms: i as u64 * 100,
+                item: StreamItem::Event(event),
+            })
+            .collect()
+    }
+
+    /// A passenger called from floor 1 and taken to floor 3, and another who stayed where
+    /// they were.
+    fn trip() -> Vec<BuildingEvent> {
+        vec![
+            CallButtonPressed(1, Direction::Up, 0),
+            AtFloor(0, 1),
+            DoorsOpened(0, 1),
+            PassengerBoarded(0, 1),
+            FloorButtonPressed(0, 3),
+            DoorsClosed(0, 1, vec![0]),
+            AtFloor(0, 2),
+            AtFloor(0, 3),
+            DoorsOpened(0, 3),
+            PassengerDelivered(3),
+            PassengerStayed(5),
+        ]
+    }
+
+    #[test]
+    fn consistent_log_is_valid() {
+        assert_eq!(validate_log(&timed(trip())), Ok(()));
+    }
+
+    #[test]
+    fn corrupted_log_reports_its_violations() {
+        let mut events = trip();
+        // Nobody boards, the elevator skips floor 2, and it never opens its doors at floor 3.
+        events.remove(6);
+        events.remove(3);
+        events.truncate(6);
+        events.push(PassengerDelivered(3));
+        events.push(CallButtonPressed(4, Direction::Down, 0));
+        let violations = validate_log(&timed(events)).unwrap_err();
+        assert_eq!(
+            violations,
+            [
+                Violation::Jumped {
+                    at_ms: 500,
+                    elevator: 0,
+                    from: 1,
+                    to: 3,
+                },
+                Violation::DeliveredWithoutBoarding {
+                    at_ms: 600,
+                    floor: 3,
+                },
+                Violation::UnansweredCall {
+                    floor: 4,
+                    direction: Direction::Down,
+                    since_ms: 700,
+                },
+                Violation::StillAboard {
+                    elevator: 0,
+                    passengers: vec![0],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn missed_events_fail_validation() {
+        let mut log = timed(trip());
+        log.insert(
+            4,
+            TimedEvent {
+                at_ms: 350,
+                item: StreamItem::Lagged(2),
+            },
+        );
+        assert_eq!(
+            validate_log(&log),
+            Err(vec![Violation::Lagged {
+                at_ms: 350,
+                missed: 2,
+            }])
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn recording_a_run_with_backpressure_misses_nothing() {
+        use crate::building::Building;
+        use crate::controller::ControllerConfig;
+        use crate::event_stream::Backpressure;
+
+        let mut building = Building::new(10, 2).with_clock_interval(100);
+        // Room for so few events that the building has to wait for the recording.
+        let recording = tokio::spawn(record(building.subscribe(Backpressure::Block, 2)));
+        let arrivals = [(0, 9), (8, 1), (3, 6), (5, 0)];
+        let (events, summary) =
+            crate::testing::run(building, ControllerConfig::default(), &arrivals).await;
+        let log = recording.await.unwrap();
+        assert_eq!(validate_log(&log), Ok(()));
+        let recorded: Vec<_> = log.iter().map(|evt| evt.item.clone()).collect();
+        let events: Vec<_> = events.into_iter().map(StreamItem::Event).collect();
+        assert_eq!(recorded, events);
+        // Timed by the building's clock, which tells the time at every tick.
+        assert!(log.last().unwrap().at_ms <= summary.simulated_ms);
+        assert!(log.last().unwrap().at_ms + 100 >= summary.simulated_ms);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn recording_leaves_out_the_time_spent_paused() {
+        use crate::building::{Building, BuildingCommand};
+        use crate::controller::ControllerConfig;
+        use crate::event_stream::Backpressure;
+        use crate::testing::Harness;
+        use std::time::Duration;
+
+        let mut building = Building::new(10, 1).with_clock_interval(100);
+        let recording = tokio::spawn(record(building.subscribe(Backpressure::Block, 1024)));
+        let mut harness = Harness::start(building, ControllerConfig::default());
+        harness.command(BuildingCommand::Pause).await;
+        harness.wait_for(|evt| *evt == BuildingEvent::Paused).await;
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        harness.command(BuildingCommand::Resume).await;
+        harness.arrive(0, 5).await;
+        harness.wait_for_deliveries(1).await;
+        let (_, summary) = harness.halt().await;
+        let log = recording.await.unwrap();
+        assert_eq!(validate_log(&log), Ok(()));
+        let last = log.last().unwrap().at_ms;
+        assert!(last <= summary.simulated_ms && last + 100 >= summary.simulated_ms);
+        // A minute paused would have counted in wall time.
+        assert!(last < 60 * 1000);
+    }
+
+    #[test]
+    fn handling_capacity_counts_the_busiest_five_minutes() {
+        const MINUTE_MS: u64 = 60 * 1000;
+        // Deliveries at these minutes, with other events in between.
+        let log: Vec<_> = [0.0, 1.0, 2.0, 4.5, 5.5, 6.0, 6.5, 7.0, 20.0]
+            .into_iter()
+            .flat_map(|minute: f64| {
+                let at_ms = (minute * MINUTE_MS as f64) as u64;
+                [
+                    TimedEvent {
+                        at_ms,
+                        item: StreamItem::Event(AtFloor(0, 3)),
+                    },
+                    TimedEvent {
+                        at_ms,
+                        item: StreamItem::Event(PassengerDelivered(3)),
+                    },
+                ]
+            })
+            .collect();
+        // By hand: the window from minute 2 up to, not including, minute 7 holds the 5
+        // deliveries at 2, 4.5, 5.5, 6 and 6.5, as does the one from minute 4.5, and no window
+        // holds more. 5 of a population of 20 is 25%.
+        assert_eq!(handling_capacity(&log, 20), 25.0);
+        // A run shorter than the window counts every delivery.
+        assert_eq!(handling_capacity(&log[..8], 20), 20.0);
+        assert_eq!(handling_capacity(&[], 20), 0.0);
+    }
+}
@@ -1,27 +1,344 @@
 //! The building simulates floors and elevators.
 
-use tokio::sync::{broadcast, mpsc};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::error::Error;
+use crate::event_stream::{Backpressure, EventStream};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio::task;
 use tokio::time;
+use tracing::{debug, info, warn};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Direction {
     Up,
     Down,
 }
 
+impl Direction {
+    /// The direction from floor `from` to floor `to`, or down if they are the same floor.
+    pub fn between(from: FloorId, to: FloorId) -> Self {
+        if from < to {
+            Direction::Up
+        } else {
+            Direction::Down
+        }
+    }
+
+    /// The other direction.
+    pub fn opposite(self) -> Self {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Up => write!(f, "up"),
+            Direction::Down => write!(f, "down"),
+        }
+    }
+}
+
+/// FloorLabels gives floors the names shown to people, such as `G` for the ground floor, while
+/// the simulation keeps numbering them from 0. Floors without a label show their number.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FloorLabels {
+    labels: Vec<String>,
+}
+
+impl FloorLabels {
+    /// Label the floors in order, starting from floor 0.
+    pub fn new(labels: Vec<String>) -> Self {
+        Self { labels }
+    }
+
+    /// The usual labels for `num_floors` floors: `G` for the ground floor, `M` for the
+    /// mezzanine above it if there is one, then `1`, `2` and so on, skipping 13.
+    pub fn conventional(num_floors: usize, mezzanine: bool) -> Self {
+        let mut labels = vec!["G".to_owned()];
+        if mezzanine {
+            labels.push("M".to_owned());
+        }
+        let numbered = num_floors.saturating_sub(labels.len());
+        labels.truncate(num_floors);
+        labels.extend(
+            (1..)
+                .filter(|&number: &usize| number != 13)
+                .take(numbered)
+                .map(|number| number.to_string()),
+        );
+        Self { labels }
+    }
+
+    /// The label of the given floor.
+    pub fn label(&self, floor: FloorId) -> String {
+        self.labels
+            .get(floor)
+            .cloned()
+            .unwrap_or_else(|| floor.to_string())
+    }
+
+    /// Show an event or command with these labels instead of floor numbers.
+    pub fn labelled<'a, T>(&'a self, value: &'a T) -> Labelled<'a, T> {
+        Labelled {
+            labels: Some(self),
+            value,
+        }
+    }
+}
+
+/// Labelled shows an event or command with floor labels, see `FloorLabels::labelled`.
+pub struct Labelled<'a, T> {
+    labels: Option<&'a FloorLabels>,
+    value: &'a T,
+}
+
+impl<T> Labelled<'_, T> {
+    fn floor(&self, floor: FloorId) -> String {
+        self.labels
+            .map_or_else(|| floor.to_string(), |labels| labels.label(floor))
+    }
+}
+
+/// StopPattern describes which floors an elevator is allowed to stop at. Buildings use
+/// skip-stop service during peaks so that each car makes fewer stops.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StopPattern {
+    /// The elevator stops at every floor.
+    #[default]
+    All,
+    /// The elevator only stops at odd floors.
+    Odd,
+    /// The elevator only stops at even floors.
+    Even,
+    /// The elevator only stops at every n-th floor, starting from the ground floor.
+    Every(usize),
+    /// The elevator belongs to a bank serving the floors from the first to the second,
+    /// inclusive. Very tall buildings split their elevators into banks that share a sky
+    /// lobby, where passengers change from one bank to another.
+    Bank(FloorId, FloorId),
+}
+
+impl StopPattern {
+    /// Returns true if an elevator with this pattern may stop at the given floor.
+    pub fn serves(&self, floor: FloorId) -> bool {
+        match *self {
+            StopPattern::All => true,
+            StopPattern::Odd => floor % 2 == 1,
+            StopPattern::Even => floor.is_multiple_of(2),
+            StopPattern::Every(n) => floor.is_multiple_of(n),
+            StopPattern::Bank(lowest, highest) => (lowest..=highest).contains(&floor),
+        }
+    }
+
+    /// Find the served floor closest to the given floor, if any floor is served at all.
+    pub fn nearest_served(&self, floor: FloorId, num_floors: usize) -> Option<FloorId> {
+        (0..num_floors)
+            .filter(|&fl| self.serves(fl))
+            .min_by_key(|&fl| fl.abs_diff(floor))
+    }
+}
+
+/// InvalidCalls decides what the building does with a passenger whose call is impossible,
+/// such as going up from the top floor.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidCalls {
+    /// Keep the passenger within the building, so that their call goes the only way it can.
+    #[default]
+    Normalize,
+    /// Turn the passenger away with `BuildingEvent::CallRejected`.
+    Reject,
+}
+
+/// BoardingOrder decides whether passengers getting off an elevator leave before those
+/// waiting get on, which matters when the elevator is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BoardingOrder {
+    /// Passengers get off first, so that a full elevator makes room for those waiting.
+    #[default]
+    AlightFirst,
+    /// Passengers waiting get on while those getting off are still aboard, so a full
+    /// elevator takes nobody at a stop even if someone gets off there.
+    BoardFirst,
+}
+
+/// ElevatorConfig describes the fixed properties of an elevator that the controller needs to
+/// know about.
+#[derive(Debug, Default, Clone)]
+pub struct ElevatorConfig {
+    /// Floors the elevator is allowed to stop at.
+    pub stop_pattern: StopPattern,
+    /// Accessible elevators move more gently and hold their doors open longer. Passengers
+    /// with reduced mobility only ride accessible elevators, if the building has any.
+    pub accessible: bool,
+    /// Floor the elevator returns to when idle, instead of the one the controller's parking
+    /// policy picks for it.
+    pub rest_floor: Option<FloorId>,
+    /// Real time the elevator takes to travel a floor at full speed, the slower way. See
+    /// `Building::with_floor_times` and `Building::with_time_scale`.
+    pub floor_time: time::Duration,
+    /// Floors no elevator stops at, see `Building::with_forbidden_floor`.
+    pub forbidden_floors: BTreeSet<FloorId>,
+}
+
+/// MotionProfile describes how elevators speed up and slow down on a trip. It only changes
+/// when they reach each floor.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MotionProfile {
+    /// Elevators travel every floor at full speed.
+    #[default]
+    Constant,
+    /// Elevators accelerate up to full speed and decelerate at a constant rate.
+    Trapezoidal,
+    /// Like `Trapezoidal`, but the acceleration itself builds up and eases off gradually
+    /// (limited jerk). Rides are smoother and short trips take slightly longer.
+    SCurve,
+}
+
+/// Full speed of an elevator, in floors per tick.
+const MAX_SPEED: f64 = 1.0;
+/// Acceleration of an elevator with a `Trapezoidal` or `SCurve` profile, in floors per tick².
+const MAX_ACCELERATION: f64 = 0.5;
+/// Jerk of an elevator with an `SCurve` profile, in floors per tick³.
+const MAX_JERK: f64 = 0.25;
+
+impl MotionProfile {
+    /// Ticks needed to travel `distance` floors from rest, speeding up as fast as allowed.
+    fn rise_ticks(&self, distance: f64) -> f64 {
+        match self {
+            MotionProfile::Constant => distance / MAX_SPEED,
+            MotionProfile::Trapezoidal => {
+                let accelerating_for = MAX_SPEED.powi(2) / (2.0 * MAX_ACCELERATION);
+                if distance <= accelerating_for {
+                    (2.0 * distance / MAX_ACCELERATION).sqrt()
+                } else {
+                    MAX_SPEED / MAX_ACCELERATION + (distance - accelerating_for) / MAX_SPEED
+                }
+            }
+            MotionProfile::SCurve => {
+                const STEP: f64 = 0.01;
+                let (mut ticks, mut travelled, mut speed, mut acceleration) =
+                    (0.0, 0.0, 0.0, 0.0_f64);
+                while travelled < distance {
+                    // Ease off the acceleration just in time to reach full speed smoothly.
+                    if speed + acceleration.powi(2) / (2.0 * MAX_JERK) >= MAX_SPEED {
+                        acceleration = f64::max(acceleration - MAX_JERK * STEP, 0.0);
+                    } else {
+                        acceleration = f64::min(acceleration + MAX_JERK * STEP, MAX_ACCELERATION);
+                    }
+                    speed = f64::min(speed + acceleration * STEP, MAX_SPEED);
+                    travelled += speed * STEP;
+                    ticks += STEP;
+                }
+                ticks
+            }
+        }
+    }
+
+    /// Ticks after setting off that an elevator on a trip of `trip` floors reaches its
+    /// `floor`-th floor. Elevators slow down the same way they speed up.
+    fn ticks_to_floor(&self, floor: usize, trip: usize) -> f64 {
+        let half = trip as f64 / 2.0;
+        if floor as f64 <= half {
+            self.rise_ticks(floor as f64)
+        } else {
+            2.0 * self.rise_ticks(half) - self.rise_ticks((trip - floor) as f64)
+        }
+    }
+
+    /// Ticks after setting off that an elevator on a trip of `trip` floors reaches each of its
+    /// floors, the first one first.
+    fn trip_ticks(&self, trip: usize) -> Vec<f64> {
+        (1..=trip)
+            .map(|floor| self.ticks_to_floor(floor, trip))
+            .collect()
+    }
+}
+
+/// Simulated time that passes at every tick.
+const TICK_MS: u64 = 100;
+
+/// Simulated time between two `BuildingEvent::Clock`, unless the building is given another
+/// interval with `Building::with_clock_interval`.
+const CLOCK_INTERVAL_MS: u64 = 60 * 1000;
+
+/// Events waiting for the slowest subscriber before the oldest is dropped, unless the building
+/// is given another capacity with `Building::with_events_capacity`.
+const EVENTS_CAPACITY: usize = 100;
+
+/// Number of ticks an accessible elevator takes to travel a single floor.
+const ACCESSIBLE_TICKS_PER_FLOOR: u32 = 2;
+/// Number of ticks an accessible elevator keeps its doors open at a stop.
+const ACCESSIBLE_DWELL_TICKS: u32 = 3;
+/// Number of ticks an elevator keeps its doors open at a ghost stop, see
+/// `BuildingEvent::GhostStop`, unless the building is given another with
+/// `Building::with_ghost_stop_dwell`.
+const GHOST_STOP_DWELL_TICKS: u32 = 1;
+
 /// A passenger is a person with a destination floor in mind.
 #[derive(Debug)]
 struct Passenger {
+    id: PassengerId,
+    /// Floor the passenger is riding to, which is a transfer floor if they change elevators
+    /// on the way.
     destination: FloorId,
-    arrived_at: std::time::Instant,
+    /// Where the passenger is going in the end, if they change elevators at `destination`.
+    final_destination: Option<FloorId>,
+    arrived_at: time::Instant,
+    reduced_mobility: bool,
+    /// True if the passenger only knows which way they are going, and picks a floor when
+    /// they board. Until then, `destination` is the last floor in that direction.
+    decides_at_boarding: bool,
+    /// Tick at which the passenger, who has just boarded, presses their floor button.
+    presses_button_at: Option<u64>,
+    /// Entrance of the floor where the passenger is waiting.
+    entrance: EntranceId,
+    /// True once the passenger has boarded an elevator, so that changing elevators doesn't
+    /// count as waiting again.
+    boarded: bool,
 }
 
 impl Passenger {
-    fn new(destination: FloorId) -> Self {
+    fn new(
+        id: PassengerId,
+        destination: FloorId,
+        reduced_mobility: bool,
+        entrance: EntranceId,
+    ) -> Self {
         Self {
+            id,
             destination,
-            arrived_at: std::time::Instant::now(),
+            final_destination: None,
+            arrived_at: time::Instant::now(),
+            reduced_mobility,
+            decides_at_boarding: false,
+            presses_button_at: None,
+            entrance,
+            boarded: false,
+        }
+    }
+
+    /// The event sent when this passenger calls an elevator at the given floor.
+    fn call(&self, at: FloorId) -> BuildingEvent {
+        let dir = Direction::between(at, self.destination);
+        if self.reduced_mobility {
+            BuildingEvent::AccessibleCallButtonPressed(at, dir)
+        } else {
+            BuildingEvent::CallButtonPressed(at, dir, self.entrance)
         }
     }
 }
@@ -29,12 +346,129 @@ impl Passenger {
 /// FloorId identifies a floor. These are zero-based integers.
 pub type FloorId = usize;
 
+/// PassengerId identifies a passenger. Passengers are numbered from 0 in the order they
+/// arrive.
+pub type PassengerId = usize;
+
+/// BuildingTask runs the building until the driver halts it or it fails. Await it for the
+/// summary of the simulation. Dropping it stops the building, so that nothing keeps running
+/// once nobody waits for the result.
+pub struct BuildingTask {
+    handle: task::JoinHandle<Result<SimulationSummary, Error>>,
+    /// Dropping this tells the building to stop.
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl Future for BuildingTask {
+    type Output = Result<Result<SimulationSummary, Error>, task::JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.handle).poll(cx)
+    }
+}
+
+/// SimulationSummary sums up how a finished simulation went.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SimulationSummary {
+    /// Passengers delivered to their floor.
+    pub delivered: usize,
+    /// Mean time from a passenger arriving to being delivered, in milliseconds of simulated
+    /// time.
+    pub mean_journey_ms: f64,
+    /// 95th percentile of the time from arriving to being delivered, in milliseconds of
+    /// simulated time.
+    pub p95_journey_ms: i64,
+    /// Mean time from a passenger arriving to boarding their first elevator, in milliseconds
+    /// of simulated time.
+    pub mean_wait_ms: f64,
+    /// 95th percentile of the time from arriving to boarding the first elevator, in
+    /// milliseconds of simulated time.
+    pub p95_wait_ms: i64,
+    /// Floors travelled by all elevators together, which stands in for the energy used.
+    pub floors_travelled: u64,
+    /// Calls that waited longer than the call SLA, see `Building::with_call_sla`.
+    pub sla_violations: usize,
+    /// Number of times each elevator turned around. Many reversals mean that the controller
+    /// keeps changing its mind.
+    pub reversals: Vec<usize>,
+    /// Time each elevator spent in service with nowhere to go and its doors closed, in
+    /// milliseconds of simulated time.
+    pub idle_ms: Vec<u64>,
+    /// Time each elevator spent in service travelling or stopped with its doors open, in
+    /// milliseconds of simulated time.
+    pub busy_ms: Vec<u64>,
+    /// Passengers who called an elevator at each floor, counting each passenger once however
+    /// many times they called.
+    pub calls_by_floor: Vec<usize>,
+    /// Passengers each elevator picked up. A passenger changing elevators counts for both.
+    pub boarded_by_elevator: Vec<usize>,
+    /// Number of finished trips by how many stops they made. A trip starts when an empty
+    /// elevator sets off or opens its doors, and ends at the first stop that leaves it empty.
+    pub trips_by_stop_count: BTreeMap<usize, usize>,
+    /// Passengers still waiting or riding when the simulation stopped, in order of arrival.
+    pub undelivered: Vec<PassengerId>,
+    /// Events dropped before every subscriber received them, because a subscriber fell more
+    /// than `Building::with_events_capacity` events behind.
+    pub dropped_events: usize,
+    /// Simulated time the simulation ran for, in milliseconds, not counting pauses.
+    pub simulated_ms: u64,
+}
+
+impl SimulationSummary {
+    /// Passengers delivered per hour of simulated time.
+    pub fn throughput_per_hour(&self) -> f64 {
+        const MS_PER_HOUR: f64 = 3_600_000.0;
+        self.delivered as f64 / self.simulated_ms.max(1) as f64 * MS_PER_HOUR
+    }
+
+    /// Returns true if every passenger who arrived got where they were going.
+    pub fn all_delivered(&self) -> bool {
+        self.undelivered.is_empty()
+    }
+
+    /// The summary as pretty-printed JSON, with the throughput per hour as well.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.with_throughput()).expect("a summary always serializes")
+    }
+
+    /// Write the summary to the file at `path` as pretty-printed JSON, see `to_json`,
+    /// replacing the file if it exists.
+    pub fn write_json(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, &self.with_throughput())?;
+        writer.flush()
+    }
+
+    fn with_throughput(&self) -> SummaryWithThroughput<'_> {
+        SummaryWithThroughput {
+            summary: self,
+            throughput_per_hour: self.throughput_per_hour(),
+        }
+    }
+}
+
+/// The JSON form of a `SimulationSummary`.
+#[derive(Serialize)]
+struct SummaryWithThroughput<'a> {
+    #[serde(flatten)]
+    summary: &'a SimulationSummary,
+    throughput_per_hour: f64,
+}
+
 /// Floor represents the current status of a floor in the building.
 #[derive(Default, Debug)]
 struct Floor {
     passengers: Vec<Passenger>,
+    /// Number of entrances with their own call station, see `Building::with_entrances`.
+    entrances: usize,
+    /// Passengers who have called an elevator here.
+    calls: usize,
 }
 
+/// EntranceId identifies one of the entrances of a floor, each with its own call station.
+/// These are zero-based integers, and every floor has at least entrance 0.
+pub type EntranceId = usize;
+
 /// ElevatorId identifies an elevator in the building. These are zero-based integers.
 pub type ElevatorId = usize;
 
@@ -53,20 +487,428 @@ struct Elevator {
     /// will not move with the doors open, but they will close at the next
     /// tick of the simulation.
     doors_open: bool,
+    /// Floors this elevator is allowed to stop at.
+    stop_pattern: StopPattern,
+    /// True if the elevator is accessible, see `ElevatorConfig::accessible`.
+    accessible: bool,
+    /// See `ElevatorConfig::rest_floor`.
+    rest_floor: Option<FloorId>,
+    /// Ticks left before the doors close.
+    dwell_ticks_left: u32,
+    /// Times the doors have reopened at the current stop.
+    door_reopens: u32,
+    /// Floor the elevator set off from on its current trip, if it is moving.
+    trip_start: Option<FloorId>,
+    /// Ticks spent travelling since setting off.
+    travel_ticks: u32,
+    /// True if the elevator has left `position` but not reached the next floor yet.
+    between_floors: bool,
+    /// Direction the elevator last travelled in.
+    last_direction: Option<Direction>,
+    /// Number of times the elevator set off in the opposite direction to its last trip.
+    reversals: usize,
+    /// Most passengers the elevator can carry at once. `None` means no limit.
+    capacity: Option<usize>,
+    /// True if the elevator is going nonstop to the lobby, see `BuildingCommand::ExpressToLobby`.
+    express: bool,
+    /// Stops made on the current trip, if the elevator is on one. A trip starts when an empty
+    /// elevator sets off or opens its doors, and ends at the first stop that leaves it empty.
+    trip_stops: Option<usize>,
+    /// Scheduled maintenance window, in milliseconds of simulated time.
+    maintenance: Option<(u64, u64)>,
+    /// True if the elevator is out of service for maintenance.
+    out_of_service: bool,
+    /// Ticks spent in service with nowhere to go and the doors closed.
+    idle_ticks: u64,
+    /// Ticks spent in service travelling or stopped with the doors open.
+    busy_ticks: u64,
+    /// Passengers who have boarded the elevator.
+    boarded: usize,
+    /// True if the elevator is on its way to end its trip, see `BuildingCommand::AbortTrip`.
+    aborting: bool,
+    /// Direction the elevator's lantern shows, see `BuildingCommand::SetDirection`.
+    announced: Option<Direction>,
+}
+
+impl Elevator {
+    fn ticks_per_floor(&self) -> u32 {
+        if self.accessible {
+            ACCESSIBLE_TICKS_PER_FLOOR
+        } else {
+            1
+        }
+    }
+
+    /// Returns true if the elevator's maintenance window has started at `now_ms`.
+    fn maintenance_due(&self, now_ms: u64) -> bool {
+        self.maintenance
+            .is_some_and(|(start_ms, end_ms)| (start_ms..end_ms).contains(&now_ms))
+    }
+
+    /// Returns true if a passenger going to `destination` would press a new floor button
+    /// while `max` floor buttons are already lit.
+    fn too_many_destinations(&self, destination: FloorId, max: usize) -> bool {
+        let destinations: BTreeSet<FloorId> =
+            self.passengers.iter().map(|px| px.destination).collect();
+        !destinations.contains(&destination) && destinations.len() >= max
+    }
+
+    /// Returns true if the elevator has left `position` and is on its way to the next floor.
+    fn is_between_floors(&self) -> bool {
+        self.between_floors && self.destination.is_some()
+    }
+
+    /// Returns true if the elevator is on its way to a floor other than `position`, even if it
+    /// hasn't left yet.
+    fn is_moving(&self) -> bool {
+        self.destination.is_some_and(|dest| dest != self.position)
+    }
+
+    /// Returns true if the elevator has no room, counting `leaving` passengers who are
+    /// getting off but still aboard.
+    fn is_full(&self, leaving: usize) -> bool {
+        self.capacity
+            .is_some_and(|capacity| self.passengers.len() + leaving >= capacity)
+    }
+
+    fn dwell_ticks(&self) -> u32 {
+        if self.accessible {
+            ACCESSIBLE_DWELL_TICKS
+        } else {
+            1
+        }
+    }
 }
 
 /// A BuildingEvent is an event that occurs in the building.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BuildingEvent {
-    /// A passenger has pressed a floor button in the elevator.
+    /// A passenger has pressed a floor button in the elevator. Every passenger presses one
+    /// once they have boarded, and nobody else does.
     FloorButtonPressed(ElevatorId, FloorId),
-    /// A passenger on the given floor has pressed the call button.
-    CallButtonPressed(FloorId, Direction),
+    /// A passenger on the given floor has pressed the call button at the given entrance.
+    /// Elevators answer the call for the whole floor, whichever entrance it came from.
+    CallButtonPressed(FloorId, Direction, EntranceId),
+    /// A passenger with reduced mobility on the given floor has pressed the call button. Only
+    /// an accessible elevator will pick them up.
+    AccessibleCallButtonPressed(FloorId, Direction),
+    /// A passenger's call at the given floor was impossible, so they were turned away. See
+    /// `InvalidCalls::Reject` and `Building::with_forbidden_floor`.
+    CallRejected(FloorId, Direction),
     /// The elevator has arrived at the given floor. If this is the
     /// elevator's destination, then it will stop open its doors.
     AtFloor(ElevatorId, FloorId),
+    /// The elevator has stopped at the given floor and opened its doors. Passengers get on
+    /// and off right after this event.
+    DoorsOpened(ElevatorId, FloorId),
+    /// The elevator closed its doors at the floor and may leave, with the given passengers
+    /// aboard.
+    DoorsClosed(ElevatorId, FloorId, Vec<PassengerId>),
+    /// Something blocked the elevator's doors as they closed, so they opened again and stay
+    /// open for another dwell. See `Building::with_door_obstruction`.
+    DoorReopened(ElevatorId),
+    /// The elevator opened its doors at the given floor with nobody to get off and nobody
+    /// waiting, for example because whoever called it boarded another elevator. The doors
+    /// close again after a short dwell, see `Building::with_ghost_stop_dwell`. Sent right
+    /// after `DoorsOpened`.
+    GhostStop(ElevatorId, FloorId),
+    /// A passenger has boarded the elevator at the given floor. They press their floor button
+    /// right away, or after the boarding delay, see `Building::with_boarding_delay`.
+    PassengerBoarded(ElevatorId, FloorId),
     /// A passenger has been delivered to their desired floor.
     PassengerDelivered(FloorId),
+    /// A passenger left at the given floor without riding an elevator, because it was where
+    /// they were going, or as close to it as any elevator could take them. Unlike
+    /// `PassengerDelivered`, they don't count as delivered.
+    PassengerStayed(FloorId),
+    /// A passenger waiting on the given floor did not board the elevator because it does not
+    /// stop at their destination. The call button stays pressed for another elevator.
+    BoardingDeclined(ElevatorId, FloorId, Direction),
+    /// A passenger waiting on the given floor didn't make it aboard the elevator stopped there
+    /// before its doors closed, see `Building::with_missed_boarding`. They call again right
+    /// away.
+    MissedBoarding(FloorId, Direction),
+    /// A passenger aboard the elevator pressed the button for a floor the elevator does not
+    /// stop at. They stay aboard until the elevator stops at a floor where they can change
+    /// to an elevator that serves their destination.
+    UnreachableDestination(ElevatorId, FloorId),
+    /// Sent at every tick while passengers are waiting for an elevator: the time in
+    /// milliseconds that the longest-waiting of them has been waiting.
+    LongestWait(u64),
+    /// Sent every simulated minute, or as often as set with `Building::with_clock_interval`:
+    /// the simulated time since the building started, in milliseconds. Time spent paused
+    /// doesn't count.
+    Clock(u64),
+    /// The elevator's trip made too many stops, so everyone aboard was let off at the given
+    /// floor, and those going elsewhere call again from there. See
+    /// `Building::with_max_trip_stops`.
+    ForcedUnload(ElevatorId, FloorId),
+    /// The elevator is heading nonstop to the given floor, the lobby or the lowest floor it
+    /// stops at. It ignores `GoToFloor` until it gets there.
+    ExpressModeEngaged(ElevatorId, FloorId),
+    /// The elevator ended its trip at the given floor, see `BuildingCommand::AbortTrip`.
+    /// Everyone aboard got off, and those who weren't where they were going call again. Sent
+    /// right before `DoorsOpened`.
+    TripAborted(ElevatorId, FloorId),
+    /// The elevator went out of service for maintenance, after letting off its passengers.
+    OutOfService(ElevatorId),
+    /// The elevator is back in service after maintenance.
+    BackInService(ElevatorId),
+    /// A passenger didn't board the elevator because too many of its floor buttons are lit
+    /// already, see `Building::with_max_destinations`. They call another elevator.
+    TooManyDestinations(ElevatorId, FloorId),
+    /// Events have been piling up without some subscriber receiving any for the stall
+    /// timeout, see `Building::with_stall_timeout`. Gives the number of events still queued
+    /// and the number of commands waiting for the building. A full event queue with full
+    /// command queue usually means the controller and a subscriber are waiting on each other.
+    StallDetected(usize, usize),
+    /// The call at the given floor going in the given direction has waited longer than the
+    /// building's call SLA, see `Building::with_call_sla`. Gives how long it has waited, in
+    /// milliseconds. Sent once per call.
+    SlaViolated(FloorId, Direction, u64),
+    /// The given elevators, in order of id, are travelling the same way close behind each
+    /// other, so that they will arrive together, see `Building::with_bunching_detection`.
+    /// Sent once when the group forms, and again if it changes.
+    Bunching(Vec<ElevatorId>),
+    /// The simulation has been paused. Nothing moves until it is resumed.
+    Paused,
+    /// The simulation has been resumed after a pause.
+    Resumed,
+}
+
+impl BuildingEvent {
+    /// The elevator the event is about, if any.
+    pub fn elevator(&self) -> Option<ElevatorId> {
+        match *self {
+            BuildingEvent::FloorButtonPressed(el, _)
+            | BuildingEvent::AtFloor(el, _)
+            | BuildingEvent::DoorsOpened(el, _)
+            | BuildingEvent::DoorsClosed(el, ..)
+            | BuildingEvent::DoorReopened(el)
+            | BuildingEvent::GhostStop(el, _)
+            | BuildingEvent::TripAborted(el, _)
+            | BuildingEvent::PassengerBoarded(el, _)
+            | BuildingEvent::BoardingDeclined(el, ..)
+            | BuildingEvent::UnreachableDestination(el, _)
+            | BuildingEvent::ForcedUnload(el, _)
+            | BuildingEvent::ExpressModeEngaged(el, _)
+            | BuildingEvent::OutOfService(el)
+            | BuildingEvent::BackInService(el)
+            | BuildingEvent::TooManyDestinations(el, _) => Some(el),
+            BuildingEvent::CallButtonPressed(..)
+            | BuildingEvent::AccessibleCallButtonPressed(..)
+            | BuildingEvent::CallRejected(..)
+            | BuildingEvent::MissedBoarding(..)
+            | BuildingEvent::PassengerDelivered(_)
+            | BuildingEvent::PassengerStayed(_)
+            | BuildingEvent::LongestWait(_)
+            | BuildingEvent::Clock(_)
+            | BuildingEvent::Paused
+            | BuildingEvent::Resumed
+            | BuildingEvent::StallDetected(..)
+            | BuildingEvent::SlaViolated(..)
+            | BuildingEvent::Bunching(_) => None,
+        }
+    }
+
+    /// The order of events that happen in the same tick: by stage of the tick, so that causes
+    /// come before their effects, then by elevator. Events of the same stage about the same
+    /// elevator, or about none, keep the order they happened in.
+    ///
+    /// The stages are: the clock; stalls; elevators going out of service and back; doors closing or
+    /// reopening; elevators reaching floors; trips aborted; doors opening, at ghost stops too;
+    /// passengers getting off; calls from passengers left waiting or changing elevators; passengers
+    /// boarding; passengers refusing or missing an elevator, after others boarded it; floor buttons
+    /// pressed, or refused; elevators unloaded by force; the longest wait; calls breaking the SLA;
+    /// elevators bunching.
+    pub fn tick_order(&self) -> (u8, Option<ElevatorId>) {
+        let stage = match self {
+            BuildingEvent::Clock(_) | BuildingEvent::StallDetected(..) => 0,
+            BuildingEvent::OutOfService(_) | BuildingEvent::BackInService(_) => 1,
+            BuildingEvent::DoorsClosed(..) | BuildingEvent::DoorReopened(_) => 2,
+            BuildingEvent::AtFloor(..) => 3,
+            BuildingEvent::TripAborted(..)
+            | BuildingEvent::DoorsOpened(..)
+            | BuildingEvent::GhostStop(..) => 4,
+            BuildingEvent::PassengerDelivered(_) | BuildingEvent::PassengerStayed(_) => 5,
+            BuildingEvent::CallButtonPressed(..)
+            | BuildingEvent::AccessibleCallButtonPressed(..)
+            | BuildingEvent::CallRejected(..) => 6,
+            BuildingEvent::PassengerBoarded(..) => 7,
+            BuildingEvent::BoardingDeclined(..) | BuildingEvent::MissedBoarding(..) => 8,
+            BuildingEvent::FloorButtonPressed(..)
+            | BuildingEvent::UnreachableDestination(..)
+            | BuildingEvent::TooManyDestinations(..) => 9,
+            BuildingEvent::ForcedUnload(..) | BuildingEvent::ExpressModeEngaged(..) => 10,
+            BuildingEvent::LongestWait(_) => 11,
+            BuildingEvent::SlaViolated(..) => 12,
+            BuildingEvent::Bunching(_) => 13,
+            // Only sent in answer to commands, never during a tick.
+            BuildingEvent::Paused | BuildingEvent::Resumed => 14,
+        };
+        (stage, self.elevator())
+    }
+}
+
+impl fmt::Display for BuildingEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Labelled {
+            labels: None,
+            value: self,
+        }
+        .fmt(f)
+    }
+}
+
+impl fmt::Display for Labelled<'_, BuildingEvent> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.value {
+            BuildingEvent::FloorButtonPressed(el, fl) => {
+                write!(f, "Floor {} requested in elevator {}", self.floor(*fl), el)
+            }
+            BuildingEvent::CallButtonPressed(fl, dir, 0) => {
+                write!(f, "Elevator called {} at floor {}", dir, self.floor(*fl))
+            }
+            BuildingEvent::CallButtonPressed(fl, dir, entrance) => {
+                write!(
+                    f,
+                    "Elevator called {} at floor {}, entrance {}",
+                    dir,
+                    self.floor(*fl),
+                    entrance
+                )
+            }
+            BuildingEvent::AccessibleCallButtonPressed(fl, dir) => {
+                write!(
+                    f,
+                    "Accessible elevator called {} at floor {}",
+                    dir,
+                    self.floor(*fl)
+                )
+            }
+            BuildingEvent::CallRejected(fl, dir) => {
+                write!(
+                    f,
+                    "Impossible call {} at floor {} rejected",
+                    dir,
+                    self.floor(*fl)
+                )
+            }
+            BuildingEvent::AtFloor(el, fl) => {
+                write!(f, "Elevator {} arrived at floor {}", el, self.floor(*fl))
+            }
+            BuildingEvent::DoorsOpened(el, fl) => {
+                write!(
+                    f,
+                    "Elevator {} opened its doors at floor {}",
+                    el,
+                    self.floor(*fl)
+                )
+            }
+            BuildingEvent::DoorsClosed(el, fl, manifest) => write!(
+                f,
+                "Elevator {} closed its doors at floor {} with passengers {:?} aboard",
+                el,
+                self.floor(*fl),
+                manifest
+            ),
+            BuildingEvent::DoorReopened(el) => write!(f, "Elevator {} reopened its doors", el),
+            BuildingEvent::GhostStop(el, fl) => write!(
+                f,
+                "Elevator {} stopped at floor {} with nobody to get on or off",
+                el,
+                self.floor(*fl)
+            ),
+            BuildingEvent::PassengerBoarded(el, fl) => {
+                write!(
+                    f,
+                    "Passenger boarded elevator {} at floor {}",
+                    el,
+                    self.floor(*fl)
+                )
+            }
+            BuildingEvent::PassengerDelivered(fl) => {
+                write!(f, "Passenger delivered to floor {}", self.floor(*fl))
+            }
+            BuildingEvent::PassengerStayed(fl) => {
+                write!(f, "Passenger stayed at floor {}", self.floor(*fl))
+            }
+            BuildingEvent::BoardingDeclined(el, fl, dir) => write!(
+                f,
+                "Passenger going {} at floor {} declined elevator {}",
+                dir,
+                self.floor(*fl),
+                el
+            ),
+            BuildingEvent::MissedBoarding(fl, dir) => write!(
+                f,
+                "Passenger going {} at floor {} missed the elevator",
+                dir,
+                self.floor(*fl)
+            ),
+            BuildingEvent::UnreachableDestination(el, fl) => {
+                write!(
+                    f,
+                    "Floor {} requested in elevator {}, which doesn't stop there",
+                    self.floor(*fl),
+                    el
+                )
+            }
+            BuildingEvent::LongestWait(ms) => {
+                write!(f, "Longest-waiting passenger has waited {}ms", ms)
+            }
+            BuildingEvent::Clock(ms) => write!(f, "{}ms of simulated time passed", ms),
+            BuildingEvent::Paused => write!(f, "Simulation paused"),
+            BuildingEvent::Resumed => write!(f, "Simulation resumed"),
+            BuildingEvent::OutOfService(el) => write!(f, "Elevator {} out of service", el),
+            BuildingEvent::BackInService(el) => write!(f, "Elevator {} back in service", el),
+            BuildingEvent::TooManyDestinations(el, fl) => {
+                write!(
+                    f,
+                    "Floor {} refused in elevator {}, too many floors requested",
+                    self.floor(*fl),
+                    el
+                )
+            }
+            BuildingEvent::StallDetected(events, commands) => write!(
+                f,
+                "Stall detected with {} events and {} commands queued",
+                events, commands
+            ),
+            BuildingEvent::SlaViolated(fl, dir, ms) => write!(
+                f,
+                "Call {} at floor {} unanswered for {}ms, over the SLA",
+                dir,
+                self.floor(*fl),
+                ms
+            ),
+            BuildingEvent::Bunching(elevators) => {
+                write!(f, "Elevators {:?} are bunched together", elevators)
+            }
+            BuildingEvent::TripAborted(el, fl) => write!(
+                f,
+                "Elevator {} aborted its trip at floor {}",
+                el,
+                self.floor(*fl)
+            ),
+            BuildingEvent::ForcedUnload(el, fl) => {
+                write!(
+                    f,
+                    "Everyone aboard elevator {} was let off at floor {}",
+                    el,
+                    self.floor(*fl)
+                )
+            }
+            BuildingEvent::ExpressModeEngaged(el, fl) => {
+                write!(
+                    f,
+                    "Elevator {} going nonstop to floor {}",
+                    el,
+                    self.floor(*fl)
+                )
+            }
+        }
+    }
 }
 
 /// A BuildingCommand tells the building what to do.
@@ -75,6 +917,102 @@ pub enum BuildingCommand {
     /// Set the elevator's destination. The elevator will close its doors
     /// if necessary and then begin moving toward this floor.
     GoToFloor(ElevatorId, FloorId),
+    /// Freeze all elevators and timers until `Resume`. Passengers' waiting times don't
+    /// include the time spent paused.
+    Pause,
+    /// Continue a paused simulation from where it left off.
+    Resume,
+    /// Send the elevator nonstop to the lobby, for example for security. Passengers aboard
+    /// stay on and are taken to their floors afterwards.
+    ExpressToLobby(ElevatorId),
+    /// End the elevator's trip at the next floor it can stop at, for example to test how the
+    /// controller recovers. Everyone aboard gets off there, and those who aren't where they
+    /// are going call again. The elevator ignores `GoToFloor` until it has stopped, and then
+    /// has nowhere to go.
+    AbortTrip(ElevatorId),
+    /// Take the elevator out of service from the first to the second time, in milliseconds
+    /// of simulated time since the start. Once the window starts, nobody boards and the
+    /// elevator goes out of service as soon as its passengers are off. Replaces any window
+    /// scheduled before.
+    ScheduleMaintenance(ElevatorId, u64, u64),
+    /// Light the elevator's lantern for the direction it leaves its next stop in. Only
+    /// passengers going that way board there, the others keep waiting for their call to be
+    /// answered. With no direction, everyone boards.
+    SetDirection(ElevatorId, Option<Direction>),
+    /// Reply with the current state of the building, for example to catch up after missing
+    /// events.
+    Snapshot(oneshot::Sender<BuildingSnapshot>),
+    /// Reply with the summary of the simulation so far, as it would be if it stopped now.
+    Summary(oneshot::Sender<SimulationSummary>),
+}
+
+impl fmt::Display for BuildingCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Labelled {
+            labels: None,
+            value: self,
+        }
+        .fmt(f)
+    }
+}
+
+impl fmt::Display for Labelled<'_, BuildingCommand> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.value {
+            BuildingCommand::GoToFloor(el, fl) => {
+                write!(f, "Elevator {} sent to floor {}", el, self.floor(*fl))
+            }
+            BuildingCommand::Pause => write!(f, "Pause the simulation"),
+            BuildingCommand::Resume => write!(f, "Resume the simulation"),
+            BuildingCommand::ExpressToLobby(el) => {
+                write!(f, "Elevator {} sent nonstop to the lobby", el)
+            }
+            BuildingCommand::AbortTrip(el) => write!(f, "Elevator {} told to abort its trip", el),
+            BuildingCommand::ScheduleMaintenance(el, start_ms, end_ms) => write!(
+                f,
+                "Elevator {} scheduled for maintenance from {}ms to {}ms",
+                el, start_ms, end_ms
+            ),
+            BuildingCommand::SetDirection(el, Some(dir)) => {
+                write!(f, "Elevator {} going {}", el, dir)
+            }
+            BuildingCommand::SetDirection(el, None) => {
+                write!(f, "Elevator {} going either way", el)
+            }
+            BuildingCommand::Snapshot(_) => write!(f, "Take a snapshot of the building"),
+            BuildingCommand::Summary(_) => write!(f, "Sum up the simulation so far"),
+        }
+    }
+}
+
+/// BuildingSnapshot is the state of the building at one moment, see
+/// `BuildingCommand::Snapshot`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildingSnapshot {
+    pub elevators: Vec<ElevatorSnapshot>,
+    /// Calls of the passengers waiting on each floor, with true for passengers waiting for
+    /// an accessible elevator.
+    pub calls: HashSet<(FloorId, Direction, bool)>,
+    /// Passengers who have called an elevator at each floor so far, see
+    /// `SimulationSummary::calls_by_floor`.
+    pub calls_by_floor: Vec<usize>,
+}
+
+/// ElevatorSnapshot is the state of an elevator at one moment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ElevatorSnapshot {
+    pub position: FloorId,
+    pub destination: Option<FloorId>,
+    /// Where each passenger aboard is riding to.
+    pub passenger_destinations: Vec<FloorId>,
+    /// True if the elevator is going nonstop to `destination`, see
+    /// `BuildingCommand::ExpressToLobby`.
+    pub express: bool,
+    pub out_of_service: bool,
+    /// Passengers who have boarded the elevator so far.
+    pub boarded: usize,
+    /// See `BuildingCommand::SetDirection`.
+    pub announced: Option<Direction>,
 }
 
 /// A DriverCommand is a message from the driver to change the state of
@@ -83,8 +1021,24 @@ pub enum BuildingCommand {
 pub enum DriverCommand {
     /// A passenger has arrived and is waiting for an elevator. The passenger will automatically
     /// press the relevant call button, board the elevator when it arrives, press their floor
-    /// button, and depart when the doors open on their destination floor.
-    PassengerArrived { at: FloorId, destination: FloorId },
+    /// button, and depart when the doors open on their destination floor. Passengers with
+    /// reduced mobility wait for an accessible elevator.
+    PassengerArrived {
+        at: FloorId,
+        destination: FloorId,
+        reduced_mobility: bool,
+        /// Entrance of the floor the passenger arrives at, 0 if it has only one.
+        entrance: EntranceId,
+    },
+
+    /// Like `PassengerArrived`, but the passenger only knows which way they are going. They
+    /// pick a random floor in that direction once they board, and press its button then.
+    PassengerArrivedUndecided {
+        at: FloorId,
+        direction: Direction,
+        reduced_mobility: bool,
+        entrance: EntranceId,
+    },
 
     /// Halt all activity in the building and end the building task.
     Halt,
@@ -96,13 +1050,94 @@ pub struct Building {
     floors: Vec<Floor>,
     elevators: Vec<Elevator>,
     elapsed_times_per_passenger: Vec<i64>,
+    /// Time each passenger waited to board their first elevator, in milliseconds of
+    /// simulated time.
+    wait_times_per_passenger: Vec<i64>,
+    /// When the simulation was paused, if it is paused.
+    paused_at: Option<time::Instant>,
+    /// Most stops a trip may make before everyone aboard is let off, see
+    /// `Building::with_max_trip_stops`.
+    max_trip_stops: Option<usize>,
+    /// Most floor buttons that may be lit in an elevator at once, see
+    /// `Building::with_max_destinations`.
+    max_destinations: Option<usize>,
+    invalid_calls: InvalidCalls,
+    boarding_order: BoardingOrder,
+    /// See `Building::with_forbidden_floor`.
+    forbidden_floors: BTreeSet<FloorId>,
+    /// Ticks the simulation has run for, not counting pauses.
+    ticks: u64,
+    /// Number of finished trips by how many stops they made.
+    trips_by_stop_count: BTreeMap<usize, usize>,
+    motion_profile: MotionProfile,
+    /// Ticks after setting off that an elevator reaches each floor of a trip, by the length of
+    /// the trip, see `MotionProfile::trip_ticks`. Each length is only worked out once, as an
+    /// `SCurve` is integrated step by step.
+    trip_ticks: HashMap<usize, Vec<f64>>,
+    /// Simulated time an elevator takes to travel a floor at full speed going up and going
+    /// down, in milliseconds. See `Building::with_floor_times`.
+    up_floor_ms: u64,
+    down_floor_ms: u64,
+    /// How many times faster than real time the simulation runs, see
+    /// `Building::with_time_scale`.
+    time_scale: f64,
+    /// Ticks a passenger takes to press their floor button after boarding.
+    boarding_delay_ticks: u64,
+    /// Floors travelled by all elevators together.
+    floors_travelled: u64,
+    /// Id of the next passenger to arrive.
+    next_passenger_id: PassengerId,
+    /// See `Building::with_seed`.
+    rng: Option<StdRng>,
+    /// See `Building::with_call_sla`.
+    call_sla: Option<time::Duration>,
+    /// Probability that the doors reopen as they close, and most times they reopen per stop.
+    /// See `Building::with_door_obstruction`.
+    door_obstruction: Option<(f64, u32)>,
+    /// See `Building::with_ghost_stop_dwell`.
+    ghost_stop_dwell: Option<u32>,
+    /// See `Building::with_clock_interval`.
+    clock_interval_ms: Option<u64>,
+    /// Probability that a waiting passenger misses an elevator, see
+    /// `Building::with_missed_boarding`.
+    missed_boarding: Option<f64>,
+    /// Calls still waiting that have been reported for breaking the SLA.
+    sla_violated_calls: HashSet<(FloorId, Direction)>,
+    sla_violations: usize,
+    /// Most floors between elevators, and fewest elevators, that count as bunched. See
+    /// `Building::with_bunching_detection`.
+    bunching: Option<(usize, usize)>,
+    /// Groups of bunched elevators found at the last tick.
+    bunches: Vec<Vec<ElevatorId>>,
+    /// See `Building::with_stall_timeout`.
+    stall_timeout: Option<time::Duration>,
+    /// See `Building::with_events_capacity`.
+    events_capacity: usize,
+    /// Subscribers the building waits for when they fall behind, see `Building::subscribe`.
+    blocking_subscribers: Vec<mpsc::Sender<BuildingEvent>>,
+    /// Subscribers that miss events when they fall behind, see `Building::subscribe`.
+    dropping_subscribers: Vec<broadcast::Sender<BuildingEvent>>,
+    /// Events sent since the blocking subscribers were last given events.
+    unsent_events: Vec<BuildingEvent>,
+    /// Events dropped before every subscriber received them.
+    dropped_events: usize,
+    /// Events queued for subscribers at the last tick.
+    queued_events: usize,
+    /// Last tick at which the event queue was empty or shrank.
+    progress_tick: u64,
+    /// True if the current stall has been reported already.
+    stall_reported: bool,
 }
 
 impl Building {
     pub fn new(num_floors: usize, num_elevators: usize) -> Self {
+        assert!(num_floors > 0, "a building needs at least one floor");
         let mut floors = vec![];
         for _ in 0..num_floors {
-            floors.push(Floor::default());
+            floors.push(Floor {
+                entrances: 1,
+                ..Default::default()
+            });
         }
         let mut elevators = vec![];
         for _ in 0..num_elevators {
@@ -111,127 +1146,1065 @@ impl Building {
         Self {
             floors,
             elevators,
+            time_scale: 1.0,
+            up_floor_ms: TICK_MS,
+            down_floor_ms: TICK_MS,
+            events_capacity: EVENTS_CAPACITY,
             ..Default::default()
         }
     }
 
+    /// Make the given elevator accessible, see `ElevatorConfig::accessible`.
+    pub fn with_accessible_elevator(mut self, el: ElevatorId) -> Self {
+        self.elevators[el].accessible = true;
+        self
+    }
+
+    /// Give the floor `entrances` entrances, each with its own call station, for example for
+    /// a building with several lobbies.
+    pub fn with_entrances(mut self, fl: FloorId, entrances: usize) -> Self {
+        self.floors[fl].entrances = entrances.max(1);
+        self
+    }
+
+    /// Limit every elevator to carrying at most `capacity` passengers at once.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        for elevator in &mut self.elevators {
+            elevator.capacity = Some(capacity);
+        }
+        self
+    }
+
+    /// Decide what to do with passengers whose call is impossible.
+    pub fn with_invalid_calls(mut self, invalid_calls: InvalidCalls) -> Self {
+        self.invalid_calls = invalid_calls;
+        self
+    }
+
+    /// Never stop any elevator at `fl`, for example a mechanical floor. Calls from or to the
+    /// floor are rejected with `BuildingEvent::CallRejected`, and elevators sent there don't
+    /// go.
+    pub fn with_forbidden_floor(mut self, fl: FloorId) -> Self {
+        self.forbidden_floors.insert(fl);
+        self
+    }
+
+    /// Decide whether passengers get off or on first at each stop.
+    pub fn with_boarding_order(mut self, boarding_order: BoardingOrder) -> Self {
+        self.boarding_order = boarding_order;
+        self
+    }
+
+    /// Let everyone off an elevator at the stop where its trip exceeds `max_stops` stops. This
+    /// is a safety net against trips that never end, not something that should happen.
+    pub fn with_max_trip_stops(mut self, max_stops: usize) -> Self {
+        self.max_trip_stops = Some(max_stops);
+        self
+    }
+
+    /// Light at most `max_destinations` floor buttons in an elevator at once, so that pressing
+    /// every button doesn't make it stop everywhere. A passenger going to another floor waits
+    /// for the next elevator.
+    pub fn with_max_destinations(mut self, max_destinations: usize) -> Self {
+        self.max_destinations = Some(max_destinations);
+        self
+    }
+
+    /// Run the simulation `time_scale` times faster than real time, for example `2.0` to run
+    /// twice as fast or `0.5` to run at half speed. Waiting times are still reported in
+    /// simulated time.
+    pub fn with_time_scale(mut self, time_scale: f64) -> Self {
+        assert!(time_scale > 0.0, "time scale must be positive");
+        self.time_scale = time_scale;
+        self
+    }
+
+    /// Keep at least `capacity` events for subscribers that fall behind; the queue rounds it
+    /// up to a power of two. Once the queue is full of events waiting for the slowest
+    /// subscriber, each new event drops the oldest, and the summary counts the events dropped.
+    pub fn with_events_capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "events capacity must be positive");
+        self.events_capacity = capacity;
+        self
+    }
+
+    /// Subscribe to every event from the start, with room for `capacity` events the
+    /// subscriber hasn't received, and `backpressure` deciding what happens once it is full:
+    /// the subscriber misses the oldest events, or the building waits for it before going on.
+    /// Unlike the subscribers of the channel returned by `Building::start`, a blocking
+    /// subscriber never misses events, but a slow one slows the whole simulation down. Either
+    /// way the subscriber has a queue of its own, so the events it misses don't count in
+    /// `SimulationSummary::dropped_events`.
+    pub fn subscribe(&mut self, backpressure: Backpressure, capacity: usize) -> EventStream {
+        match backpressure {
+            Backpressure::Drop => {
+                let (events_tx, events_rx) = broadcast::channel(capacity);
+                self.dropping_subscribers.push(events_tx);
+                events_rx.into()
+            }
+            Backpressure::Block => {
+                let (events_tx, events_rx) = mpsc::channel(capacity);
+                self.blocking_subscribers.push(events_tx);
+                events_rx.into()
+            }
+        }
+    }
+
+    /// Watch for subscribers that stop receiving events, and send `StallDetected` once events
+    /// have piled up for `timeout` of simulated time.
+    pub fn with_stall_timeout(mut self, timeout: time::Duration) -> Self {
+        self.stall_timeout = Some(timeout);
+        self
+    }
+
+    /// Have passengers take `delay` of simulated time to step in and press their floor button
+    /// after boarding, rounded up to whole ticks. The elevator doesn't know where they are
+    /// going until then.
+    pub fn with_boarding_delay(mut self, delay: time::Duration) -> Self {
+        self.boarding_delay_ticks = (delay.as_millis() as u64).div_ceil(TICK_MS);
+        self
+    }
+
+    /// Expect every call to be answered within `sla` of simulated time, and send
+    /// `SlaViolated` for calls that wait longer.
+    pub fn with_call_sla(mut self, sla: time::Duration) -> Self {
+        self.call_sla = Some(sla);
+        self
+    }
+
+    /// Send `Bunching` when at least `min_elevators` elevators travel the same way with at
+    /// most `max_gap` floors between each and the next. Bunched elevators arrive together, one
+    /// right after the other, and waste their capacity, a common problem under heavy up-peak
+    /// traffic.
+    pub fn with_bunching_detection(mut self, max_gap: usize, min_elevators: usize) -> Self {
+        assert!(
+            min_elevators >= 2,
+            "it takes at least two elevators to bunch"
+        );
+        self.bunching = Some((max_gap, min_elevators));
+        self
+    }
+
+    /// Have something block the doors as they close with the given `probability`, so that
+    /// they open again for another dwell, at most `max_reopens` times per stop. Each reopening
+    /// sends `DoorReopened`.
+    pub fn with_door_obstruction(mut self, probability: f64, max_reopens: u32) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&probability),
+            "door obstruction probability must be between 0 and 1"
+        );
+        self.door_obstruction = Some((probability, max_reopens));
+        self
+    }
+
+    /// Start with a passenger aboard elevator `el` riding to `destination`, having pressed
+    /// their floor button already, for example to simulate the middle of a day. The
+    /// controller learns of them from the snapshot it takes as it starts.
+    pub fn with_passenger_aboard(mut self, el: ElevatorId, destination: FloorId) -> Self {
+        assert!(
+            destination < self.floors.len(),
+            "destination {} is above the top floor",
+            destination
+        );
+        let px = Passenger::new(self.next_passenger_id, destination, false, 0);
+        self.next_passenger_id += 1;
+        let elevator = &mut self.elevators[el];
+        elevator.passengers.push(px);
+        elevator.trip_stops.get_or_insert(0);
+        self
+    }
+
+    /// Start with a passenger waiting at floor `at` to go to `destination`, their call
+    /// pending. Like passengers aboard, see `Building::with_passenger_aboard`, the controller
+    /// learns of the call from its first snapshot, and no `CallButtonPressed` is sent for it.
+    pub fn with_waiting_passenger(mut self, at: FloorId, destination: FloorId) -> Self {
+        assert!(
+            at < self.floors.len() && destination < self.floors.len(),
+            "call at floor {} to floor {} is above the top floor",
+            at,
+            destination
+        );
+        assert_ne!(at, destination, "passenger is already at their destination");
+        let px = Passenger::new(self.next_passenger_id, destination, false, 0);
+        self.next_passenger_id += 1;
+        self.floors[at].calls += 1;
+        self.floors[at].passengers.push(px);
+        self
+    }
+
+    /// Have each waiting passenger miss an elevator that stops for them with the given
+    /// `probability`, as if the doors closed before they made it. They send `MissedBoarding`
+    /// and call again for the next elevator.
+    pub fn with_missed_boarding(mut self, probability: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&probability),
+            "missed boarding probability must be between 0 and 1"
+        );
+        self.missed_boarding = Some(probability);
+        self
+    }
+
+    /// Keep the doors open for `ticks` ticks at ghost stops, where nobody gets on or off, see
+    /// `BuildingEvent::GhostStop`. By default they close after a single tick.
+    pub fn with_ghost_stop_dwell(mut self, ticks: u32) -> Self {
+        assert!(ticks > 0, "ghost stop dwell must be at least a tick");
+        self.ghost_stop_dwell = Some(ticks);
+        self
+    }
+
+    /// Tell the simulated time every `interval_ms` milliseconds, see `BuildingEvent::Clock`,
+    /// rather than every minute. The interval must be a whole number of ticks.
+    pub fn with_clock_interval(mut self, interval_ms: u64) -> Self {
+        assert!(
+            interval_ms > 0 && interval_ms.is_multiple_of(TICK_MS),
+            "clock interval must be a whole number of ticks"
+        );
+        self.clock_interval_ms = Some(interval_ms);
+        self
+    }
+
+    /// Draw the building's random choices, such as the floors undecided passengers pick, from
+    /// `seed`, so that a run can be reproduced. Without a seed the choices differ every run.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Some(StdRng::seed_from_u64(seed));
+        self
+    }
+
+    /// Move every elevator according to the given motion profile.
+    pub fn with_motion_profile(mut self, motion_profile: MotionProfile) -> Self {
+        self.motion_profile = motion_profile;
+        self
+    }
+
+    /// Have elevators take `up_floor_ms` milliseconds of simulated time to travel a floor at
+    /// full speed going up, and `down_floor_ms` going down, for example because of the
+    /// counterweight. Both are a tick, 100ms, unless given, and can't be any shorter. The
+    /// motion profile and accessible elevators stretch them the same way. Arrivals still
+    /// happen on ticks, so times that aren't a whole number of ticks are rounded up.
+    pub fn with_floor_times(mut self, up_floor_ms: u64, down_floor_ms: u64) -> Self {
+        assert!(
+            up_floor_ms >= TICK_MS && down_floor_ms >= TICK_MS,
+            "floor times must be at least {}ms",
+            TICK_MS
+        );
+        self.up_floor_ms = up_floor_ms;
+        self.down_floor_ms = down_floor_ms;
+        self
+    }
+
+    /// Restrict the given elevator to the floors served by the stop pattern.
+    pub fn with_stop_pattern(mut self, el: ElevatorId, stop_pattern: StopPattern) -> Self {
+        self.elevators[el].stop_pattern = stop_pattern;
+        self
+    }
+
+    /// Have the given elevator return to `fl` when idle, see `ElevatorConfig::rest_floor`.
+    pub fn with_rest_floor(mut self, el: ElevatorId, fl: FloorId) -> Self {
+        assert!(
+            fl < self.floors.len(),
+            "rest floor {} is not in the building",
+            fl
+        );
+        self.elevators[el].rest_floor = Some(fl);
+        self
+    }
+
     /// Start the building. The resulting channels are used to communicate
     /// with the building
     pub fn start(
         self,
     ) -> (
-        task::JoinHandle<()>,
+        BuildingTask,
         broadcast::Receiver<BuildingEvent>,
         mpsc::Sender<BuildingCommand>,
         mpsc::Sender<DriverCommand>,
     ) {
-        let (events_tx, events_rx) = broadcast::channel(100);
+        let (events_tx, events_rx) = broadcast::channel(self.events_capacity);
         let (building_cmd_tx, building_cmd_rx) = mpsc::channel(100);
         let (driver_cmd_tx, driver_cmd_rx) = mpsc::channel(100);
-        let task = tokio::spawn(self.run(events_tx, building_cmd_rx, driver_cmd_rx));
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let handle = tokio::spawn(self.run(
+            events_tx,
+            building_cmd_tx.downgrade(),
+            building_cmd_rx,
+            driver_cmd_rx,
+            shutdown_rx,
+        ));
+        let task = BuildingTask {
+            handle,
+            _shutdown: shutdown_tx,
+        };
         (task, events_rx, building_cmd_tx, driver_cmd_tx)
     }
 
     async fn run(
         mut self,
         events_tx: broadcast::Sender<BuildingEvent>,
+        building_cmd_tx: mpsc::WeakSender<BuildingCommand>,
         mut building_cmd_rx: mpsc::Receiver<BuildingCommand>,
         mut driver_cmd_rx: mpsc::Receiver<DriverCommand>,
-    ) {
-        let mut ticker = time::interval(time::Duration::from_millis(100));
+        mut shutdown_rx: oneshot::Receiver<()>,
+    ) -> Result<SimulationSummary, Error> {
+        // Passengers the building started with have been waiting since the start.
+        let floor_passengers = self.floors.iter_mut().flat_map(|fl| &mut fl.passengers);
+        let elevator_passengers = self.elevators.iter_mut().flat_map(|el| &mut el.passengers);
+        let now = time::Instant::now();
+        for px in floor_passengers.chain(elevator_passengers) {
+            px.arrived_at = now;
+        }
+        let mut ticker =
+            time::interval(time::Duration::from_millis(TICK_MS).div_f64(self.time_scale));
+        // Ticks missed while the building was busy are late, not lost, so elevators never
+        // jump ahead to catch up.
+        ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
         loop {
             tokio::select! {
-                Some(BuildingCommand::GoToFloor(el, fl)) = building_cmd_rx.recv() => {
-                    self.elevators[el].destination = Some(fl);
+                // The building task was dropped, so nobody wants the summary.
+                _ = &mut shutdown_rx => return Ok(self.summary()),
+                Some(cmd) = building_cmd_rx.recv() => {
+                    match cmd {
+                        BuildingCommand::GoToFloor(el, fl) => {
+                            // Elevators never stop at floors outside of their stop pattern, and
+                            // express elevators only stop at the lobby.
+                            let elevator = &mut self.elevators[el];
+                            if self.forbidden_floors.contains(&fl) {
+                                warn!(elevator = el, floor = fl, "elevator sent to a forbidden floor");
+                            } else if elevator.stop_pattern.serves(fl)
+                                && !elevator.express
+                                && !elevator.aborting
+                                && !elevator.out_of_service
+                            {
+                                // An elevator that is on its way elsewhere can't stop at the
+                                // floor it is leaving, so it keeps going.
+                                if fl == elevator.position && elevator.is_moving() {
+                                    warn!(
+                                        elevator = el,
+                                        floor = fl,
+                                        "elevator sent to a floor while moving past it"
+                                    );
+                                } else {
+                                    elevator.destination = Some(fl);
+                                }
+                            }
+                        }
+                        BuildingCommand::Pause => self.pause(&events_tx)?,
+                        BuildingCommand::Resume => {
+                            self.resume(&events_tx)?;
+                            // The ticks missed while paused never happened.
+                            ticker.reset();
+                        }
+                        BuildingCommand::ExpressToLobby(el) => self.express_to_lobby(&events_tx, el)?,
+                        BuildingCommand::AbortTrip(el) => self.abort_trip(el),
+                        BuildingCommand::ScheduleMaintenance(el, start_ms, end_ms) => {
+                            self.elevators[el].maintenance = Some((start_ms, end_ms));
+                        }
+                        BuildingCommand::SetDirection(el, direction) => {
+                            self.elevators[el].announced = direction;
+                        }
+                        BuildingCommand::Snapshot(reply) => {
+                            // Nobody to tell if the asker has gone away.
+                            let _ = reply.send(self.snapshot());
+                        }
+                        BuildingCommand::Summary(reply) => {
+                            let _ = reply.send(self.summary());
+                        }
+                    }
                 }
                 Some(cmd) = driver_cmd_rx.recv() => {
                     match cmd {
-                        DriverCommand::PassengerArrived{at, destination} => {
-                            self.new_passenger(&events_tx, at, destination).await;
+                        DriverCommand::PassengerArrived{at, destination, reduced_mobility, entrance} => {
+                            self.new_passenger(&events_tx, at, destination, reduced_mobility, entrance, false).await?;
+                        }
+                        DriverCommand::PassengerArrivedUndecided{at, direction, reduced_mobility, entrance} => {
+                            let destination = match direction {
+                                Direction::Up => self.floors.len() - 1,
+                                Direction::Down => 0,
+                            };
+                            self.new_passenger(&events_tx, at, destination, reduced_mobility, entrance, true).await?;
                         }
                         DriverCommand::Halt => break,
                     }
                 }
-                _ = ticker.tick() => self.move_elevators(&events_tx).await
+                _ = ticker.tick(), if self.paused_at.is_none() => {
+                    self.ticks += 1;
+                    // Events of the same tick happen at the same time, so they are sent in
+                    // a fixed order rather than the order they were noticed in.
+                    let mut events = Vec::new();
+                    self.tell_time(&mut events);
+                    self.watch_for_stall(&events_tx, &mut events, &building_cmd_tx);
+                    self.press_floor_buttons(&mut events);
+                    self.service_elevators(&mut events);
+                    self.move_elevators(&mut events);
+                    self.report_longest_wait(&mut events);
+                    self.check_call_sla(&mut events);
+                    self.detect_bunching(&mut events);
+                    events.sort_by_key(BuildingEvent::tick_order);
+                    for evt in events {
+                        self.send_event(&events_tx, evt)?;
+                    }
+                }
             }
+            self.send_blocking().await;
         }
-        let average: i64 = self.elapsed_times_per_passenger.iter().sum::<i64>()
-            / self.elapsed_times_per_passenger.len() as i64;
+        self.send_blocking().await;
+        // Nobody may have been delivered, for example if nobody came.
+        let delivered = self.elapsed_times_per_passenger.len().max(1) as i64;
+        let average: i64 = self.elapsed_times_per_passenger.iter().sum::<i64>() / delivered;
         let variance: i64 = self
             .elapsed_times_per_passenger
             .iter()
             .map(|&x| (x - average).pow(2))
             .sum::<i64>()
-            / self.elapsed_times_per_passenger.len() as i64;
-        println!("DISTRIBUTION {} +- {}", average, (variance as f64).sqrt());
+            / delivered;
+        info!(
+            mean_ms = average,
+            std_dev_ms = (variance as f64).sqrt(),
+            "journey time distribution"
+        );
+        for (stops, trips) in &self.trips_by_stop_count {
+            info!(stops, trips, "trips by stop count");
+        }
+        for (el, elevator) in self.elevators.iter().enumerate() {
+            info!(
+                elevator = el,
+                idle_ms = elevator.idle_ticks * TICK_MS,
+                busy_ms = elevator.busy_ticks * TICK_MS,
+                reversals = elevator.reversals,
+                "elevator utilization"
+            );
+        }
+        if self.call_sla.is_some() {
+            info!(violations = self.sla_violations, "call SLA");
+        }
+        info!(dropped = self.dropped_events, "dropped events");
+        let summary = self.summary();
+        info!(
+            passengers_per_hour = summary.throughput_per_hour().round(),
+            simulated_ms = summary.simulated_ms,
+            "throughput"
+        );
+        Ok(summary)
     }
 
-    /// Move the elevators toward their destinations.
-    async fn move_elevators(&mut self, events_tx: &broadcast::Sender<BuildingEvent>) {
-        for el in 0..self.elevators.len() {
-            let elevator = &mut self.elevators[el];
+    fn summary(&self) -> SimulationSummary {
+        let delivered = self.elapsed_times_per_passenger.len();
+        let (mean_journey_ms, p95_journey_ms) = mean_and_p95(&self.elapsed_times_per_passenger);
+        let (mean_wait_ms, p95_wait_ms) = mean_and_p95(&self.wait_times_per_passenger);
+        let waiting = self.floors.iter().flat_map(|floor| &floor.passengers);
+        let riding = self
+            .elevators
+            .iter()
+            .flat_map(|elevator| &elevator.passengers);
+        let mut undelivered: Vec<PassengerId> = waiting.chain(riding).map(|px| px.id).collect();
+        undelivered.sort_unstable();
+        SimulationSummary {
+            delivered,
+            mean_journey_ms,
+            p95_journey_ms,
+            mean_wait_ms,
+            p95_wait_ms,
+            floors_travelled: self.floors_travelled,
+            sla_violations: self.sla_violations,
+            reversals: self
+                .elevators
+                .iter()
+                .map(|elevator| elevator.reversals)
+                .collect(),
+            idle_ms: self
+                .elevators
+                .iter()
+                .map(|elevator| elevator.idle_ticks * TICK_MS)
+                .collect(),
+            busy_ms: self
+                .elevators
+                .iter()
+                .map(|elevator| elevator.busy_ticks * TICK_MS)
+                .collect(),
+            calls_by_floor: self.floors.iter().map(|floor| floor.calls).collect(),
+            boarded_by_elevator: self
+                .elevators
+                .iter()
+                .map(|elevator| elevator.boarded)
+                .collect(),
+            trips_by_stop_count: self.trips_by_stop_count.clone(),
+            undelivered,
+            dropped_events: self.dropped_events,
+            simulated_ms: self.ticks * TICK_MS,
+        }
+    }
 
-            // If the elevator's doors are open, close them and wait for the next tick.
-            if elevator.doors_open {
-                elevator.doors_open = false;
-                continue;
-            }
+    /// Send `evt` to the subscribers, counting the event it drops if the queue is full.
+    fn send_event(
+        &mut self,
+        events_tx: &broadcast::Sender<BuildingEvent>,
+        evt: BuildingEvent,
+    ) -> Result<(), Error> {
+        // A full queue holds an event that some subscriber has yet to receive, and the new
+        // event takes its place. The queue's capacity is rounded up to a power of two.
+        if events_tx.len() >= self.events_capacity.next_power_of_two() {
+            self.dropped_events += 1;
+        }
+        if !self.blocking_subscribers.is_empty() {
+            self.unsent_events.push(evt.clone());
+        }
+        // Subscribers that have gone away are forgotten.
+        self.dropping_subscribers
+            .retain(|events_tx| events_tx.send(evt.clone()).is_ok());
+        events_tx.send(evt)?;
+        Ok(())
+    }
+
+    /// Give the events sent since the last call to the blocking subscribers, waiting for room
+    /// in their queues. Subscribers that have gone away are forgotten.
+    async fn send_blocking(&mut self) {
+        for evt in std::mem::take(&mut self.unsent_events) {
+            let mut subscribers = Vec::with_capacity(self.blocking_subscribers.len());
+            for events_tx in self.blocking_subscribers.drain(..) {
+                if events_tx.send(evt.clone()).await.is_ok() {
+                    subscribers.push(events_tx);
+                }
+            }
+            self.blocking_subscribers = subscribers;
+        }
+    }
+
+    fn pause(&mut self, events_tx: &broadcast::Sender<BuildingEvent>) -> Result<(), Error> {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(time::Instant::now());
+            self.send_event(events_tx, BuildingEvent::Paused)?;
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self, events_tx: &broadcast::Sender<BuildingEvent>) -> Result<(), Error> {
+        let Some(paused_at) = self.paused_at.take() else {
+            return Ok(());
+        };
+        // Shift arrival times so that the pause doesn't count toward anyone's wait.
+        let paused_for = paused_at.elapsed();
+        let floor_passengers = self.floors.iter_mut().flat_map(|fl| &mut fl.passengers);
+        let elevator_passengers = self.elevators.iter_mut().flat_map(|el| &mut el.passengers);
+        for px in floor_passengers.chain(elevator_passengers) {
+            px.arrived_at += paused_for;
+        }
+        self.send_event(events_tx, BuildingEvent::Resumed)?;
+        Ok(())
+    }
+
+    fn snapshot(&self) -> BuildingSnapshot {
+        let elevators = self
+            .elevators
+            .iter()
+            .map(|elevator| ElevatorSnapshot {
+                position: elevator.position,
+                destination: elevator.destination,
+                passenger_destinations: elevator
+                    .passengers
+                    .iter()
+                    .map(|px| px.destination)
+                    .collect(),
+                express: elevator.express,
+                out_of_service: elevator.out_of_service,
+                boarded: elevator.boarded,
+                announced: elevator.announced,
+            })
+            .collect();
+        let calls = self
+            .floors
+            .iter()
+            .enumerate()
+            .flat_map(|(fl, floor)| {
+                floor.passengers.iter().map(move |px| {
+                    let dir = Direction::between(fl, px.destination);
+                    (fl, dir, px.reduced_mobility)
+                })
+            })
+            .collect();
+        BuildingSnapshot {
+            elevators,
+            calls,
+            calls_by_floor: self.floors.iter().map(|floor| floor.calls).collect(),
+        }
+    }
+
+    /// Report a stall if the event queue has neither emptied nor shrunk for the stall
+    /// timeout, which means that some subscriber hasn't received anything in that time.
+    fn watch_for_stall(
+        &mut self,
+        events_tx: &broadcast::Sender<BuildingEvent>,
+        events: &mut Vec<BuildingEvent>,
+        building_cmd_tx: &mpsc::WeakSender<BuildingCommand>,
+    ) {
+        let Some(timeout) = self.stall_timeout else {
+            return;
+        };
+        let queued_events = events_tx.len();
+        if queued_events == 0 || queued_events < self.queued_events {
+            self.progress_tick = self.ticks;
+            self.stall_reported = false;
+        }
+        self.queued_events = queued_events;
+        let stalled_ms = (self.ticks - self.progress_tick) * TICK_MS;
+        if !self.stall_reported && u128::from(stalled_ms) >= timeout.as_millis() {
+            self.stall_reported = true;
+            let queued_commands = building_cmd_tx
+                .upgrade()
+                .map_or(0, |tx| tx.max_capacity() - tx.capacity());
+            warn!(
+                stalled_ms,
+                queued_events, queued_commands, "no progress on events"
+            );
+            events.push(BuildingEvent::StallDetected(queued_events, queued_commands));
+        }
+    }
+
+    /// Press the floor buttons of passengers whose boarding delay is over.
+    fn press_floor_buttons(&mut self, events: &mut Vec<BuildingEvent>) {
+        for (el, elevator) in self.elevators.iter_mut().enumerate() {
+            for px in &mut elevator.passengers {
+                if px.presses_button_at.is_some_and(|at| at <= self.ticks) {
+                    px.presses_button_at = None;
+                    events.push(floor_button(el, elevator.stop_pattern, px.destination));
+                }
+            }
+        }
+    }
+
+    /// Take elevators out of service and back according to their maintenance windows.
+    fn service_elevators(&mut self, events: &mut Vec<BuildingEvent>) {
+        let now_ms = self.ticks * TICK_MS;
+        for (el, elevator) in self.elevators.iter_mut().enumerate() {
+            let Some((_, end_ms)) = elevator.maintenance else {
+                continue;
+            };
+            if now_ms >= end_ms {
+                elevator.maintenance = None;
+                if elevator.out_of_service {
+                    elevator.out_of_service = false;
+                    events.push(BuildingEvent::BackInService(el));
+                }
+            } else if elevator.maintenance_due(now_ms)
+                && !elevator.out_of_service
+                && !elevator.doors_open
+                && elevator.passengers.is_empty()
+            {
+                elevator.out_of_service = true;
+                elevator.destination = None;
+                elevator.express = false;
+                events.push(BuildingEvent::OutOfService(el));
+            }
+        }
+    }
+
+    /// Send the elevator nonstop to the lowest floor it stops at.
+    fn express_to_lobby(
+        &mut self,
+        events_tx: &broadcast::Sender<BuildingEvent>,
+        el: ElevatorId,
+    ) -> Result<(), Error> {
+        let Some(lobby) = self.nearest_stop(self.elevators[el].stop_pattern, 0) else {
+            return Ok(());
+        };
+        let elevator = &mut self.elevators[el];
+        elevator.express = true;
+        elevator.announced = None;
+        elevator.destination = Some(lobby);
+        self.send_event(events_tx, BuildingEvent::ExpressModeEngaged(el, lobby))?;
+        Ok(())
+    }
+
+    /// Stop the elevator at the next floor it can stop at, see `BuildingCommand::AbortTrip`.
+    fn abort_trip(&mut self, el: ElevatorId) {
+        let elevator = &self.elevators[el];
+        if elevator.out_of_service
+            || (elevator.destination.is_none() && elevator.passengers.is_empty())
+        {
+            return;
+        }
+        let position = elevator.position;
+        let stops_at =
+            |fl: &FloorId| elevator.stop_pattern.serves(*fl) && !self.forbidden_floors.contains(fl);
+        // An elevator that has left its floor can only stop further on.
+        let next = match elevator.destination {
+            Some(dest) if elevator.is_between_floors() && dest > position => {
+                (position + 1..=dest).find(stops_at).unwrap_or(dest)
+            }
+            Some(dest) if elevator.is_between_floors() => {
+                (dest..position).rev().find(stops_at).unwrap_or(dest)
+            }
+            _ => position,
+        };
+        let elevator = &mut self.elevators[el];
+        elevator.destination = Some(next);
+        elevator.express = false;
+        elevator.aborting = true;
+    }
+
+    /// Move the elevators toward their destinations.
+    fn move_elevators(&mut self, events: &mut Vec<BuildingEvent>) {
+        for el in 0..self.elevators.len() {
+            let elevator = &mut self.elevators[el];
+
+            // An elevator is idle from the moment it has nowhere to go until it is sent
+            // somewhere. Time out of service counts as neither idle nor busy.
+            if !elevator.out_of_service {
+                if elevator.destination.is_none() && !elevator.doors_open {
+                    elevator.idle_ticks += 1;
+                } else {
+                    elevator.busy_ticks += 1;
+                }
+            }
+
+            // If the elevator's doors are open, close them once the dwell time is over and
+            // wait for the next tick.
+            if elevator.doors_open {
+                elevator.dwell_ticks_left = elevator.dwell_ticks_left.saturating_sub(1);
+                if elevator.dwell_ticks_left == 0 && self.doors_obstructed(el) {
+                    let elevator = &mut self.elevators[el];
+                    elevator.door_reopens += 1;
+                    elevator.dwell_ticks_left = elevator.dwell_ticks();
+                    events.push(BuildingEvent::DoorReopened(el));
+                    continue;
+                }
+                let elevator = &mut self.elevators[el];
+                elevator.doors_open = elevator.dwell_ticks_left > 0;
+                if !elevator.doors_open {
+                    let manifest = elevator.passengers.iter().map(|px| px.id).collect();
+                    events.push(BuildingEvent::DoorsClosed(el, elevator.position, manifest));
+                }
+                continue;
+            }
 
             // If the elevator has somewhere to go, move toward it.
             if let Some(dest) = elevator.destination {
+                if dest != elevator.position {
+                    // Set off again if the elevator is at rest or has to turn around.
+                    let start = match elevator.trip_start {
+                        Some(start)
+                            if start == elevator.position
+                                || (start < elevator.position) == (elevator.position < dest) =>
+                        {
+                            start
+                        }
+                        _ => {
+                            elevator.travel_ticks = 0;
+                            elevator.position
+                        }
+                    };
+                    elevator.trip_start = Some(start);
+                    elevator.trip_stops.get_or_insert(0);
+                    elevator.travel_ticks += 1;
+                    let travelled = start.abs_diff(elevator.position);
+                    let trip = travelled + elevator.position.abs_diff(dest);
+                    let floor_ms = if dest > elevator.position {
+                        self.up_floor_ms
+                    } else {
+                        self.down_floor_ms
+                    };
+                    let motion_profile = self.motion_profile;
+                    let trip_ticks = self
+                        .trip_ticks
+                        .entry(trip)
+                        .or_insert_with(|| motion_profile.trip_ticks(trip));
+                    let ticks =
+                        trip_ticks[travelled] * elevator.ticks_per_floor() as f64 * floor_ms as f64
+                            / TICK_MS as f64;
+                    if (elevator.travel_ticks as f64) < ticks - 1e-9 {
+                        elevator.between_floors = true;
+                        continue;
+                    }
+                }
+                elevator.between_floors = false;
+                if dest != elevator.position {
+                    self.floors_travelled += 1;
+                    let direction = Direction::between(elevator.position, dest);
+                    if elevator
+                        .last_direction
+                        .is_some_and(|last| last != direction)
+                    {
+                        elevator.reversals += 1;
+                    }
+                    elevator.last_direction = Some(direction);
+                }
                 if dest > elevator.position {
                     elevator.position += 1;
                 }
                 if dest < elevator.position {
                     elevator.position -= 1;
                 }
-                events_tx
-                    .send(BuildingEvent::AtFloor(el, elevator.position))
-                    .unwrap();
+                events.push(BuildingEvent::AtFloor(el, elevator.position));
 
                 // If the elevator has reached its destination, open
                 // the doors and let passengers get on and off.
                 if elevator.position == dest {
                     elevator.destination = None;
+                    elevator.trip_start = None;
+                    elevator.express = false;
                     elevator.doors_open = true;
-                    self.exchange_passengers(events_tx, el).await;
+                    elevator.dwell_ticks_left = elevator.dwell_ticks();
+                    elevator.door_reopens = 0;
+                    *elevator.trip_stops.get_or_insert(0) += 1;
+                    let aborted = std::mem::take(&mut elevator.aborting);
+                    if aborted {
+                        events.push(BuildingEvent::TripAborted(el, dest));
+                    }
+                    events.push(BuildingEvent::DoorsOpened(el, dest));
+                    let ghost_stop = !aborted
+                        && self.floors[dest].passengers.is_empty()
+                        && elevator.passengers.iter().all(|px| px.destination != dest);
+                    if ghost_stop {
+                        elevator.dwell_ticks_left =
+                            self.ghost_stop_dwell.unwrap_or(GHOST_STOP_DWELL_TICKS);
+                        events.push(BuildingEvent::GhostStop(el, dest));
+                    }
+                    if aborted {
+                        self.let_everyone_off(events, el);
+                    } else {
+                        self.exchange_passengers(events, el);
+                    }
+                    let stops = self.elevators[el].trip_stops.unwrap_or_default();
+                    if self
+                        .max_trip_stops
+                        .is_some_and(|max_stops| stops > max_stops)
+                    {
+                        warn!(
+                            elevator = el,
+                            stops,
+                            passengers = self.elevators[el].passengers.len(),
+                            floor = dest,
+                            "too many stops on one trip, unloading everyone"
+                        );
+                        self.let_everyone_off(events, el);
+                        events.push(BuildingEvent::ForcedUnload(el, dest));
+                    }
+                    let elevator = &mut self.elevators[el];
+                    if elevator.passengers.is_empty() {
+                        if let Some(stops) = elevator.trip_stops.take() {
+                            *self.trips_by_stop_count.entry(stops).or_default() += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns true if something blocks the elevator's doors as they close, so that they
+    /// open again. See `Building::with_door_obstruction`.
+    fn doors_obstructed(&mut self, el: ElevatorId) -> bool {
+        let Some((probability, max_reopens)) = self.door_obstruction else {
+            return false;
+        };
+        self.elevators[el].door_reopens < max_reopens && self.rng().gen_bool(probability)
+    }
+
+    /// Returns true if a waiting passenger misses the elevator stopped for them. See
+    /// `Building::with_missed_boarding`.
+    fn boarding_missed(&mut self) -> bool {
+        let Some(probability) = self.missed_boarding else {
+            return false;
+        };
+        self.rng().gen_bool(probability)
+    }
+
+    /// Tell the simulated time, once every `CLOCK_INTERVAL_MS` unless the building is given
+    /// another interval.
+    fn tell_time(&self, events: &mut Vec<BuildingEvent>) {
+        let now_ms = self.ticks * TICK_MS;
+        if now_ms.is_multiple_of(self.clock_interval_ms.unwrap_or(CLOCK_INTERVAL_MS)) {
+            events.push(BuildingEvent::Clock(now_ms));
+        }
+    }
+
+    /// Report how long the longest-waiting passenger has been waiting, if anyone is waiting.
+    fn report_longest_wait(&self, events: &mut Vec<BuildingEvent>) {
+        let oldest = self
+            .floors
+            .iter()
+            .flat_map(|floor| &floor.passengers)
+            .map(|px| px.arrived_at)
+            .min();
+        if let Some(arrived_at) = oldest {
+            let waited = arrived_at.elapsed().mul_f64(self.time_scale).as_millis() as u64;
+            events.push(BuildingEvent::LongestWait(waited));
+        }
+    }
+
+    /// Report the calls that have been waiting for longer than the call SLA.
+    fn check_call_sla(&mut self, events: &mut Vec<BuildingEvent>) {
+        let Some(sla) = self.call_sla else {
+            return;
+        };
+        // A call is as old as the passenger who has waited longest for it.
+        let mut oldest: HashMap<(FloorId, Direction), time::Instant> = HashMap::new();
+        for (fl, floor) in self.floors.iter().enumerate() {
+            for px in &floor.passengers {
+                let dir = Direction::between(fl, px.destination);
+                let arrived_at = oldest.entry((fl, dir)).or_insert(px.arrived_at);
+                *arrived_at = (*arrived_at).min(px.arrived_at);
+            }
+        }
+        // Calls that have been answered may break the SLA again.
+        self.sla_violated_calls
+            .retain(|call| oldest.contains_key(call));
+        for (call, arrived_at) in oldest {
+            let waited = arrived_at.elapsed().mul_f64(self.time_scale);
+            if waited > sla && self.sla_violated_calls.insert(call) {
+                self.sla_violations += 1;
+                let (fl, dir) = call;
+                events.push(BuildingEvent::SlaViolated(
+                    fl,
+                    dir,
+                    waited.as_millis() as u64,
+                ));
+            }
+        }
+    }
+
+    /// Report the groups of elevators travelling the same way close behind each other, when
+    /// they form or change. See `Building::with_bunching_detection`.
+    fn detect_bunching(&mut self, events: &mut Vec<BuildingEvent>) {
+        let Some((max_gap, min_elevators)) = self.bunching else {
+            return;
+        };
+        let mut groups = Vec::new();
+        for direction in [Direction::Up, Direction::Down] {
+            let mut moving: Vec<(FloorId, ElevatorId)> = self
+                .elevators
+                .iter()
+                .enumerate()
+                .filter(|(_, elevator)| {
+                    elevator.destination.is_some_and(|dest| {
+                        dest != elevator.position
+                            && (dest > elevator.position) == (direction == Direction::Up)
+                    })
+                })
+                .map(|(el, elevator)| (elevator.position, el))
+                .collect();
+            moving.sort_unstable();
+            // Elevators close enough to the one below belong to its group.
+            let mut group: Vec<(FloorId, ElevatorId)> = Vec::new();
+            for (position, el) in moving {
+                if group
+                    .last()
+                    .is_some_and(|&(below, _)| position - below > max_gap)
+                {
+                    groups.push(std::mem::take(&mut group));
                 }
+                group.push((position, el));
             }
+            groups.push(group);
         }
+        let mut bunches: Vec<Vec<ElevatorId>> = groups
+            .into_iter()
+            .filter(|group| group.len() >= min_elevators)
+            .map(|group| {
+                let mut elevators: Vec<ElevatorId> = group.into_iter().map(|(_, el)| el).collect();
+                elevators.sort_unstable();
+                elevators
+            })
+            .collect();
+        bunches.sort_unstable();
+        for bunch in &bunches {
+            if !self.bunches.contains(bunch) {
+                events.push(BuildingEvent::Bunching(bunch.clone()));
+            }
+        }
+        self.bunches = bunches;
     }
 
     /// Handle a new passenger arriving at the given floor.
     async fn new_passenger(
         &mut self,
         events_tx: &broadcast::Sender<BuildingEvent>,
-        at: FloorId,
-        destination: FloorId,
-    ) {
-        println!("Passenger arrived at {} going to {}", at, destination);
+        mut at: FloorId,
+        mut destination: FloorId,
+        reduced_mobility: bool,
+        mut entrance: EntranceId,
+        decides_at_boarding: bool,
+    ) -> Result<(), Error> {
+        if decides_at_boarding {
+            let dir = Direction::between(at, destination);
+            debug!(floor = at, direction = %dir, "passenger arrived");
+        } else {
+            debug!(floor = at, destination, "passenger arrived");
+        }
+        let top = self.floors.len() - 1;
+        if at > top || destination > top {
+            let dir = Direction::between(at, destination);
+            warn!(
+                direction = %dir,
+                floor = at,
+                destination,
+                top_floor = top,
+                "call beyond the top floor"
+            );
+            match self.invalid_calls {
+                InvalidCalls::Normalize => {
+                    at = at.min(top);
+                    destination = destination.min(top);
+                }
+                InvalidCalls::Reject => {
+                    self.send_event(events_tx, BuildingEvent::CallRejected(at, dir))?;
+                    return Ok(());
+                }
+            }
+        }
+        // Passengers who haven't decided have no destination yet.
+        let forbidden_destination =
+            !decides_at_boarding && self.forbidden_floors.contains(&destination);
+        if self.forbidden_floors.contains(&at) || forbidden_destination {
+            let dir = Direction::between(at, destination);
+            warn!(
+                direction = %dir,
+                floor = at,
+                destination,
+                unserved = if forbidden_destination { destination } else { at },
+                "call to or from a floor no elevator stops at"
+            );
+            self.send_event(events_tx, BuildingEvent::CallRejected(at, dir))?;
+            return Ok(());
+        }
         if at == destination {
-            events_tx
-                .send(BuildingEvent::PassengerDelivered(destination))
-                .unwrap();
-            return;
+            self.send_event(events_tx, BuildingEvent::PassengerStayed(destination))?;
+            return Ok(());
+        }
+        let entrances = self.floors[at].entrances;
+        if entrance >= entrances {
+            let dir = Direction::between(at, destination);
+            warn!(
+                direction = %dir,
+                floor = at,
+                entrance,
+                entrances,
+                "call from an entrance the floor doesn't have"
+            );
+            match self.invalid_calls {
+                InvalidCalls::Normalize => entrance = 0,
+                InvalidCalls::Reject => {
+                    self.send_event(events_tx, BuildingEvent::CallRejected(at, dir))?;
+                    return Ok(());
+                }
+            }
         }
 
-        self.floors[at].passengers.push(Passenger::new(destination));
-        let dir = if at < destination {
-            Direction::Up
-        } else {
-            Direction::Down
-        };
-        events_tx
-            .send(BuildingEvent::CallButtonPressed(at, dir))
-            .unwrap();
+        // Passengers with reduced mobility take any elevator if none are accessible.
+        let reduced_mobility = reduced_mobility && self.has_accessible_elevator();
+        let mut px = Passenger::new(
+            self.next_passenger_id,
+            destination,
+            reduced_mobility,
+            entrance,
+        );
+        self.next_passenger_id += 1;
+        px.decides_at_boarding = decides_at_boarding;
+        self.floors[at].calls += 1;
+        self.send_event(events_tx, px.call(at))?;
+        self.floors[at].passengers.push(px);
+        Ok(())
     }
 
     /// The doors for the given elevator are open, so take on and discharge passengers.
-    async fn exchange_passengers(
-        &mut self,
-        events_tx: &broadcast::Sender<BuildingEvent>,
-        el: ElevatorId,
-    ) {
+    fn exchange_passengers(&mut self, events: &mut Vec<BuildingEvent>, el: ElevatorId) {
         let elevator = &mut self.elevators[el];
         let fl = elevator.position;
 
@@ -240,30 +2213,1505 @@ impl Building {
             .passengers
             .drain(..)
             .partition(|px| px.destination == fl);
-        for px in this_floor {
-            let now = std::time::Instant::now();
-            let elapsed = (now - px.arrived_at).as_millis();
+        let mut leaving = this_floor.len();
+        for mut px in this_floor {
+            // Passengers changing elevators here call the next one.
+            if let Some(final_destination) = px.final_destination.take() {
+                px.destination = final_destination;
+                // They wait at the main entrance, where the elevators are.
+                px.entrance = 0;
+                events.push(px.call(fl));
+                self.floors[fl].passengers.push(px);
+                continue;
+            }
+            // Report the wait in simulated time.
+            let elapsed = px.arrived_at.elapsed().mul_f64(self.time_scale).as_millis();
             self.elapsed_times_per_passenger.push(elapsed as i64);
-            events_tx
-                .send(BuildingEvent::PassengerDelivered(px.destination))
-                .unwrap();
+            events.push(BuildingEvent::PassengerDelivered(px.destination));
         }
-        elevator.passengers = other_floors;
 
-        // Handle passengers entering the elevator.
-        for px in self.floors[fl].passengers.drain(..) {
-            events_tx
-                .send(BuildingEvent::FloorButtonPressed(el, px.destination))
-                .unwrap();
-            elevator.passengers.push(px);
+        // Passengers who can't get to their destination with this elevator change to one that
+        // can, if it stops here.
+        let stop_pattern = elevator.stop_pattern;
+        let (transferring, staying): (Vec<Passenger>, Vec<Passenger>) =
+            other_floors.into_iter().partition(|px| {
+                !stop_pattern.serves(px.destination)
+                    && self.elevators.iter().any(|other| {
+                        other.stop_pattern.serves(fl) && other.stop_pattern.serves(px.destination)
+                    })
+            });
+        self.elevators[el].passengers = staying;
+        leaving += transferring.len();
+        self.floors[fl].passengers.extend(transferring);
+        // Passengers getting off only make room for those waiting if they leave first.
+        if self.boarding_order == BoardingOrder::AlightFirst {
+            leaving = 0;
+        }
+
+        // Handle passengers entering the elevator. Passengers only board if the elevator
+        // stops at their destination, or if they can change to an elevator that does on the
+        // way and no elevator takes them there from here. If no elevator stops at their
+        // destination, they ride to the closest floor this elevator serves instead.
+        let accessible = self.elevators[el].accessible;
+        let mut declined = HashSet::new();
+        let mut left_behind = HashSet::new();
+        let announced = self.elevators[el].announced;
+        for mut px in std::mem::take(&mut self.floors[fl].passengers) {
+            // Passengers with reduced mobility keep waiting for their accessible elevator, and
+            // passengers going the other way to the lantern for an elevator going their way.
+            let going = Direction::between(fl, px.destination);
+            if (px.reduced_mobility && !accessible) || announced.is_some_and(|dir| dir != going) {
+                self.floors[fl].passengers.push(px);
+                continue;
+            }
+            if self.boarding_missed() {
+                events.push(BuildingEvent::MissedBoarding(fl, going));
+                left_behind.insert((going, px.reduced_mobility, px.entrance));
+                self.floors[fl].passengers.push(px);
+                continue;
+            }
+            // Passengers who haven't decided pick any floor in the direction they called.
+            if px.decides_at_boarding {
+                px.decides_at_boarding = false;
+                let floors = if fl < px.destination {
+                    fl + 1..px.destination + 1
+                } else {
+                    px.destination..fl
+                };
+                let floors: Vec<FloorId> = floors
+                    .filter(|fl| !self.forbidden_floors.contains(fl))
+                    .collect();
+                // Every floor that way is forbidden, so the passenger stays here.
+                if floors.is_empty() {
+                    events.push(BuildingEvent::PassengerStayed(fl));
+                    continue;
+                }
+                px.destination = floors[self.rng().gen_range(0..floors.len())];
+            }
+            if !stop_pattern.serves(px.destination) {
+                let served_elsewhere = self
+                    .elevators
+                    .iter()
+                    .any(|elevator| elevator.stop_pattern.serves(px.destination));
+                let direct = self.elevators.iter().any(|elevator| {
+                    elevator.stop_pattern.serves(fl) && elevator.stop_pattern.serves(px.destination)
+                });
+                match self.nearest_stop(stop_pattern, px.destination) {
+                    Some(nearest) if !served_elsewhere && nearest == fl => {
+                        // This floor is already as close as the passenger can get.
+                        events.push(BuildingEvent::PassengerStayed(fl));
+                        continue;
+                    }
+                    Some(nearest) if !served_elsewhere => px.destination = nearest,
+                    _ => match self.transfer_floor(stop_pattern, fl, px.destination) {
+                        Some(transfer) if !direct => {
+                            px.final_destination = Some(px.destination);
+                            px.destination = transfer;
+                        }
+                        _ => {
+                            declined.insert(Direction::between(fl, px.destination));
+                            self.floors[fl].passengers.push(px);
+                            continue;
+                        }
+                    },
+                }
+            }
+            let now_ms = self.ticks * TICK_MS;
+            let too_many_destinations = self
+                .max_destinations
+                .is_some_and(|max| self.elevators[el].too_many_destinations(px.destination, max));
+            if too_many_destinations {
+                events.push(BuildingEvent::TooManyDestinations(el, px.destination));
+            }
+            if too_many_destinations
+                || self.elevators[el].is_full(leaving)
+                || self.elevators[el].maintenance_due(now_ms)
+            {
+                if let Some(final_destination) = px.final_destination.take() {
+                    px.destination = final_destination;
+                }
+                let dir = Direction::between(fl, px.destination);
+                left_behind.insert((dir, px.reduced_mobility, px.entrance));
+                self.floors[fl].passengers.push(px);
+                continue;
+            }
+            events.push(BuildingEvent::PassengerBoarded(el, fl));
+            self.elevators[el].boarded += 1;
+            if !px.boarded {
+                px.boarded = true;
+                let waited = px.arrived_at.elapsed().mul_f64(self.time_scale).as_millis();
+                self.wait_times_per_passenger.push(waited as i64);
+            }
+            if self.boarding_delay_ticks == 0 {
+                events.push(floor_button(el, stop_pattern, px.destination));
+            } else {
+                px.presses_button_at = Some(self.ticks + self.boarding_delay_ticks);
+            }
+            self.elevators[el].passengers.push(px);
+        }
+        for dir in declined {
+            events.push(BuildingEvent::BoardingDeclined(el, fl, dir));
+        }
+        // Passengers who didn't fit, whose floor button wasn't lit, whose elevator is due for
+        // maintenance, or who missed it, call another elevator.
+        for (dir, reduced_mobility, entrance) in left_behind {
+            let evt = if reduced_mobility {
+                BuildingEvent::AccessibleCallButtonPressed(fl, dir)
+            } else {
+                BuildingEvent::CallButtonPressed(fl, dir, entrance)
+            };
+            events.push(evt);
         }
     }
 
-    pub(crate) fn num_elevators(&self) -> usize {
+    /// The generator for the building's random choices, see `Building::with_seed`.
+    fn rng(&mut self) -> &mut StdRng {
+        self.rng.get_or_insert_with(StdRng::from_entropy)
+    }
+
+    /// Let everyone off the elevator at the end of an aborted trip or one with too many stops.
+    /// Passengers who aren't where they are going wait on the floor and call again.
+    fn let_everyone_off(&mut self, events: &mut Vec<BuildingEvent>, el: ElevatorId) {
+        let fl = self.elevators[el].position;
+        for mut px in std::mem::take(&mut self.elevators[el].passengers) {
+            // Passengers changing elevators go the rest of the way from here.
+            if let Some(final_destination) = px.final_destination.take() {
+                px.destination = final_destination;
+            }
+            if px.destination == fl {
+                let elapsed = px.arrived_at.elapsed().mul_f64(self.time_scale).as_millis();
+                self.elapsed_times_per_passenger.push(elapsed as i64);
+                events.push(BuildingEvent::PassengerDelivered(fl));
+                continue;
+            }
+            px.presses_button_at = None;
+            px.entrance = 0;
+            events.push(px.call(fl));
+            self.floors[fl].passengers.push(px);
+        }
+    }
+
+    /// The floor closest to `floor` where an elevator with the given stop pattern may stop,
+    /// leaving out forbidden floors.
+    fn nearest_stop(&self, stop_pattern: StopPattern, floor: FloorId) -> Option<FloorId> {
+        (0..self.floors.len())
+            .filter(|fl| stop_pattern.serves(*fl) && !self.forbidden_floors.contains(fl))
+            .min_by_key(|fl| fl.abs_diff(floor))
+    }
+
+    /// The floor closest to `destination` where a passenger riding an elevator with the given
+    /// stop pattern from `from` can change to an elevator that stops at `destination`.
+    fn transfer_floor(
+        &self,
+        stop_pattern: StopPattern,
+        from: FloorId,
+        destination: FloorId,
+    ) -> Option<FloorId> {
+        (0..self.floors.len())
+            .filter(|&fl| {
+                fl != from && stop_pattern.serves(fl) && !self.forbidden_floors.contains(&fl)
+            })
+            .filter(|&fl| {
+                self.elevators.iter().any(|elevator| {
+                    elevator.stop_pattern.serves(fl) && elevator.stop_pattern.serves(destination)
+                })
+            })
+            .min_by_key(|fl| fl.abs_diff(destination))
+    }
+
+    pub fn num_elevators(&self) -> usize {
         self.elevators.len()
     }
 
-    pub(crate) fn num_floors(&self) -> usize {
+    pub fn num_floors(&self) -> usize {
         self.floors.len()
     }
+
+    /// See `Building::with_time_scale`.
+    pub fn time_scale(&self) -> f64 {
+        self.time_scale
+    }
+
+    pub fn elevator_configs(&self) -> Vec<ElevatorConfig> {
+        self.elevators
+            .iter()
+            .map(|elevator| ElevatorConfig {
+                stop_pattern: elevator.stop_pattern,
+                accessible: elevator.accessible,
+                rest_floor: elevator.rest_floor,
+                floor_time: time::Duration::from_millis(
+                    self.up_floor_ms.max(self.down_floor_ms)
+                        * u64::from(elevator.ticks_per_floor()),
+                )
+                .div_f64(self.time_scale),
+                forbidden_floors: self.forbidden_floors.clone(),
+            })
+            .collect()
+    }
+
+    fn has_accessible_elevator(&self) -> bool {
+        self.elevators.iter().any(|elevator| elevator.accessible)
+    }
+}
+
+/// The event sent when a passenger aboard the elevator presses the button for `destination`.
+fn floor_button(el: ElevatorId, stop_pattern: StopPattern, destination: FloorId) -> BuildingEvent {
+    if stop_pattern.serves(destination) {
+        BuildingEvent::FloorButtonPressed(el, destination)
+    } else {
+        BuildingEvent::UnreachableDestination(el, destination)
+    }
+}
+
+/// The mean and the 95th percentile of `times`, or zeros if there are none.
+fn mean_and_p95(times: &[i64]) -> (f64, i64) {
+    let mut sorted = times.to_vec();
+    sorted.sort_unstable();
+    let mean = sorted.iter().sum::<i64>() as f64 / sorted.len().max(1) as f64;
+    let p95 = sorted
+        .get((sorted.len() * 95).div_ceil(100).saturating_sub(1))
+        .copied()
+        .unwrap_or_default();
+    (mean, p95)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::ControllerConfig;
+    use crate::event_stream::StreamItem;
+    use crate::testing::{self, Harness};
+
+    #[test]
+    fn stop_patterns_serve_their_floors() {
+        assert!((0..10).all(|fl| StopPattern::All.serves(fl)));
+        assert_eq!(
+            (0..10)
+                .filter(|&fl| StopPattern::Odd.serves(fl))
+                .collect::<Vec<_>>(),
+            [1, 3, 5, 7, 9]
+        );
+        assert_eq!(
+            (0..10)
+                .filter(|&fl| StopPattern::Even.serves(fl))
+                .collect::<Vec<_>>(),
+            [0, 2, 4, 6, 8]
+        );
+        assert_eq!(
+            (0..10)
+                .filter(|&fl| StopPattern::Every(3).serves(fl))
+                .collect::<Vec<_>>(),
+            [0, 3, 6, 9]
+        );
+        assert_eq!(StopPattern::Odd.nearest_served(0, 10), Some(1));
+        assert_eq!(StopPattern::Every(4).nearest_served(7, 10), Some(8));
+        assert_eq!(StopPattern::Bank(5, 6).nearest_served(0, 3), None);
+    }
+
+    #[test]
+    fn events_display_as_sentences() {
+        use BuildingEvent::*;
+        let cases = [
+            (
+                FloorButtonPressed(3, 12),
+                "Floor 12 requested in elevator 3",
+            ),
+            (
+                CallButtonPressed(4, Direction::Up, 0),
+                "Elevator called up at floor 4",
+            ),
+            (
+                CallButtonPressed(4, Direction::Down, 1),
+                "Elevator called down at floor 4, entrance 1",
+            ),
+            (
+                AccessibleCallButtonPressed(2, Direction::Up),
+                "Accessible elevator called up at floor 2",
+            ),
+            (
+                CallRejected(0, Direction::Down),
+                "Impossible call down at floor 0 rejected",
+            ),
+            (AtFloor(3, 12), "Elevator 3 arrived at floor 12"),
+            (DoorsOpened(1, 5), "Elevator 1 opened its doors at floor 5"),
+            (
+                DoorsClosed(1, 5, vec![7, 8]),
+                "Elevator 1 closed its doors at floor 5 with passengers [7, 8] aboard",
+            ),
+            (DoorReopened(2), "Elevator 2 reopened its doors"),
+            (
+                GhostStop(0, 6),
+                "Elevator 0 stopped at floor 6 with nobody to get on or off",
+            ),
+            (
+                PassengerBoarded(0, 3),
+                "Passenger boarded elevator 0 at floor 3",
+            ),
+            (PassengerDelivered(9), "Passenger delivered to floor 9"),
+            (PassengerStayed(2), "Passenger stayed at floor 2"),
+            (
+                BoardingDeclined(1, 4, Direction::Up),
+                "Passenger going up at floor 4 declined elevator 1",
+            ),
+            (
+                MissedBoarding(4, Direction::Down),
+                "Passenger going down at floor 4 missed the elevator",
+            ),
+            (
+                UnreachableDestination(0, 4),
+                "Floor 4 requested in elevator 0, which doesn't stop there",
+            ),
+            (
+                LongestWait(1500),
+                "Longest-waiting passenger has waited 1500ms",
+            ),
+            (
+                ForcedUnload(2, 7),
+                "Everyone aboard elevator 2 was let off at floor 7",
+            ),
+            (
+                ExpressModeEngaged(1, 0),
+                "Elevator 1 going nonstop to floor 0",
+            ),
+            (TripAborted(1, 3), "Elevator 1 aborted its trip at floor 3"),
+            (OutOfService(2), "Elevator 2 out of service"),
+            (BackInService(2), "Elevator 2 back in service"),
+            (
+                TooManyDestinations(0, 8),
+                "Floor 8 refused in elevator 0, too many floors requested",
+            ),
+            (
+                StallDetected(100, 3),
+                "Stall detected with 100 events and 3 commands queued",
+            ),
+            (
+                SlaViolated(5, Direction::Up, 60000),
+                "Call up at floor 5 unanswered for 60000ms, over the SLA",
+            ),
+            (
+                Bunching(vec![0, 2]),
+                "Elevators [0, 2] are bunched together",
+            ),
+            (Clock(60000), "60000ms of simulated time passed"),
+            (Paused, "Simulation paused"),
+            (Resumed, "Simulation resumed"),
+        ];
+        for (evt, expected) in cases {
+            assert_eq!(evt.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn commands_display_as_sentences() {
+        use BuildingCommand::*;
+        let cases = [
+            (GoToFloor(3, 12), "Elevator 3 sent to floor 12"),
+            (Pause, "Pause the simulation"),
+            (Resume, "Resume the simulation"),
+            (ExpressToLobby(1), "Elevator 1 sent nonstop to the lobby"),
+            (AbortTrip(0), "Elevator 0 told to abort its trip"),
+            (
+                SetDirection(1, Some(Direction::Down)),
+                "Elevator 1 going down",
+            ),
+            (SetDirection(1, None), "Elevator 1 going either way"),
+            (
+                ScheduleMaintenance(2, 1000, 5000),
+                "Elevator 2 scheduled for maintenance from 1000ms to 5000ms",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(cmd.to_string(), expected);
+        }
+        assert_eq!(Direction::Up.to_string(), "up");
+        assert_eq!(Direction::Down.to_string(), "down");
+    }
+
+    #[test]
+    fn direction_between_floors() {
+        assert_eq!(Direction::between(2, 7), Direction::Up);
+        assert_eq!(Direction::between(7, 2), Direction::Down);
+        assert_eq!(Direction::between(4, 4), Direction::Down);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one floor")]
+    fn building_without_floors_is_refused() {
+        Building::new(0, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn odd_only_elevator_never_stops_on_even_floors() {
+        let building = Building::new(10, 2).with_stop_pattern(0, StopPattern::Odd);
+        let arrivals = [(0, 5), (3, 8), (9, 2), (7, 1), (4, 9), (6, 0), (1, 7)];
+        let (events, summary) =
+            testing::run(building, ControllerConfig::default(), &arrivals).await;
+        assert_eq!(summary.delivered, arrivals.len());
+        let odd_stops = events
+            .iter()
+            .filter_map(|evt| match *evt {
+                BuildingEvent::DoorsOpened(0, fl) => Some(fl),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert!(
+            !odd_stops.is_empty(),
+            "the odd-only elevator was never used"
+        );
+        assert!(
+            odd_stops.iter().all(|fl| fl % 2 == 1),
+            "the odd-only elevator stopped at {:?}",
+            odd_stops
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn longest_wait_is_the_age_of_the_oldest_call() {
+        let (task, mut events_rx, _building_cmd_tx, driver_cmd_tx) = Building::new(10, 1).start();
+        let arrive = |at| DriverCommand::PassengerArrived {
+            at,
+            destination: 0,
+            reduced_mobility: false,
+            entrance: 0,
+        };
+        // Nobody answers the calls, so the first passenger waits the whole time.
+        driver_cmd_tx.send(arrive(3)).await.unwrap();
+        time::sleep(time::Duration::from_millis(1000)).await;
+        driver_cmd_tx.send(arrive(5)).await.unwrap();
+        time::sleep(time::Duration::from_millis(1000)).await;
+        driver_cmd_tx.send(DriverCommand::Halt).await.unwrap();
+        task.await.unwrap().unwrap();
+        let mut waits = Vec::new();
+        while let Ok(evt) = events_rx.recv().await {
+            if let BuildingEvent::LongestWait(ms) = evt {
+                waits.push(ms);
+            }
+        }
+        assert!(waits.len() >= 19, "only {} reports", waits.len());
+        // Each tick the oldest call is a tick older, even after the second call.
+        for pair in waits.windows(2) {
+            assert_eq!(pair[1], pair[0] + TICK_MS);
+        }
+        assert!(*waits.last().unwrap() >= 1900);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn trip_paused_midway_takes_as_long_as_without_pausing() {
+        let (_, unpaused) = testing::run(Building::new(10, 1), Default::default(), &[(0, 9)]).await;
+
+        let mut harness = Harness::start(Building::new(10, 1), Default::default());
+        harness.arrive(0, 9).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::AtFloor(0, 4))
+            .await;
+        harness.command(BuildingCommand::Pause).await;
+        harness.wait_for(|evt| *evt == BuildingEvent::Paused).await;
+        harness.run_for(time::Duration::from_secs(30)).await;
+        assert_eq!(harness.log.last(), Some(&BuildingEvent::Paused));
+        harness.command(BuildingCommand::Resume).await;
+        let resumed_at = time::Instant::now();
+        harness.wait_for_deliveries(1).await;
+        // Five more floors, a tick each, rather than catching up on the ticks missed.
+        assert!(resumed_at.elapsed() >= time::Duration::from_millis(5 * TICK_MS));
+        let (events, paused) = harness.halt().await;
+        let floors: Vec<_> = events
+            .iter()
+            .filter_map(|evt| match *evt {
+                BuildingEvent::AtFloor(0, fl) => Some(fl),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(floors, (0..=9).collect::<Vec<_>>());
+        assert_eq!(paused.mean_journey_ms, unpaused.mean_journey_ms);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reduced_mobility_passenger_rides_the_accessible_elevator() {
+        let building = Building::new(10, 2).with_accessible_elevator(1);
+        let mut harness = Harness::start(building, Default::default());
+        harness
+            .drive(DriverCommand::PassengerArrived {
+                at: 3,
+                destination: 7,
+                reduced_mobility: true,
+                entrance: 0,
+            })
+            .await;
+        let mut opened_at = None;
+        let mut dwell = None;
+        while dwell.is_none() {
+            match harness.next_event().await.unwrap() {
+                BuildingEvent::DoorsOpened(1, 3) => opened_at = Some(time::Instant::now()),
+                BuildingEvent::DoorsClosed(1, 3, _) => dwell = opened_at.map(|at| at.elapsed()),
+                _ => {}
+            }
+        }
+        harness.wait_for_deliveries(1).await;
+        let (events, _) = harness.halt().await;
+        assert!(events.contains(&BuildingEvent::AccessibleCallButtonPressed(
+            3,
+            Direction::Up
+        )));
+        assert!(events.contains(&BuildingEvent::PassengerBoarded(1, 3)));
+        assert!(!events
+            .iter()
+            .any(|evt| matches!(evt, BuildingEvent::PassengerBoarded(0, _))));
+        assert_eq!(
+            dwell,
+            Some(time::Duration::from_millis(
+                u64::from(ACCESSIBLE_DWELL_TICKS) * TICK_MS
+            ))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn building_with_nobody_listening_fails() {
+        let (task, events_rx, _building_cmd_tx, driver_cmd_tx) = Building::new(10, 1).start();
+        drop(events_rx);
+        driver_cmd_tx
+            .send(DriverCommand::PassengerArrived {
+                at: 3,
+                destination: 0,
+                reduced_mobility: false,
+                entrance: 0,
+            })
+            .await
+            .unwrap();
+        assert_eq!(task.await.unwrap(), Err(Error::NoEventSubscribers));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn floor_outside_the_stop_pattern_is_rejected() {
+        assert_eq!(
+            floor_button(0, StopPattern::Odd, 4),
+            BuildingEvent::UnreachableDestination(0, 4)
+        );
+        let mut building = Building::new(10, 1).with_stop_pattern(0, StopPattern::Odd);
+        // A passenger who got aboard anyway, about to press the button for floor 4.
+        building.elevators[0].passengers.push(Passenger {
+            presses_button_at: Some(1),
+            ..Passenger::new(0, 4, false, 0)
+        });
+        let (_task, mut events_rx, building_cmd_tx, _driver_cmd_tx) = building.start();
+        assert_eq!(
+            events_rx.recv().await.unwrap(),
+            BuildingEvent::UnreachableDestination(0, 4)
+        );
+        let (reply_tx, reply_rx) = oneshot::channel();
+        building_cmd_tx
+            .send(BuildingCommand::Snapshot(reply_tx))
+            .await
+            .unwrap();
+        let snapshot = reply_rx.await.unwrap();
+        assert_eq!(snapshot.elevators[0].passenger_destinations, [4]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn passenger_changes_banks_at_the_sky_lobby() {
+        let building = Building::new(41, 2)
+            .with_stop_pattern(0, StopPattern::Bank(0, 20))
+            .with_stop_pattern(1, StopPattern::Bank(20, 40));
+        let (events, summary) =
+            testing::run(building, ControllerConfig::default(), &[(5, 35)]).await;
+        assert_eq!(summary.delivered, 1);
+        let legs: Vec<_> = events
+            .iter()
+            .filter(|evt| {
+                matches!(
+                    evt,
+                    BuildingEvent::PassengerBoarded(..) | BuildingEvent::PassengerDelivered(_)
+                )
+            })
+            .cloned()
+            .collect();
+        assert_eq!(
+            legs,
+            [
+                BuildingEvent::PassengerBoarded(0, 5),
+                BuildingEvent::PassengerBoarded(1, 20),
+                BuildingEvent::PassengerDelivered(35),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn s_curve_short_trips_take_longer_than_trapezoidal() {
+        assert!(
+            MotionProfile::SCurve.ticks_to_floor(2, 2)
+                > MotionProfile::Trapezoidal.ticks_to_floor(2, 2)
+        );
+        let mut journeys = Vec::new();
+        for profile in [MotionProfile::Trapezoidal, MotionProfile::SCurve] {
+            let building = Building::new(10, 1).with_motion_profile(profile);
+            let (_, summary) = testing::run(building, ControllerConfig::default(), &[(0, 2)]).await;
+            journeys.push(summary.mean_journey_ms);
+        }
+        assert!(journeys[1] > journeys[0], "journeys took {:?}", journeys);
+    }
+
+    #[test]
+    fn trip_ticks_are_the_ticks_to_each_floor() {
+        let profile = MotionProfile::SCurve;
+        let ticks = profile.trip_ticks(5);
+        assert_eq!(ticks.len(), 5);
+        for (floor, &at) in (1..=5).zip(&ticks) {
+            assert_eq!(at, profile.ticks_to_floor(floor, 5));
+        }
+        assert!(
+            ticks.windows(2).all(|pair| pair[0] < pair[1]),
+            "{:?}",
+            ticks
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn passengers_sharing_a_floor_get_off_at_one_stop() {
+        let (events, summary) = testing::run(
+            Building::new(10, 1),
+            ControllerConfig::default(),
+            &[(0, 7), (0, 7), (0, 7)],
+        )
+        .await;
+        assert_eq!(summary.delivered, 3);
+        let stops: Vec<_> = events
+            .iter()
+            .enumerate()
+            .filter(|(_, evt)| **evt == BuildingEvent::DoorsOpened(0, 7))
+            .collect();
+        assert_eq!(stops.len(), 1);
+        // Everyone gets off in the tick the doors open.
+        let first = stops[0].0 + 1;
+        assert_eq!(
+            events[first..first + 3],
+            [
+                BuildingEvent::PassengerDelivered(7),
+                BuildingEvent::PassengerDelivered(7),
+                BuildingEvent::PassengerDelivered(7),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn express_to_lobby_skips_every_stop_on_the_way() {
+        let mut harness = Harness::start(Building::new(10, 1), ControllerConfig::default());
+        harness.arrive(9, 5).await;
+        harness.arrive(9, 3).await;
+        harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::DoorsClosed(0, 9, _)))
+            .await;
+        harness.command(BuildingCommand::ExpressToLobby(0)).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::ExpressModeEngaged(0, 0))
+            .await;
+        let stop = harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::DoorsOpened(..)))
+            .await;
+        assert_eq!(stop, BuildingEvent::DoorsOpened(0, 0));
+        // The passengers aboard are taken to their floors afterwards.
+        harness.wait_for_deliveries(2).await;
+        let (events, _) = harness.halt().await;
+        assert!(events.contains(&BuildingEvent::PassengerDelivered(5)));
+        assert!(events.contains(&BuildingEvent::PassengerDelivered(3)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn trips_are_counted_by_their_stops() {
+        let mut harness = Harness::start(Building::new(10, 1), ControllerConfig::default());
+        // Picked up at the lobby, then two stops to leave the elevator empty.
+        harness.arrive(0, 3).await;
+        harness.arrive(0, 5).await;
+        harness.wait_for_deliveries(2).await;
+        // Set off empty, then a stop to pick up and one to drop off.
+        harness.arrive(7, 2).await;
+        harness.wait_for_deliveries(1).await;
+        let (_, summary) = harness.halt().await;
+        assert_eq!(
+            summary.trips_by_stop_count,
+            BTreeMap::from([(2, 1), (3, 1)])
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn trip_with_too_many_stops_unloads_and_requeues_everyone() {
+        let building = Building::new(10, 1).with_max_trip_stops(2);
+        let arrivals = [(0, 9), (2, 9), (4, 9), (6, 9)];
+        let mut harness = Harness::start(building, ControllerConfig::default());
+        for (at, destination) in arrivals {
+            harness.arrive(at, destination).await;
+        }
+        harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::ForcedUnload(0, _)))
+            .await;
+        harness.wait_for_deliveries(arrivals.len()).await;
+        let (events, summary) = harness.halt().await;
+        // Nobody counts as delivered where they were let off.
+        let delivered: Vec<_> = events
+            .iter()
+            .filter_map(|evt| match *evt {
+                BuildingEvent::PassengerDelivered(fl) => Some(fl),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(delivered, [9; 4]);
+        assert_eq!(summary.delivered, arrivals.len());
+        assert!(summary.all_delivered());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn elevator_is_out_of_service_only_during_its_maintenance_window() {
+        let start = time::Instant::now();
+        let mut harness = Harness::start(Building::new(10, 2), ControllerConfig::default());
+        harness
+            .command(BuildingCommand::ScheduleMaintenance(1, 10_000, 30_000))
+            .await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::OutOfService(1))
+            .await;
+        let went = start.elapsed();
+        // Calls during the window are all answered by the other elevator.
+        harness.arrive(9, 0).await;
+        harness.arrive(0, 4).await;
+        harness.wait_for_deliveries(2).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::BackInService(1))
+            .await;
+        let back = start.elapsed();
+        let (events, _) = harness.halt().await;
+        let tick = time::Duration::from_millis(TICK_MS);
+        assert!(
+            went.abs_diff(time::Duration::from_secs(10)) <= tick,
+            "out at {:?}",
+            went
+        );
+        assert!(
+            back.abs_diff(time::Duration::from_secs(30)) <= tick,
+            "back at {:?}",
+            back
+        );
+        let window = events
+            .iter()
+            .skip_while(|evt| **evt != BuildingEvent::OutOfService(1))
+            .take_while(|evt| **evt != BuildingEvent::BackInService(1));
+        for evt in window {
+            assert!(
+                !matches!(
+                    evt,
+                    BuildingEvent::AtFloor(1, _) | BuildingEvent::DoorsOpened(1, _)
+                ),
+                "{:?} during maintenance",
+                evt
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn call_beyond_the_top_floor_is_rejected_or_normalized() {
+        let building = Building::new(10, 1).with_invalid_calls(InvalidCalls::Reject);
+        let mut harness = Harness::start(building, ControllerConfig::default());
+        harness.arrive(9, 12).await;
+        let evt = harness
+            .wait_for(|evt| {
+                matches!(
+                    evt,
+                    BuildingEvent::CallRejected(..) | BuildingEvent::CallButtonPressed(..)
+                )
+            })
+            .await;
+        assert_eq!(evt, BuildingEvent::CallRejected(9, Direction::Up));
+        harness.halt().await;
+
+        let building = Building::new(10, 1).with_invalid_calls(InvalidCalls::Normalize);
+        let (events, summary) =
+            testing::run(building, ControllerConfig::default(), &[(12, 3)]).await;
+        assert!(events.contains(&BuildingEvent::CallButtonPressed(9, Direction::Down, 0)));
+        assert!(events.contains(&BuildingEvent::PassengerDelivered(3)));
+        assert_eq!(summary.delivered, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn floors_beyond_the_destination_cap_are_refused() {
+        let building = Building::new(10, 1).with_max_destinations(2);
+        let arrivals = [(5, 6), (5, 7), (5, 8)];
+        let (events, summary) =
+            testing::run(building, ControllerConfig::default(), &arrivals).await;
+        // The last passenger to board is refused, maybe again if the doors reopen for them.
+        let refused: Vec<_> = events
+            .iter()
+            .filter(|evt| matches!(evt, BuildingEvent::TooManyDestinations(..)))
+            .collect();
+        assert!(!refused.is_empty());
+        assert!(refused
+            .iter()
+            .all(|evt| **evt == BuildingEvent::TooManyDestinations(0, 8)));
+        // The first trip only stops at two floors.
+        let first_trip: BTreeSet<_> = events
+            .iter()
+            .take_while(|evt| !matches!(evt, BuildingEvent::TooManyDestinations(..)))
+            .filter_map(|evt| match *evt {
+                BuildingEvent::FloorButtonPressed(_, fl) => Some(fl),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(first_trip.len(), 2);
+        // The refused passenger gets there on a later trip.
+        assert_eq!(summary.delivered, arrivals.len());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn time_scale_speeds_the_whole_trip_up() {
+        let mut took = Vec::new();
+        for time_scale in [1.0, 10.0] {
+            let start = time::Instant::now();
+            let building = Building::new(10, 1).with_time_scale(time_scale);
+            testing::run(building, ControllerConfig::default(), &[(0, 9)]).await;
+            took.push(start.elapsed());
+        }
+        let speedup = took[0].as_secs_f64() / took[1].as_secs_f64();
+        assert!((9.0..=11.0).contains(&speedup), "{:?}", took);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_and_busy_time_follow_the_timeline() {
+        let start = time::Instant::now();
+        let mut harness = Harness::start(Building::new(10, 1), ControllerConfig::default());
+        harness.run_for(time::Duration::from_secs(10)).await;
+        let called = start.elapsed();
+        harness.arrive(0, 5).await;
+        harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::DoorsClosed(0, 5, _)))
+            .await;
+        let done = start.elapsed();
+        harness.run_for(time::Duration::from_secs(10)).await;
+        let total = start.elapsed();
+        let (_, summary) = harness.halt().await;
+        let close = |ms: u64, expected: time::Duration| {
+            ms.abs_diff(expected.as_millis() as u64) <= 2 * TICK_MS
+        };
+        assert!(
+            close(summary.busy_ms[0], done - called),
+            "{:?}",
+            summary.busy_ms
+        );
+        assert!(
+            close(summary.idle_ms[0], total - (done - called)),
+            "{:?}",
+            summary.idle_ms
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn undecided_passenger_picks_a_floor_once_aboard() {
+        let mut harness = Harness::start(Building::new(10, 1), ControllerConfig::default());
+        harness
+            .drive(DriverCommand::PassengerArrivedUndecided {
+                at: 4,
+                direction: Direction::Up,
+                reduced_mobility: false,
+                entrance: 0,
+            })
+            .await;
+        harness.wait_for_deliveries(1).await;
+        let (events, _) = harness.halt().await;
+        let boarded = events
+            .iter()
+            .position(|evt| *evt == BuildingEvent::PassengerBoarded(0, 4))
+            .expect("passenger never boarded");
+        let pressed = events
+            .iter()
+            .position(|evt| matches!(evt, BuildingEvent::FloorButtonPressed(..)))
+            .expect("passenger never pressed a floor button");
+        assert!(pressed > boarded, "{:?}", events);
+        let BuildingEvent::FloorButtonPressed(0, destination) = events[pressed] else {
+            panic!("{:?}", events[pressed]);
+        };
+        assert!(destination > 4);
+        assert!(events.contains(&BuildingEvent::PassengerDelivered(destination)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn moving_elevator_sent_to_its_own_floor_keeps_going() {
+        let mut harness = Harness::start(Building::new(10, 1), ControllerConfig::default());
+        harness.arrive(0, 9).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::AtFloor(0, 3))
+            .await;
+        harness.command(BuildingCommand::GoToFloor(0, 3)).await;
+        harness.wait_for_deliveries(1).await;
+        let (events, _) = harness.halt().await;
+        let stops: Vec<_> = events
+            .iter()
+            .filter_map(|evt| match *evt {
+                BuildingEvent::DoorsOpened(_, fl) => Some(fl),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stops, [0, 9]);
+        let at_3 = events
+            .iter()
+            .filter(|evt| **evt == BuildingEvent::AtFloor(0, 3))
+            .count();
+        assert_eq!(at_3, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn subscriber_that_stops_reading_is_detected() {
+        let building = Building::new(10, 1).with_stall_timeout(time::Duration::from_secs(2));
+        let (task, stuck_rx, _building_cmd_tx, driver_cmd_tx) = building.start();
+        let mut events_rx = stuck_rx.resubscribe();
+        driver_cmd_tx
+            .send(DriverCommand::PassengerArrived {
+                at: 3,
+                destination: 5,
+                reduced_mobility: false,
+                entrance: 0,
+            })
+            .await
+            .unwrap();
+        let stall = time::timeout(time::Duration::from_secs(60), async {
+            loop {
+                if let BuildingEvent::StallDetected(events, commands) =
+                    events_rx.recv().await.unwrap()
+                {
+                    return (events, commands);
+                }
+            }
+        })
+        .await
+        .expect("stall never detected");
+        // The call is still waiting for the subscriber that stopped reading.
+        assert!(stall.0 >= 1, "{:?}", stall);
+        assert_eq!(stall.1, 0);
+        drop(stuck_rx);
+        driver_cmd_tx.send(DriverCommand::Halt).await.unwrap();
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn floor_button_is_pressed_a_boarding_delay_after_boarding() {
+        let delay = time::Duration::from_secs(2);
+        let building = Building::new(10, 1).with_boarding_delay(delay);
+        let mut harness = Harness::start(building, ControllerConfig::default());
+        harness.arrive(3, 7).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::PassengerBoarded(0, 3))
+            .await;
+        let boarded = time::Instant::now();
+        harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::FloorButtonPressed(..)))
+            .await;
+        let lag = boarded.elapsed();
+        assert!(
+            lag.abs_diff(delay) <= time::Duration::from_millis(TICK_MS),
+            "{:?}",
+            lag
+        );
+        harness.wait_for_deliveries(1).await;
+        harness.halt().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn calls_from_each_entrance_are_served_and_told_apart() {
+        let building = Building::new(10, 1).with_entrances(0, 2);
+        let mut harness = Harness::start(building, ControllerConfig::default());
+        for (entrance, destination) in [(0, 5), (1, 8)] {
+            harness
+                .drive(DriverCommand::PassengerArrived {
+                    at: 0,
+                    destination,
+                    reduced_mobility: false,
+                    entrance,
+                })
+                .await;
+        }
+        harness.wait_for_deliveries(2).await;
+        let (events, summary) = harness.halt().await;
+        for entrance in 0..2 {
+            assert!(
+                events.contains(&BuildingEvent::CallButtonPressed(
+                    0,
+                    Direction::Up,
+                    entrance
+                )),
+                "no call from entrance {}",
+                entrance
+            );
+        }
+        assert_eq!(summary.delivered, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn calls_waiting_past_the_sla_are_reported_once() {
+        let sla = time::Duration::from_secs(1);
+        let building = Building::new(20, 1).with_call_sla(sla);
+        let arrivals: Vec<_> = (10..20).map(|at| (at, 0)).chain([(0, 19)]).collect();
+        let (events, summary) = testing::run(building, Default::default(), &arrivals).await;
+        let violations: Vec<_> = events
+            .iter()
+            .filter_map(|evt| match *evt {
+                BuildingEvent::SlaViolated(fl, dir, ms) => Some((fl, dir, ms)),
+                _ => None,
+            })
+            .collect();
+        assert!(
+            !violations.is_empty(),
+            "one car can't answer every call in time"
+        );
+        assert_eq!(violations.len(), summary.sla_violations);
+        let sla_ms = sla.as_millis() as u64;
+        for &(fl, dir, ms) in &violations {
+            // Reported on the first tick past the SLA.
+            assert!(ms > sla_ms && ms <= sla_ms + TICK_MS, "{} ms", ms);
+            let expected = if fl == 0 {
+                Direction::Up
+            } else {
+                Direction::Down
+            };
+            assert_eq!(dir, expected);
+        }
+        let calls: HashSet<_> = violations.iter().map(|&(fl, dir, _)| (fl, dir)).collect();
+        assert_eq!(calls.len(), violations.len(), "a call was reported twice");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn manifest_shrinks_as_passengers_get_off() {
+        let arrivals = [(0, 3), (0, 5), (0, 8)];
+        let (events, _) = testing::run(Building::new(10, 1), Default::default(), &arrivals).await;
+        let manifests: Vec<_> = events
+            .iter()
+            .filter_map(|evt| match evt {
+                BuildingEvent::DoorsClosed(0, fl, manifest) => Some((*fl, manifest.clone())),
+                _ => None,
+            })
+            .filter(|(fl, _)| [0, 3, 5, 8].contains(fl))
+            .collect();
+        // Passengers are numbered in the order they arrived. The building stops once the
+        // last one is off, before the doors close at 8.
+        assert_eq!(
+            manifests,
+            [(0, vec![0, 1, 2]), (3, vec![1, 2]), (5, vec![2])]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn every_turn_around_is_a_reversal() {
+        let mut harness = Harness::start(Building::new(10, 1), Default::default());
+        // Back and forth, one passenger at a time.
+        for (at, destination) in [(0, 5), (5, 0), (0, 5), (5, 0), (0, 9)] {
+            harness.arrive(at, destination).await;
+            harness.wait_for_deliveries(1).await;
+        }
+        let (_, summary) = harness.halt().await;
+        // Up, down, up, down and up again.
+        assert_eq!(summary.reversals, [4]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn full_elevator_takes_the_passenger_waiting_where_someone_gets_off() {
+        for (order, boards) in [
+            (BoardingOrder::AlightFirst, true),
+            (BoardingOrder::BoardFirst, false),
+        ] {
+            let building = Building::new(10, 1)
+                .with_capacity(1)
+                .with_boarding_order(order)
+                .with_passenger_aboard(0, 5)
+                .with_waiting_passenger(5, 9);
+            let mut harness = Harness::start(building, Default::default());
+            harness
+                .wait_for(|evt| matches!(evt, BuildingEvent::DoorsClosed(0, 5, _)))
+                .await;
+            let boarded_at_5 = harness.log.contains(&BuildingEvent::PassengerBoarded(0, 5));
+            assert_eq!(boarded_at_5, boards, "{:?}", order);
+            harness.halt().await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn summary_tells_whether_everyone_was_delivered() {
+        let arrivals = [(0, 4), (6, 2), (3, 9)];
+        let (_, summary) = testing::run(Building::new(10, 2), Default::default(), &arrivals).await;
+        assert!(summary.all_delivered());
+        assert!(summary.undelivered.is_empty());
+
+        // Halted before anyone could be picked up.
+        let harness = Harness::start(Building::new(10, 1), Default::default());
+        harness.arrive(9, 0).await;
+        harness.arrive(8, 0).await;
+        let (_, summary) = harness.halt().await;
+        assert!(!summary.all_delivered());
+        assert_eq!(summary.undelivered, [0, 1]);
+    }
+
+    #[test]
+    fn events_of_a_tick_are_ordered_by_stage_then_elevator() {
+        use BuildingEvent::*;
+        let ordered = [
+            DoorsClosed(0, 2, vec![]),
+            AtFloor(0, 3),
+            AtFloor(1, 7),
+            DoorsOpened(1, 7),
+            PassengerDelivered(7),
+            PassengerBoarded(0, 3),
+            PassengerBoarded(1, 7),
+            BoardingDeclined(0, 3, Direction::Up),
+            FloorButtonPressed(1, 9),
+            LongestWait(300),
+        ];
+        let mut events: Vec<_> = ordered.iter().rev().cloned().collect();
+        events.sort_by_key(BuildingEvent::tick_order);
+        assert_eq!(events, ordered);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropping_the_task_stops_the_building() {
+        let (task, mut events_rx, building_cmd_tx, _driver_cmd_tx) = Building::new(10, 1).start();
+        drop(task);
+        // The building drops its end of the channels as it stops.
+        time::timeout(time::Duration::from_secs(1), async {
+            while events_rx.recv().await.is_ok() {}
+        })
+        .await
+        .expect("the building kept running");
+        assert!(building_cmd_tx.send(BuildingCommand::Pause).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn no_elevator_stops_at_a_forbidden_floor() {
+        let building = Building::new(20, 1)
+            .with_forbidden_floor(13)
+            .with_stop_pattern(0, StopPattern::Odd);
+        let mut harness = Harness::start(building, Default::default());
+        // Calls from or to the floor are turned away.
+        harness.arrive(13, 2).await;
+        harness.arrive(2, 13).await;
+        // The elevator doesn't stop at 14, so it takes the passenger as close as it can. That
+        // is 15, the floor below being forbidden.
+        harness.arrive(11, 14).await;
+        harness.command(BuildingCommand::GoToFloor(0, 13)).await;
+        harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::PassengerDelivered(_)))
+            .await;
+        let (events, _) = harness.halt().await;
+        assert_eq!(
+            events
+                .iter()
+                .filter(|evt| matches!(evt, BuildingEvent::CallRejected(..)))
+                .count(),
+            2
+        );
+        assert!(events.contains(&BuildingEvent::PassengerDelivered(15)));
+        assert!(!events
+            .iter()
+            .any(|evt| matches!(evt, BuildingEvent::DoorsOpened(_, 13))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropped_events_are_the_ones_a_slow_subscriber_missed() {
+        let building = Building::new(10, 1).with_events_capacity(3);
+        let (task, mut events_rx, _building_cmd_tx, driver_cmd_tx) = building.start();
+        // Nobody reads the events until the building has stopped.
+        for at in 1..10 {
+            driver_cmd_tx
+                .send(DriverCommand::PassengerArrived {
+                    at,
+                    destination: 0,
+                    reduced_mobility: false,
+                    entrance: 0,
+                })
+                .await
+                .unwrap();
+        }
+        driver_cmd_tx.send(DriverCommand::Halt).await.unwrap();
+        let summary = task.await.unwrap().unwrap();
+        let mut missed = 0;
+        let mut received = 0;
+        loop {
+            match events_rx.recv().await {
+                Ok(_) => received += 1,
+                Err(broadcast::error::RecvError::Lagged(n)) => missed += n as usize,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+        assert!(missed > 0);
+        assert_eq!(summary.dropped_events, missed);
+        // The queue kept as many events as its capacity, rounded up to a power of two.
+        assert_eq!(received, 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn passenger_already_on_their_floor_stays_without_being_delivered() {
+        let mut harness = Harness::start(Building::new(10, 1), Default::default());
+        harness.arrive(3, 3).await;
+        let evt = harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::PassengerStayed(_)))
+            .await;
+        assert_eq!(evt, BuildingEvent::PassengerStayed(3));
+        let (events, summary) = harness.halt().await;
+        assert!(!events
+            .iter()
+            .any(|evt| matches!(evt, BuildingEvent::PassengerDelivered(_))));
+        assert_eq!(summary.delivered, 0);
+        assert!(summary.all_delivered());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn obstructed_doors_reopen_once_per_stop() {
+        let building = Building::new(10, 1)
+            .with_door_obstruction(1.0, 1)
+            .with_seed(183);
+        let mut harness = Harness::start(building, Default::default());
+        harness.arrive(0, 5).await;
+        let mut opened_at = None;
+        let mut reopens = 0;
+        let mut dwell = None;
+        while dwell.is_none() {
+            match harness.next_event().await.unwrap() {
+                BuildingEvent::DoorsOpened(0, 5) => opened_at = Some(time::Instant::now()),
+                BuildingEvent::DoorReopened(0) if opened_at.is_some() => reopens += 1,
+                BuildingEvent::DoorsClosed(0, 5, _) => dwell = opened_at.map(|at| at.elapsed()),
+                _ => {}
+            }
+        }
+        harness.halt().await;
+        // One dwell, then one more after the doors reopen.
+        assert_eq!(reopens, 1);
+        assert_eq!(dwell, Some(time::Duration::from_millis(2 * TICK_MS)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn summary_reports_waits_and_serializes_every_metric() {
+        let (_, summary) =
+            testing::run(Building::new(10, 1), ControllerConfig::default(), &[(4, 0)]).await;
+        // The passenger waited for the elevator to come up, then rode back down.
+        assert!(summary.mean_wait_ms > 0.0);
+        assert!(summary.mean_wait_ms < summary.mean_journey_ms);
+        assert_eq!(summary.p95_wait_ms as f64, summary.mean_wait_ms);
+        let json: serde_json::Value = serde_json::from_str(&summary.to_json()).unwrap();
+        for key in [
+            "delivered",
+            "mean_journey_ms",
+            "p95_journey_ms",
+            "mean_wait_ms",
+            "p95_wait_ms",
+            "floors_travelled",
+            "reversals",
+            "idle_ms",
+            "busy_ms",
+            "boarded_by_elevator",
+            "throughput_per_hour",
+        ] {
+            assert!(json.get(key).is_some(), "{} missing", key);
+        }
+        assert_eq!(json["p95_wait_ms"], summary.p95_wait_ms);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropping_subscriber_misses_events_and_blocking_one_misses_none() {
+        let mut building = Building::new(10, 2);
+        // Both subscribers take a second over each event, far slower than the building.
+        let slow = |mut events: EventStream| {
+            tokio::spawn(async move {
+                let mut items = Vec::new();
+                while let Some(item) = events.next().await {
+                    time::sleep(time::Duration::from_secs(1)).await;
+                    items.push(item);
+                }
+                items
+            })
+        };
+        let dropping = slow(building.subscribe(Backpressure::Drop, 4));
+        let blocking = slow(building.subscribe(Backpressure::Block, 4));
+        let arrivals = [(0, 9), (8, 1), (3, 6)];
+        let (events, summary) =
+            testing::run(building, ControllerConfig::default(), &arrivals).await;
+        assert_eq!(summary.delivered, arrivals.len());
+        let events: Vec<_> = events.into_iter().map(StreamItem::Event).collect();
+        assert_eq!(blocking.await.unwrap(), events);
+        let dropping = dropping.await.unwrap();
+        let missed: u64 = dropping
+            .iter()
+            .map(|item| match item {
+                StreamItem::Lagged(missed) => *missed,
+                StreamItem::Event(_) => 0,
+            })
+            .sum();
+        assert!(missed > 0);
+        let received = dropping
+            .iter()
+            .filter(|item| matches!(item, StreamItem::Event(_)))
+            .count();
+        assert_eq!(received as u64 + missed, events.len() as u64);
+        // The subscriber's own queue overflowed, not the building's.
+        assert_eq!(summary.dropped_events, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn passengers_seeded_at_the_start_are_delivered() {
+        let building = Building::new(10, 2)
+            .with_passenger_aboard(1, 7)
+            .with_waiting_passenger(3, 0);
+        let mut harness = Harness::start(building, Default::default());
+        harness.wait_for_deliveries(2).await;
+        let (events, summary) = harness.halt().await;
+        assert!(events.contains(&BuildingEvent::AtFloor(1, 7)));
+        assert!(events.contains(&BuildingEvent::PassengerDelivered(7)));
+        assert!(events.contains(&BuildingEvent::PassengerDelivered(0)));
+        // Neither was announced, the controller found them in its first snapshot.
+        assert!(!events.contains(&BuildingEvent::FloorButtonPressed(1, 7)));
+        assert!(!events
+            .iter()
+            .any(|evt| matches!(evt, BuildingEvent::CallButtonPressed(3, ..))));
+        assert!(summary.all_delivered());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn passenger_who_always_misses_the_elevator_keeps_calling() {
+        let building = Building::new(10, 1).with_missed_boarding(1.0);
+        let mut harness = Harness::start(building, Default::default());
+        harness.arrive(4, 8).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::MissedBoarding(4, Direction::Up))
+            .await;
+        let evt = harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::DoorsClosed(0, 4, _)))
+            .await;
+        assert_eq!(evt, BuildingEvent::DoorsClosed(0, 4, vec![]));
+        assert!(harness
+            .log
+            .iter()
+            .any(|evt| matches!(evt, BuildingEvent::CallButtonPressed(4, Direction::Up, _))));
+        harness.run_for(time::Duration::from_secs(60)).await;
+        assert!(!harness
+            .log
+            .iter()
+            .any(|evt| matches!(evt, BuildingEvent::PassengerBoarded(..))));
+        // The call is still pending, so the elevator keeps coming back for it.
+        let missed = harness
+            .log
+            .iter()
+            .filter(|evt| **evt == BuildingEvent::MissedBoarding(4, Direction::Up))
+            .count();
+        assert!(missed > 1, "missed {} times", missed);
+        let state = harness.state().await;
+        assert!(
+            state.waiting_calls.contains(&(4, Direction::Up))
+                || state.elevators[0]
+                    .hall_calls()
+                    .contains(&(4, Direction::Up))
+        );
+        let (_, summary) = harness.halt().await;
+        assert_eq!(summary.undelivered, [0]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn calls_are_counted_by_the_floor_they_came_from() {
+        let mut harness = Harness::start(Building::new(10, 2), Default::default());
+        for (at, destination) in [(0, 5), (0, 7), (3, 1), (9, 0), (0, 2)] {
+            harness.arrive(at, destination).await;
+        }
+        harness.wait_for_deliveries(2).await;
+        let (reply_tx, reply_rx) = oneshot::channel();
+        harness.command(BuildingCommand::Snapshot(reply_tx)).await;
+        let snapshot = reply_rx.await.unwrap();
+        assert_eq!(snapshot.calls_by_floor, [3, 0, 0, 1, 0, 0, 0, 0, 0, 1]);
+        harness.wait_for_deliveries(3).await;
+        let (_, summary) = harness.halt().await;
+        assert_eq!(summary.calls_by_floor, [3, 0, 0, 1, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(summary.boarded_by_elevator.iter().sum::<usize>(), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn passengers_of_an_aborted_trip_are_still_delivered() {
+        let mut harness = Harness::start(Building::new(10, 2), Default::default());
+        harness.arrive(0, 9).await;
+        harness.arrive(0, 8).await;
+        let el = match harness
+            .wait_for(
+                |evt| matches!(evt, BuildingEvent::DoorsClosed(_, 0, aboard) if aboard.len() == 2),
+            )
+            .await
+        {
+            BuildingEvent::DoorsClosed(el, ..) => el,
+            _ => unreachable!(),
+        };
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::AtFloor(el, 2))
+            .await;
+        harness.command(BuildingCommand::AbortTrip(el)).await;
+        let aborted_at = match harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::TripAborted(..)))
+            .await
+        {
+            BuildingEvent::TripAborted(aborted, floor) => {
+                assert_eq!(aborted, el);
+                floor
+            }
+            _ => unreachable!(),
+        };
+        assert!((2..8).contains(&aborted_at), "aborted at {}", aborted_at);
+        harness.wait_for_deliveries(2).await;
+        let (events, summary) = harness.halt().await;
+        // Both got off and called again from where the trip ended.
+        let calls_again = events
+            .iter()
+            .filter(|evt| **evt == BuildingEvent::CallButtonPressed(aborted_at, Direction::Up, 0))
+            .count();
+        assert!(calls_again >= 1);
+        assert!(events.contains(&BuildingEvent::PassengerDelivered(9)));
+        assert!(events.contains(&BuildingEvent::PassengerDelivered(8)));
+        assert!(summary.all_delivered());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn trips_up_and_down_take_their_own_floor_times() {
+        let building = Building::new(10, 1).with_floor_times(300, 200);
+        let mut harness = Harness::start(building, Default::default());
+        harness.arrive(0, 6).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::AtFloor(0, 1))
+            .await;
+        let start = time::Instant::now();
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::AtFloor(0, 6))
+            .await;
+        let up = start.elapsed();
+        harness.wait_for_deliveries(1).await;
+        harness.arrive(6, 0).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::AtFloor(0, 5))
+            .await;
+        let start = time::Instant::now();
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::AtFloor(0, 0))
+            .await;
+        let down = start.elapsed();
+        // Five floors each way.
+        assert_eq!(up, time::Duration::from_millis(5 * 300));
+        assert_eq!(down, time::Duration::from_millis(5 * 200));
+        harness.halt().await;
+    }
+
+    #[test]
+    fn elevators_close_behind_each_other_are_reported_bunched() {
+        let mut building = Building::new(20, 4).with_bunching_detection(2, 3);
+        // Three elevators going up a floor or two apart, and one going down among them.
+        for (el, position, destination) in [(0, 3, 15), (1, 5, 15), (2, 6, 18), (3, 4, 0)] {
+            building.elevators[el].position = position;
+            building.elevators[el].destination = Some(destination);
+        }
+        let mut events = Vec::new();
+        building.detect_bunching(&mut events);
+        assert_eq!(events, [BuildingEvent::Bunching(vec![0, 1, 2])]);
+        // Sent once while the group stays the same.
+        events.clear();
+        building.detect_bunching(&mut events);
+        assert!(events.is_empty());
+        // One pulling away breaks the bunch up.
+        building.elevators[2].position = 9;
+        building.detect_bunching(&mut events);
+        assert!(events.is_empty());
+        building.elevators[2].position = 7;
+        building.detect_bunching(&mut events);
+        assert_eq!(events, [BuildingEvent::Bunching(vec![0, 1, 2])]);
+    }
 }
@@ -0,0 +1,70 @@
+//! Compares controller configurations by running each of them on the same traffic.
+
+use crate::building::{Building, SimulationSummary};
+use crate::controller::ControllerConfig;
+use crate::driver;
+use crate::error::Error;
+use crate::traffic::Recorded;
+
+/// Run a new building from `make_building` under each of the controller `modes` in turn,
+/// with the passengers of `traffic` arriving at the same times in every run. Every run draws
+/// its random choices from `seed`, unless its mode has a seed of its own. Returns the summary
+/// of each run, in the order of `modes`.
+pub async fn compare_modes(
+    make_building: impl Fn() -> Building,
+    traffic: &Recorded,
+    modes: &[ControllerConfig],
+    seed: u64,
+) -> Result<Vec<SimulationSummary>, Error> {
+    let mut summaries = Vec::with_capacity(modes.len());
+    for config in modes {
+        let summary = driver::run(
+            make_building(),
+            traffic.len(),
+            traffic.clone(),
+            config.clone(),
+            seed,
+        )
+        .await?;
+        summaries.push(summary);
+    }
+    Ok(summaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::strategy::{NearestCar, Scan};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn every_mode_delivers_the_same_passengers() {
+        let secs = Duration::from_secs;
+        let traffic = Recorded::new(vec![
+            (0, 7, secs(0)),
+            (5, 1, secs(1)),
+            (9, 0, secs(1)),
+            (2, 6, secs(3)),
+            (8, 3, secs(6)),
+        ]);
+        let modes = [
+            ControllerConfig {
+                strategy: Some(Arc::new(NearestCar)),
+                ..Default::default()
+            },
+            ControllerConfig {
+                strategy: Some(Arc::new(Scan)),
+                ..Default::default()
+            },
+        ];
+        let summaries = compare_modes(|| Building::new(10, 2), &traffic, &modes, 144)
+            .await
+            .unwrap();
+        assert_eq!(summaries.len(), 2);
+        for summary in summaries {
+            assert_eq!(summary.delivered, traffic.len());
+            assert!(summary.all_delivered());
+        }
+    }
+}
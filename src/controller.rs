@@ -2,23 +2,439 @@
 //! get to their destinations.
 
 use std::{
-    collections::{BTreeSet, HashSet},
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
     ops::Range,
+    sync::Arc,
+    time::Duration,
 };
 
-use crate::building::{BuildingCommand, BuildingEvent, Direction, ElevatorId, FloorId};
-use std::sync::Arc;
-use tokio::sync::{broadcast, mpsc};
-#[derive(Default, Clone)]
-struct ElevatorButtonsInfo {
+use crate::building::{
+    BuildingCommand, BuildingEvent, Direction, ElevatorConfig, ElevatorId, FloorId, FloorLabels,
+    StopPattern,
+};
+use crate::error::Error;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::{self, Instant};
+use tracing::{info, warn};
+
+pub mod strategy;
+#[cfg(feature = "tui")]
+mod tui;
+
+use strategy::{Comfort, DispatchStrategy, Predictive};
+
+/// ControllerConfig tunes how the controller dispatches elevators.
+#[derive(Debug, Default, Clone)]
+pub struct ControllerConfig {
+    /// Maximum number of elevators that may head to the same floor for hall calls at once.
+    /// Further calls at that floor wait until one of those elevators arrives, which keeps
+    /// idle elevators from all bunching at the lobby. `None` means no limit.
+    pub max_cars_per_floor: Option<usize>,
+    /// Where idle elevators wait for their next call. `None` leaves them where they stopped,
+    /// unless `idle_gravity` is set. Elevators with a `rest_floor` of their own return there
+    /// instead.
+    pub parking: Option<ParkingPolicy>,
+    /// Idle elevators with nowhere to park drift down toward the lobby, each time they have
+    /// waited this long going halfway to the lowest floor they stop at, so that random traffic
+    /// doesn't leave them all high up in the building. A call arriving in the meantime
+    /// cancels the move. `None` leaves them where they stopped.
+    pub idle_gravity: Option<Duration>,
+    /// Idle elevators wait a random time up to this long before moving to park, so that
+    /// they don't all move at once. A call arriving in the meantime cancels the move.
+    pub park_stagger: Duration,
+    /// How long an elevator that has no stops left the way it is going waits for calls that
+    /// way before turning around, so that it doesn't turn around just as one comes in. It
+    /// still turns around at once for passengers aboard. Zero turns around at once.
+    pub reversal_grace: Duration,
+    /// Seed for the controller's random choices, such as `park_stagger` delays, so that a run
+    /// can be reproduced. `None` makes different choices every run.
+    pub seed: Option<u64>,
+    /// Queue each call that no elevator can take yet with one of the elevators that may
+    /// answer it, in weighted round robin: the elevator with the fewest calls queued for its
+    /// load, so that the busiest elevators get the fewest. Each elevator answers the calls
+    /// queued for it as soon as it is free, and no other elevator answers them. Otherwise such
+    /// calls wait for whichever elevator can take them first, often the first to be free.
+    pub queue_unmatched: bool,
+    /// Power down idle elevators while there is little to do, keeping at least this many
+    /// elevators in service and free to answer the next call, and power them up again when
+    /// calls are waiting and no elevator in service is free to answer them. Elevators out of
+    /// service for maintenance don't count. `None` keeps every elevator powered.
+    pub min_active_cars: Option<usize>,
+    /// Switch to another policy during the night of every simulated day, for example to park
+    /// at the lobby and keep fewer elevators in service. `None` keeps the same policy all day.
+    pub night: Option<NightPolicy>,
+    /// Keep an idle elevator waiting at the lobby whenever one is free, ready for the next
+    /// arrival. Most calls come from the lobby during the morning up-peak.
+    pub up_peak_bias: bool,
+    /// Log every command sent to the building, along with the event that caused it and the
+    /// state of the elevator at the time. Useful to find out why an elevator moved.
+    pub log_commands: bool,
+    /// Which elevator answers a call when several are equally close.
+    pub tie_break: TieBreak,
+    /// Furthest an idle elevator is sent for a hall call, in floors, if a busy elevator closer
+    /// to the call can take it instead. The busy elevator may arrive later, but the idle one
+    /// is spared a long trip. `None` means no limit.
+    pub max_idle_divert: Option<usize>,
+    /// Whether an elevator at speed stops for a call at the next floor.
+    pub adjacent_stops: AdjacentStops,
+    /// Which elevators may stop for a hall call going the other way on their way past it.
+    pub opposite_calls: OppositeCalls,
+    /// Which elevator answers each call. `None` uses the `Predictive` strategy. `NearestCar`
+    /// looks its matches up in an `ElevatorIndex` when there are many elevators and ties go
+    /// to the lowest id.
+    pub strategy: Option<Arc<dyn DispatchStrategy>>,
+    /// How much the controller favours short waits over saving energy, from 1, where the
+    /// `strategy` alone picks the elevator, down to 0, where the elevator that travels the
+    /// fewest extra floors answers each call. Favouring energy batches calls onto elevators
+    /// already heading their way and makes elevators turn around less, so passengers wait
+    /// longer. `None` is the same as 1.
+    pub comfort_factor: Option<f64>,
+    /// How long before the time of a `ControllerQuery::Preposition` hint an idle elevator
+    /// should be at the floor. It sets off early enough to get there by then, going by the
+    /// floors it has to travel.
+    pub preposition_lead: Duration,
+    /// How long the controller takes to react: every command reaches the building this long
+    /// after the controller sends it, in the order they were sent, while the controller goes
+    /// on handling events. Zero sends commands at once.
+    pub latency: Duration,
+    /// Resynchronize with the building when an elevator that was sent somewhere has neither
+    /// reached a floor nor opened its doors for this long, for example because the command
+    /// never reached it. `None` trusts every command to arrive.
+    pub progress_timeout: Option<Duration>,
+    /// Handle every event already waiting before assigning calls, rather than assigning
+    /// them again after each event. Saves work when many events arrive at once.
+    pub batch_events: bool,
+    /// Names of the floors, shown in the shaft diagram. `None` leaves the diagram without
+    /// floor names.
+    pub floor_labels: Option<FloorLabels>,
+    /// Also send the shaft diagram here every time it changes, for example to stream it to
+    /// terminals with `dashboard::serve`.
+    pub dashboard: Option<broadcast::Sender<String>>,
+}
+
+/// ControllerQuery asks the controller about its plans, for example to show them on hall
+/// displays, or tells it what to plan for.
+#[derive(Debug)]
+pub enum ControllerQuery {
+    /// Reply with the elevator assigned to the call at the given floor going in the given
+    /// direction, or `None` if no elevator is on its way yet.
+    Assignment(FloorId, Direction, oneshot::Sender<Option<ElevatorId>>),
+    /// Demand is expected at the given floor at the given time, for example when a meeting
+    /// ends. The closest idle elevator sets off in time to be there
+    /// `ControllerConfig::preposition_lead` before then, waits there, and remains free to
+    /// answer calls. If no elevator is idle when it is due to set off, nothing moves.
+    Preposition(FloorId, Instant),
+    /// A priority passenger, such as a patient or a guest of honour, waits at the first floor
+    /// to go to the second. The closest elevator with nobody aboard is locked to the call: it
+    /// hands its other calls over, picks the passenger up and takes them to their floor, and
+    /// answers no other call until it has. Replies with the locked elevator, or `None` if no
+    /// elevator is free, in which case the passenger's call is answered like any other.
+    PriorityCall(
+        PriorityCallId,
+        FloorId,
+        FloorId,
+        oneshot::Sender<Option<ElevatorId>>,
+    ),
+    /// Reply with the controller's view of the elevators and of the calls waiting for one, for
+    /// example to show what it intends next to what the building does.
+    State(oneshot::Sender<ControllerState>),
+}
+
+/// ControllerState is the controller's view of the building at one moment, see
+/// `ControllerQuery::State`.
+#[derive(Debug, Clone, Default)]
+pub struct ControllerState {
+    pub elevators: Vec<ElevatorButtonsInfo>,
+    /// Hall calls that no elevator has been sent to answer yet.
+    pub waiting_calls: HashSet<(FloorId, Direction)>,
+    /// Calls from passengers with reduced mobility that no accessible elevator has been sent
+    /// to answer yet.
+    pub accessible_calls: HashSet<(FloorId, Direction)>,
+}
+
+/// PriorityCallId identifies a priority call, see `ControllerQuery::PriorityCall`.
+pub type PriorityCallId = u64;
+
+/// PriorityLock keeps an elevator for a priority call, see `ControllerQuery::PriorityCall`.
+#[derive(Debug, Clone, Copy)]
+struct PriorityLock {
+    id: PriorityCallId,
+    pickup: FloorId,
+    destination: FloorId,
+    /// The elevator has opened its doors at `pickup`.
+    picked_up: bool,
+}
+
+impl PriorityLock {
+    /// The direction of the call.
+    fn direction(&self) -> Direction {
+        Direction::between(self.pickup, self.destination)
+    }
+}
+
+/// ParkingPolicy decides where idle elevators wait for their next call.
+#[derive(Debug, Clone)]
+pub enum ParkingPolicy {
+    /// Every idle elevator returns to the lobby.
+    AllLobby,
+    /// Idle elevators spread out evenly over the building, starting at the lobby.
+    Spread,
+    /// Idle elevators spread out in proportion to the weight of each floor, for example the
+    /// share of calls expected to come from it.
+    Weighted(Vec<f64>),
+}
+
+impl ParkingPolicy {
+    /// The floors that `elevator_count` elevators should park at, one for each elevator.
+    fn parking_floors(&self, floors_count: usize, elevator_count: usize) -> Vec<FloorId> {
+        match self {
+            ParkingPolicy::AllLobby => vec![0; elevator_count],
+            ParkingPolicy::Spread => (0..elevator_count)
+                .map(|i| i * floors_count / elevator_count)
+                .collect(),
+            ParkingPolicy::Weighted(weights) => {
+                let total: f64 = weights.iter().sum();
+                (0..elevator_count)
+                    .map(|i| {
+                        // Park each elevator in the middle of its share of the total weight.
+                        let share = (i as f64 + 0.5) / elevator_count as f64 * total;
+                        let mut cumulative = 0.0;
+                        weights
+                            .iter()
+                            .position(|weight| {
+                                cumulative += weight;
+                                cumulative >= share
+                            })
+                            .unwrap_or(weights.len().saturating_sub(1))
+                            .min(floors_count - 1)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Length of a simulated day.
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// NightPolicy replaces parts of the controller's configuration during the night, see
+/// `ControllerConfig::night`. The controller tells the time of day from the building's clock,
+/// see `BuildingEvent::Clock`, so it switches within a simulated minute of the times given.
+#[derive(Debug, Clone)]
+pub struct NightPolicy {
+    /// Time of day the night starts, since midnight.
+    pub starts_at: Duration,
+    /// Time of day the night ends, since midnight. The night spans midnight if this is
+    /// earlier than `starts_at`.
+    pub ends_at: Duration,
+    /// Time of day when the building starts.
+    pub start_time: Duration,
+    /// Used at night instead of `ControllerConfig::parking`.
+    pub parking: Option<ParkingPolicy>,
+    /// Used at night instead of `ControllerConfig::idle_gravity`.
+    pub idle_gravity: Option<Duration>,
+    /// Used at night instead of `ControllerConfig::min_active_cars`.
+    pub min_active_cars: Option<usize>,
+}
+
+impl NightPolicy {
+    /// Returns true if it is night once the building has run for `simulated` time.
+    fn is_night(&self, simulated: Duration) -> bool {
+        let now = Duration::from_nanos(
+            ((self.start_time + simulated).as_nanos() % DAY.as_nanos()) as u64,
+        );
+        if self.starts_at <= self.ends_at {
+            (self.starts_at..self.ends_at).contains(&now)
+        } else {
+            now >= self.starts_at || now < self.ends_at
+        }
+    }
+
+    /// Swap the night's policy with the one in `config`, so that each takes the other's place.
+    fn swap(&mut self, config: &mut ControllerConfig) {
+        std::mem::swap(&mut self.parking, &mut config.parking);
+        std::mem::swap(&mut self.idle_gravity, &mut config.idle_gravity);
+        std::mem::swap(&mut self.min_active_cars, &mut config.min_active_cars);
+    }
+}
+
+/// AdjacentStops decides whether an elevator at speed stops for a hall call at the floor right
+/// ahead of it. An elevator is at speed once it has passed a floor without stopping there,
+/// and stopping at the very next floor then means braking hard. Stops for the passengers
+/// aboard are always made, whatever the policy.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AdjacentStops {
+    /// Stop for every call that the elevator is best placed to answer.
+    #[default]
+    Always,
+    /// Leave a hall call at the floor right ahead of an elevator at speed waiting rather than
+    /// giving it to that elevator. By the time calls are assigned again the elevator has
+    /// passed the floor, so another elevator, or this one on its way back, answers the call.
+    /// Elevators starting from a stop still answer calls at the next floor.
+    Defer,
+}
+
+/// OppositeCalls decides which elevators may answer a hall call that they pass on their way,
+/// going the other way to the call. Such an elevator stops for the call on its way there and
+/// picks the passenger up once it turns around.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OppositeCalls {
+    /// Any elevator may answer the call.
+    #[default]
+    Any,
+    /// Only an elevator with no passengers aboard may answer the call. The passengers aboard
+    /// an elevator are never made to stop at a floor where nobody gets in or out.
+    EmptyOnly,
+}
+
+/// TieBreak decides which elevator answers a call when several could pick the passenger up
+/// equally soon. Every policy is deterministic.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TieBreak {
+    /// The elevator with the lowest id. This favours the first elevators.
+    LowestId,
+    /// The elevator with the highest id.
+    HighestId,
+    /// The least utilized elevator: the one with the fewest passengers aboard, then the fewest
+    /// stops to make, then the lowest id.
+    Balanced,
+    /// The elevator that has been sent out the fewest times, then the lowest id. Spreads the
+    /// work over elevators waiting together, for example at the lobby.
+    #[default]
+    FewestTrips,
+}
+
+impl TieBreak {
+    /// Key ranking elevators that are tied, lowest first.
+    fn key(self, id: ElevatorId, elevator: &ElevatorButtonsInfo) -> (usize, usize, usize) {
+        match self {
+            TieBreak::LowestId => (0, 0, id),
+            TieBreak::HighestId => (0, 0, usize::MAX - id),
+            TieBreak::Balanced => (elevator.passenger_count, elevator.should_visit.len(), id),
+            TieBreak::FewestTrips => (elevator.trips, 0, id),
+        }
+    }
+}
+
+/// ElevatorButtonsInfo is the controller's view of a single elevator.
+#[derive(Debug, Default, Clone)]
+pub struct ElevatorButtonsInfo {
     position: FloorId,
     passenger_count: usize,
     should_visit: BTreeSet<FloorId>,
+    /// Destinations of the passengers aboard, with the number of passengers going to each.
+    /// These are also in `should_visit`, which holds hall calls as well.
+    car_calls: BTreeMap<FloorId, usize>,
     direction: Option<Direction>,
+    stop_pattern: StopPattern,
+    accessible: bool,
+    /// See `ElevatorConfig::rest_floor`.
+    rest_floor: Option<FloorId>,
+    /// See `ElevatorConfig::floor_time`.
+    floor_time: Duration,
+    /// See `ElevatorConfig::forbidden_floors`.
+    forbidden_floors: BTreeSet<FloorId>,
+    /// Hall calls this elevator is heading to answer.
+    hall_calls: HashSet<(FloorId, Direction)>,
+    /// Direction the elevator's lantern was last set to, see `BuildingCommand::SetDirection`.
+    announced: Option<Direction>,
+    /// Floor this elevator was sent to park at when it last became idle.
+    parking_at: Option<FloorId>,
+    /// When the elevator sets off for `parking_at`, if it is still waiting to.
+    park_after: Option<Instant>,
+    /// Hall calls where a waiting passenger refused to board this elevator because it does
+    /// not stop at their destination.
+    declined: HashSet<(FloorId, Direction)>,
+    /// Floor the elevator is going to nonstop, see `BuildingCommand::ExpressToLobby`.
+    express_to: Option<FloorId>,
+    /// True if the elevator is out of service for maintenance.
+    out_of_service: bool,
+    /// True if the elevator is powered down, see `ControllerConfig::min_active_cars`.
+    standby: bool,
+    /// Calls to answer once the elevator is free, see `ControllerConfig::queue_unmatched`.
+    queued_calls: VecDeque<(FloorId, Direction)>,
+    /// The elevator became idle with its doors open, so the passengers boarding now decide
+    /// which way it goes first.
+    picking_up: bool,
+    /// Number of times the elevator was sent out while idle, to answer calls or to park,
+    /// since the controller started.
+    trips: usize,
+    /// The elevator was sent somewhere since it last stopped with nowhere to go, so sending it
+    /// on is part of the same trip.
+    sent_out: bool,
+    /// The elevator passed its last floor without stopping, see `AdjacentStops`.
+    at_speed: bool,
+    /// Draws the elevator's `park_stagger` delays if `ControllerConfig::seed` is set.
+    stagger_rng: Option<StdRng>,
+    /// The priority call this elevator is locked to, if any.
+    priority: Option<PriorityLock>,
+    /// Passengers who have boarded the elevator and not pressed their floor button yet.
+    awaiting_press: usize,
+    /// When the elevator turns around, if it is waiting for calls the way it was going
+    /// first. See `ControllerConfig::reversal_grace`.
+    reverse_after: Option<Instant>,
+    /// When the elevator was first sent somewhere since it last reached a floor or opened its
+    /// doors. See `ControllerConfig::progress_timeout`.
+    commanded_at: Option<Instant>,
 }
 
 impl ElevatorButtonsInfo {
-    fn is_idle(&self) -> bool {
+    /// Create the state of an elevator at `position`, travelling in `direction` and due to
+    /// stop at every floor in `should_visit`.
+    pub fn new(
+        position: FloorId,
+        direction: Option<Direction>,
+        should_visit: BTreeSet<FloorId>,
+    ) -> Self {
+        Self {
+            position,
+            direction,
+            should_visit,
+            ..Default::default()
+        }
+    }
+
+    pub fn position(&self) -> FloorId {
+        self.position
+    }
+
+    pub fn direction(&self) -> Option<Direction> {
+        self.direction
+    }
+
+    /// The floors the elevator is due to stop at, for passengers aboard or hall calls.
+    pub fn should_visit(&self) -> &BTreeSet<FloorId> {
+        &self.should_visit
+    }
+
+    pub fn passenger_count(&self) -> usize {
+        self.passenger_count
+    }
+
+    /// The hall calls the elevator is heading to answer.
+    pub fn hall_calls(&self) -> &HashSet<(FloorId, Direction)> {
+        &self.hall_calls
+    }
+
+    /// Returns true if the elevator may stop at `floor`: its stop pattern serves it and it
+    /// isn't forbidden.
+    fn stops_at(&self, floor: FloorId) -> bool {
+        self.stop_pattern.serves(floor) && !self.forbidden_floors.contains(&floor)
+    }
+
+    /// The floor closest to `floor` where the elevator may stop, if any.
+    fn nearest_stop(&self, floor: FloorId, floors_count: usize) -> Option<FloorId> {
+        (0..floors_count)
+            .filter(|&fl| self.stops_at(fl))
+            .min_by_key(|fl| fl.abs_diff(floor))
+    }
+
+    /// Returns true if the elevator has nowhere to go.
+    pub fn is_idle(&self) -> bool {
         self.should_visit.is_empty()
     }
 
@@ -34,14 +450,74 @@ impl ElevatorButtonsInfo {
 
     fn next_step(&self) -> Option<FloorId> {
         let direction = self.direction?;
+        let stops = |&floor: &FloorId| !self.passes_by(floor, direction);
         match direction {
-            Direction::Up => self.should_visit.range(self.position..).next().copied(),
+            Direction::Up => self
+                .should_visit
+                .range(self.position..)
+                .copied()
+                .find(stops),
             Direction::Down => self
                 .should_visit
                 .range(0..=self.position)
                 .rev()
-                .next()
-                .copied(),
+                .copied()
+                .find(stops),
+        }
+    }
+
+    /// Returns true if the elevator has floors to visit beyond `floor` going in `direction`.
+    fn has_stops_beyond(&self, floor: FloorId, direction: Direction) -> bool {
+        match direction {
+            Direction::Up => self.should_visit.range(floor + 1..).next().is_some(),
+            Direction::Down => self.should_visit.range(..floor).next().is_some(),
+        }
+    }
+
+    /// Returns true if the elevator going in `direction` only has to stop at `floor` for a
+    /// call the other way, and has stops beyond it. It answers the call on its way back.
+    fn passes_by(&self, floor: FloorId, direction: Direction) -> bool {
+        self.hall_calls.contains(&(floor, direction.opposite()))
+            && !self.hall_calls.contains(&(floor, direction))
+            && !self.car_calls.contains_key(&floor)
+            && self.express_to != Some(floor)
+            && self.has_stops_beyond(floor, direction)
+    }
+
+    /// The direction to light the lantern in when the elevator stops at `floor`, see
+    /// `BuildingCommand::SetDirection`. An elevator with stops beyond the floor goes on the
+    /// way it is going, and one that turns around there takes the call it is answering. If it
+    /// answers calls both ways, or none, everyone boards.
+    fn lantern(&self, floor: FloorId) -> Option<Direction> {
+        if let Some(direction) = self.direction {
+            if self.has_stops_beyond(floor, direction) {
+                return Some(direction);
+            }
+        }
+        let up = self.hall_calls.contains(&(floor, Direction::Up));
+        let down = self.hall_calls.contains(&(floor, Direction::Down));
+        match (up, down) {
+            (true, false) => Some(Direction::Up),
+            (false, true) => Some(Direction::Down),
+            _ => None,
+        }
+    }
+
+    /// The floor the elevator reaches next if it keeps going, if it is moving.
+    fn next_floor(&self) -> Option<FloorId> {
+        match self.direction.filter(|_| !self.is_idle())? {
+            Direction::Up => Some(self.position + 1),
+            Direction::Down => self.position.checked_sub(1),
+        }
+    }
+
+    /// Returns true if the elevator is moving the other way to `direction` and has yet to
+    /// pass `floor`.
+    fn passes_against(&self, floor: FloorId, direction: Direction) -> bool {
+        match self.direction.filter(|_| !self.is_idle()) {
+            Some(Direction::Up) => direction == Direction::Down && floor > self.position,
+            Some(Direction::Down) => direction == Direction::Up && floor < self.position,
+            None => false,
         }
     }
 
@@ -49,36 +525,340 @@ impl ElevatorButtonsInfo {
         (self.position as i32 - floor as i32).abs()
     }
 
-    fn swap_direction(&mut self) {
-        self.direction = self.direction.map(|dir| {
-            if dir == Direction::Up {
-                Direction::Down
-            } else {
-                Direction::Up
+    /// Returns true if a passenger aboard still has to be taken further in the current
+    /// direction.
+    fn has_car_calls_ahead(&self) -> bool {
+        match self.direction {
+            Some(Direction::Up) => self.car_calls.range(self.position + 1..).next().is_some(),
+            Some(Direction::Down) => self.car_calls.range(..self.position).next().is_some(),
+            None => false,
+        }
+    }
+
+    /// The direction with more pending work, counting both the floors this elevator should
+    /// visit and the `waiting` hall calls. `None` if there is as much work either way.
+    fn busiest_direction(&self, waiting: &HashSet<(FloorId, Direction)>) -> Option<Direction> {
+        let above = self.should_visit.range(self.position + 1..).count()
+            + waiting
+                .iter()
+                .filter(|&&(floor, _)| floor > self.position)
+                .count();
+        let below = self.should_visit.range(..self.position).count()
+            + waiting
+                .iter()
+                .filter(|&&(floor, _)| floor < self.position)
+                .count();
+        match above.cmp(&below) {
+            Ordering::Greater => Some(Direction::Up),
+            Ordering::Less => Some(Direction::Down),
+            Ordering::Equal => None,
+        }
+    }
+
+    /// Give up the hall calls this elevator was heading to, so that other elevators answer
+    /// them.
+    fn hand_over_hall_calls(
+        &mut self,
+        call_button_pressed_by_floor: &mut HashSet<(FloorId, Direction)>,
+        accessible_calls: &mut HashSet<(FloorId, Direction)>,
+    ) {
+        for call in self.hall_calls.drain() {
+            call_button_pressed_by_floor.insert(call);
+            if self.accessible {
+                accessible_calls.insert(call);
             }
-        });
+        }
+    }
+
+    /// Predict how many steps the elevator needs before it can pick up a passenger at `floor`
+    /// going in `direction`, following its stops for up to `PREDICTION_HORIZON` steps. Every
+    /// floor travelled and every stop made takes a step. `None` if it won't pass the floor
+    /// going that way within the horizon.
+    fn steps_until_pickup(&self, floor: FloorId, direction: Direction) -> Option<usize> {
+        if self.is_idle() {
+            return Some(self.position.abs_diff(floor));
+        }
+        let mut direction_now = self.direction?;
+        let mut position = self.position;
+        let mut stops = self.should_visit.clone();
+        let mut steps = 0;
+        while steps <= PREDICTION_HORIZON {
+            if stops.remove(&position) {
+                steps += 1;
+            }
+            // Once it has made all its stops, the elevator comes straight over.
+            if stops.is_empty() {
+                return Some(steps + position.abs_diff(floor));
+            }
+            let stops_ahead = match direction_now {
+                Direction::Up => stops.range(position + 1..).next().is_some(),
+                Direction::Down => stops.range(..position).next().is_some(),
+            };
+            if !stops_ahead {
+                direction_now = direction_now.opposite();
+            }
+            if position == floor && direction_now == direction {
+                return Some(steps);
+            }
+            match direction_now {
+                Direction::Up => position += 1,
+                Direction::Down => position -= 1,
+            }
+            steps += 1;
+        }
+        None
+    }
+
+    fn swap_direction(&mut self) -> Result<(), Error> {
+        // Never carry passengers away from their destination.
+        if self.has_car_calls_ahead() {
+            return Ok(());
+        }
+        self.direction = self.direction.map(Direction::opposite);
         if self.direction.is_none() {
-            let first = *self.should_visit.first().unwrap();
+            let first = *self.should_visit.first().ok_or(Error::EmptyShouldVisit)?;
             self.direction = if self.position < first {
                 Some(Direction::Up)
             } else {
                 Some(Direction::Down)
             };
         }
+        Ok(())
+    }
+}
+
+/// Number of steps ahead that `find_best_elevator_match` predicts where moving elevators
+/// will be.
+pub const PREDICTION_HORIZON: usize = 20;
+
+/// Number of elevators above which `process_waiting_list` looks up the matches of the
+/// `NearestCar` strategy in an `ElevatorIndex` instead of scanning every elevator for every
+/// call.
+pub const INDEXED_MATCH_THRESHOLD: usize = 32;
+
+/// Returns true if the elevator may be sent to answer the call at all.
+fn can_answer(elevator: &ElevatorButtonsInfo, floor: FloorId, direction: Direction) -> bool {
+    elevator.stops_at(floor)
+        && !elevator.declined.contains(&(floor, direction))
+        && elevator.express_to.is_none()
+        && !elevator.out_of_service
+        && !elevator.standby
+        && elevator.priority.is_none()
+}
+
+/// Where an elevator is stored in an `ElevatorIndex`.
+#[derive(Clone, Copy)]
+enum IndexKey {
+    Idle(FloorId),
+    Moving(Direction, FloorId),
+    /// The elevator has stops but no trip, so it can't answer calls.
+    Unavailable,
+}
+
+/// ElevatorIndex sorts elevators by position so that a match for a call can be found without
+/// scanning every elevator. It gives the same answers as `find_nearest_elevator_on_route`.
+#[derive(Default)]
+pub struct ElevatorIndex {
+    /// Idle elevators by position.
+    idle: BTreeMap<FloorId, BTreeSet<ElevatorId>>,
+    /// Busy elevators by the direction and start of their current trip, mapping each elevator
+    /// to the end of that trip.
+    moving: HashMap<Direction, BTreeMap<FloorId, BTreeMap<ElevatorId, FloorId>>>,
+    keys: Vec<IndexKey>,
+}
+
+impl ElevatorIndex {
+    pub fn new(should_visit_by_elevator: &[ElevatorButtonsInfo]) -> Self {
+        let mut index = Self {
+            keys: vec![IndexKey::Unavailable; should_visit_by_elevator.len()],
+            ..Default::default()
+        };
+        for (id, elevator) in should_visit_by_elevator.iter().enumerate() {
+            index.insert(id, elevator);
+        }
+        index
+    }
+
+    fn insert(&mut self, id: ElevatorId, elevator: &ElevatorButtonsInfo) {
+        self.keys[id] = if elevator.is_idle() {
+            self.idle.entry(elevator.position).or_default().insert(id);
+            IndexKey::Idle(elevator.position)
+        } else if let (Some(trip), Some(direction)) = (elevator.current_trip(), elevator.direction)
+        {
+            self.moving
+                .entry(direction)
+                .or_default()
+                .entry(trip.start)
+                .or_default()
+                .insert(id, trip.end);
+            IndexKey::Moving(direction, trip.start)
+        } else {
+            IndexKey::Unavailable
+        };
+    }
+
+    fn remove(&mut self, id: ElevatorId) {
+        match self.keys[id] {
+            IndexKey::Idle(position) => {
+                let ids = self.idle.get_mut(&position).unwrap();
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.idle.remove(&position);
+                }
+            }
+            IndexKey::Moving(direction, start) => {
+                let trips = self.moving.get_mut(&direction).unwrap();
+                let ids = trips.get_mut(&start).unwrap();
+                ids.remove(&id);
+                if ids.is_empty() {
+                    trips.remove(&start);
+                }
+            }
+            IndexKey::Unavailable => {}
+        }
+        self.keys[id] = IndexKey::Unavailable;
+    }
+
+    /// Re-index an elevator after its state changed.
+    pub fn update(&mut self, id: ElevatorId, elevator: &ElevatorButtonsInfo) {
+        self.remove(id);
+        self.insert(id, elevator);
+    }
+
+    /// Find the same elevator as `find_nearest_elevator_on_route` would.
+    pub fn find_best_elevator_match(
+        &self,
+        floor: FloorId,
+        direction: Direction,
+        should_visit_by_elevator: &[ElevatorButtonsInfo],
+    ) -> Option<ElevatorId> {
+        let can_answer =
+            |id: ElevatorId| can_answer(&should_visit_by_elevator[id], floor, direction);
+        let idle = self.nearest_idle(floor, can_answer);
+        let moving = self.nearest_moving(floor, direction, can_answer);
+        // Ties go to the lowest id, as in the linear scan.
+        idle.into_iter().chain(moving).min().map(|(_, id)| id)
+    }
+
+    fn nearest_idle(
+        &self,
+        floor: FloorId,
+        can_answer: impl Fn(ElevatorId) -> bool,
+    ) -> Option<(usize, ElevatorId)> {
+        let mut below = self
+            .idle
+            .range(..=floor)
+            .rev()
+            .map(|(&position, ids)| (floor - position, ids))
+            .peekable();
+        let mut above = self
+            .idle
+            .range(floor + 1..)
+            .map(|(&position, ids)| (position - floor, ids))
+            .peekable();
+        let mut best: Option<(usize, ElevatorId)> = None;
+        loop {
+            let next = match (below.peek(), above.peek()) {
+                (Some(b), Some(a)) if b.0 <= a.0 => below.next(),
+                (Some(_), Some(_)) => above.next(),
+                (Some(_), None) => below.next(),
+                (None, _) => above.next(),
+            };
+            let Some((distance, ids)) = next else {
+                break;
+            };
+            // Keep going while elevators on the other side are just as close.
+            if best.is_some_and(|(best_distance, _)| distance > best_distance) {
+                break;
+            }
+            if let Some(id) = ids.iter().copied().find(|&id| can_answer(id)) {
+                let candidate = (distance, id);
+                best = Some(best.map_or(candidate, |best| best.min(candidate)));
+            }
+        }
+        best
+    }
+
+    fn nearest_moving(
+        &self,
+        floor: FloorId,
+        direction: Direction,
+        can_answer: impl Fn(ElevatorId) -> bool,
+    ) -> Option<(usize, ElevatorId)> {
+        // A trip only covers the floor if it starts at or below it, so the closest elevator
+        // is the one whose trip starts highest.
+        self.moving
+            .get(&direction)?
+            .range(..=floor)
+            .rev()
+            .find_map(|(&start, ids)| {
+                ids.iter()
+                    .find(|&(&id, &end)| floor < end && can_answer(id))
+                    .map(|(&id, _)| (floor - start, id))
+            })
     }
 }
 
-fn find_best_elevator_match(
+/// Find the elevator that should answer a call on `floor` going in `direction`: the one that
+/// can pick the passenger up soonest, predicting where moving elevators will be over the next
+/// `PREDICTION_HORIZON` steps. Ties are broken by `tie_break`.
+pub fn find_best_elevator_match(
+    floor: FloorId,
+    direction: Direction,
+    should_visit_by_elevator: &[ElevatorButtonsInfo],
+    tie_break: TieBreak,
+) -> Option<ElevatorId> {
+    find_best_elevator_match_where(
+        floor,
+        direction,
+        should_visit_by_elevator,
+        &Predictive,
+        tie_break,
+        |_| true,
+    )
+}
+
+/// Like `find_best_elevator_match`, but rates elevators with `strategy` and only considers
+/// the elevators accepted by `filter`.
+fn find_best_elevator_match_where(
+    floor: FloorId,
+    direction: Direction,
+    should_visit_by_elevator: &[ElevatorButtonsInfo],
+    strategy: &dyn DispatchStrategy,
+    tie_break: TieBreak,
+    filter: impl Fn(&ElevatorButtonsInfo) -> bool,
+) -> Option<ElevatorId> {
+    should_visit_by_elevator
+        .iter()
+        .enumerate()
+        .filter(|(_, elevator)| filter(elevator) && can_answer(elevator, floor, direction))
+        .filter_map(|(id, elevator)| {
+            let cost = strategy.cost(elevator, floor, direction)?;
+            Some(((cost, tie_break.key(id, elevator)), id))
+        })
+        .min()
+        .map(|(_, id)| id)
+}
+
+/// Find the closest elevator that is either idle or already passing `floor` in `direction` on
+/// its current trip. This is cheaper than `find_best_elevator_match` but doesn't foresee
+/// elevators turning around.
+pub fn find_nearest_elevator_on_route(
     floor: FloorId,
     direction: Direction,
     should_visit_by_elevator: &[ElevatorButtonsInfo],
 ) -> Option<ElevatorId> {
-    let mut lowest_distance = std::i32::MAX;
+    let mut lowest_distance = i32::MAX;
     let mut result = None;
     for (id, elevator) in should_visit_by_elevator.iter().enumerate() {
+        if !can_answer(elevator, floor, direction) {
+            continue;
+        }
         if elevator.is_idle()
-            || (elevator.current_trip().unwrap().contains(&floor)
-                && elevator.direction.unwrap() == direction)
+            || (elevator
+                .current_trip()
+                .is_some_and(|trip| trip.contains(&floor))
+                && elevator.direction == Some(direction))
         {
             let distance = elevator.distance_to(floor);
             if distance < lowest_distance {
@@ -90,46 +870,381 @@ fn find_best_elevator_match(
     result
 }
 
-async fn process_waiting_list(
+/// CommandSender sends the controller's commands to the building, logging them if
+/// `ControllerConfig::log_commands` is set.
+struct CommandSender<'a> {
+    tx: &'a mpsc::Sender<BuildingCommand>,
+    /// The event being handled, if any.
+    cause: Option<&'a BuildingEvent>,
+    log: bool,
+    /// Number of floors in the building, which no command may send an elevator beyond.
+    floors_count: usize,
+}
+
+impl CommandSender<'_> {
+    /// Send `elevator` to `floor`.
+    async fn go_to_floor(
+        &self,
+        elevator_id: ElevatorId,
+        elevator: &mut ElevatorButtonsInfo,
+        mut floor: FloorId,
+    ) -> Result<(), Error> {
+        // Only corrupted state sends an elevator out of the building.
+        if floor >= self.floors_count {
+            let top_floor = self.floors_count.saturating_sub(1);
+            warn!(
+                elevator = elevator_id,
+                floor,
+                floors = self.floors_count,
+                instead = top_floor,
+                "elevator sent beyond the top floor"
+            );
+            floor = top_floor;
+        }
+        if !elevator.sent_out {
+            elevator.sent_out = true;
+            elevator.trips += 1;
+        }
+        elevator.commanded_at.get_or_insert_with(Instant::now);
+        let announced = elevator.lantern(floor);
+        if announced != elevator.announced {
+            elevator.announced = announced;
+            self.tx
+                .send(BuildingCommand::SetDirection(elevator_id, announced))
+                .await?;
+        }
+        let cmd = BuildingCommand::GoToFloor(elevator_id, floor);
+        if self.log {
+            let cause = self
+                .cause
+                .map_or("no event".to_owned(), |evt| evt.to_string());
+            let direction = elevator
+                .direction
+                .map_or("none".to_owned(), |dir| dir.to_string());
+            info!(
+                elevator = elevator_id,
+                floor,
+                cause = %cause,
+                position = elevator.position,
+                direction = %direction,
+                passengers = elevator.passenger_count,
+                should_visit = ?elevator.should_visit,
+                "{}",
+                cmd
+            );
+        }
+        self.tx.send(cmd).await?;
+        Ok(())
+    }
+
+    /// Open the doors of `elevator` again at the floor it is stopped at, without sending it
+    /// anywhere.
+    async fn reopen_doors(
+        &self,
+        elevator_id: ElevatorId,
+        elevator: &ElevatorButtonsInfo,
+    ) -> Result<(), Error> {
+        self.tx
+            .send(BuildingCommand::GoToFloor(elevator_id, elevator.position))
+            .await?;
+        Ok(())
+    }
+
+    /// Send `elevator` to the next floor it should visit.
+    async fn next_step(
+        &self,
+        elevator_id: ElevatorId,
+        elevator: &mut ElevatorButtonsInfo,
+    ) -> Result<(), Error> {
+        let floor = elevator.next_step().ok_or(Error::NoNextStep(elevator_id))?;
+        self.go_to_floor(elevator_id, elevator, floor).await
+    }
+}
+
+/// The elevator heading to answer the call at `floor` going in `direction`, if any.
+pub fn assigned_elevator(
+    should_visit_by_elevator: &[ElevatorButtonsInfo],
+    floor: FloorId,
+    direction: Direction,
+) -> Option<ElevatorId> {
+    should_visit_by_elevator
+        .iter()
+        .position(|elevator| elevator.hall_calls.contains(&(floor, direction)))
+}
+
+/// Assign waiting calls to elevators, leaving only the calls that no elevator can take yet.
+pub async fn process_waiting_list(
+    floors_count: usize,
     should_visit_by_elevator: &mut [ElevatorButtonsInfo],
     call_button_pressed_by_floor: &mut HashSet<(FloorId, Direction)>,
     building_cmd_tx: &mpsc::Sender<BuildingCommand>,
-) {
+    config: &ControllerConfig,
+) -> Result<(), Error> {
+    let sender = CommandSender {
+        tx: building_cmd_tx,
+        cause: None,
+        log: config.log_commands,
+        floors_count,
+    };
+    assign_calls(
+        should_visit_by_elevator,
+        call_button_pressed_by_floor,
+        &sender,
+        config,
+        false,
+    )
+    .await
+}
+
+/// Assign waiting calls to elevators. If `accessible_only` is set, the calls are only
+/// assigned to accessible elevators.
+async fn assign_calls(
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    call_button_pressed_by_floor: &mut HashSet<(FloorId, Direction)>,
+    sender: &CommandSender<'_>,
+    config: &ControllerConfig,
+    accessible_only: bool,
+) -> Result<(), Error> {
     let mut waiters_to_remove = Vec::new();
+    // Elevators are sent once all the calls are assigned, so that an elevator given several
+    // calls at once gets a single command.
+    let mut to_send = BTreeSet::new();
+    // The index always breaks ties by lowest id and lets any elevator answer calls going the
+    // other way.
+    let mut index = (!accessible_only
+        && config
+            .strategy
+            .as_deref()
+            .is_some_and(|strategy| strategy.matches_nearest_on_route())
+        && config.tie_break == TieBreak::LowestId
+        && config.opposite_calls == OppositeCalls::Any
+        && config.comfort_factor.is_none()
+        && should_visit_by_elevator.len() > INDEXED_MATCH_THRESHOLD)
+        .then(|| ElevatorIndex::new(should_visit_by_elevator));
+    let strategy = config.strategy.as_deref().unwrap_or(&Predictive);
+    let comfort;
+    let strategy = match config.comfort_factor {
+        Some(comfort_factor) => {
+            comfort = Comfort {
+                strategy,
+                comfort_factor: comfort_factor.clamp(0.0, 1.0),
+            };
+            &comfort
+        }
+        None => strategy,
+    };
     for &(floor, direction) in &*call_button_pressed_by_floor {
-        if let Some(elevator_id) =
-            find_best_elevator_match(floor, direction, should_visit_by_elevator)
-        {
-            waiters_to_remove.push((floor, direction));
-
-            let elevator_info = should_visit_by_elevator.get_mut(elevator_id).unwrap();
-            // Don't stop the elevator suddenly at the current floor if it is moving.
+        let queued =
+            |elevator: &ElevatorButtonsInfo| elevator.queued_calls.contains(&(floor, direction));
+        if config.queue_unmatched && should_visit_by_elevator.iter().any(queued) {
+            continue;
+        }
+        if let Some(max_cars) = config.max_cars_per_floor {
+            let cars_heading_here = should_visit_by_elevator
+                .iter()
+                .filter(|elevator| elevator.hall_calls.iter().any(|&(at, _)| at == floor))
+                .count();
+            if cars_heading_here >= max_cars {
+                continue;
+            }
+        }
+        let may_answer = |elevator: &ElevatorButtonsInfo| {
+            (elevator.accessible || !accessible_only)
+                && (config.opposite_calls == OppositeCalls::Any
+                    || elevator.passenger_count == 0
+                    || !elevator.passes_against(floor, direction))
+        };
+        let best_match = match &index {
+            Some(index) => {
+                index.find_best_elevator_match(floor, direction, should_visit_by_elevator)
+            }
+            None => find_best_elevator_match_where(
+                floor,
+                direction,
+                should_visit_by_elevator,
+                strategy,
+                config.tie_break,
+                may_answer,
+            ),
+        };
+        let best_match = match (best_match, config.max_idle_divert) {
+            (Some(id), Some(max_divert))
+                if should_visit_by_elevator[id].is_idle()
+                    && should_visit_by_elevator[id].position.abs_diff(floor) > max_divert =>
+            {
+                let divert = should_visit_by_elevator[id].position.abs_diff(floor);
+                find_best_elevator_match_where(
+                    floor,
+                    direction,
+                    should_visit_by_elevator,
+                    strategy,
+                    config.tie_break,
+                    |elevator| {
+                        may_answer(elevator)
+                            && !elevator.is_idle()
+                            && elevator.position.abs_diff(floor) < divert
+                    },
+                )
+                .or(Some(id))
+            }
+            (best_match, _) => best_match,
+        };
+        if let Some(elevator_id) = best_match {
+            let elevator_info = &mut should_visit_by_elevator[elevator_id];
+            // Don't stop the elevator suddenly at the current floor if it is moving. Keep the
+            // call so that it is answered once the elevator is out of the way.
             if floor == elevator_info.position && !elevator_info.is_idle() {
                 continue;
             }
+            if config.adjacent_stops == AdjacentStops::Defer
+                && elevator_info.at_speed
+                && elevator_info.next_floor() == Some(floor)
+            {
+                continue;
+            }
+            waiters_to_remove.push((floor, direction));
+            elevator_info.parking_at = None;
+            elevator_info.park_after = None;
             elevator_info.should_visit.insert(floor);
-            if elevator_info.next_step().is_none() {
-                elevator_info.swap_direction();
+            elevator_info.hall_calls.insert((floor, direction));
+            // A call behind an elevator waiting to turn around is answered once it does.
+            let holding =
+                elevator_info.next_step().is_none() && elevator_info.reverse_after.is_some();
+            if elevator_info.next_step().is_none() && !holding {
+                elevator_info.swap_direction()?;
             }
-            building_cmd_tx
-                .send(BuildingCommand::GoToFloor(
-                    elevator_id,
-                    elevator_info.next_step().unwrap(),
-                ))
-                .await
-                .unwrap();
+            if let Some(index) = &mut index {
+                index.update(elevator_id, elevator_info);
+            }
+            if !holding {
+                elevator_info.reverse_after = None;
+                to_send.insert(elevator_id);
+            }
+        } else if config.queue_unmatched {
+            queue_call(floor, direction, should_visit_by_elevator, may_answer);
         }
     }
+    for elevator_id in to_send {
+        sender
+            .next_step(elevator_id, &mut should_visit_by_elevator[elevator_id])
+            .await?;
+    }
     for (floor, direction) in waiters_to_remove {
         call_button_pressed_by_floor.remove(&(floor, direction));
     }
+    Ok(())
+}
+
+/// Queue the call at `floor` going in `direction` with the elevator accepted by `filter` that
+/// should answer it once it is free. See `ControllerConfig::queue_unmatched`.
+fn queue_call(
+    floor: FloorId,
+    direction: Direction,
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    filter: impl Fn(&ElevatorButtonsInfo) -> bool,
+) {
+    let load =
+        |elevator: &ElevatorButtonsInfo| elevator.passenger_count + elevator.should_visit.len() + 1;
+    if let Some(elevator) = should_visit_by_elevator
+        .iter_mut()
+        .filter(|elevator| filter(elevator) && can_answer(elevator, floor, direction))
+        .min_by_key(|elevator| (elevator.queued_calls.len() + 1) * load(elevator))
+    {
+        elevator.queued_calls.push_back((floor, direction));
+    }
+}
+
+/// Send each free elevator to the calls queued for it that are still waiting. A free
+/// elevator with no calls queued takes over the oldest call it can answer from the longest
+/// queue of a busy elevator. See `ControllerConfig::queue_unmatched`.
+async fn answer_queued_calls(
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    call_button_pressed_by_floor: &mut HashSet<(FloorId, Direction)>,
+    accessible_calls: &mut HashSet<(FloorId, Direction)>,
+    sender: &CommandSender<'_>,
+) -> Result<(), Error> {
+    let free = |elevator: &ElevatorButtonsInfo| {
+        elevator.is_idle()
+            && elevator.express_to.is_none()
+            && !elevator.out_of_service
+            && !elevator.standby
+            && elevator.priority.is_none()
+    };
+    for id in 0..should_visit_by_elevator.len() {
+        if !free(&should_visit_by_elevator[id])
+            || !should_visit_by_elevator[id].queued_calls.is_empty()
+        {
+            continue;
+        }
+        let Some(busiest) = (0..should_visit_by_elevator.len())
+            .filter(|&other| !free(&should_visit_by_elevator[other]))
+            .max_by_key(|&other| should_visit_by_elevator[other].queued_calls.len())
+        else {
+            continue;
+        };
+        let taker = &should_visit_by_elevator[id];
+        let Some(i) = should_visit_by_elevator[busiest]
+            .queued_calls
+            .iter()
+            .position(|&(floor, direction)| can_answer(taker, floor, direction))
+        else {
+            continue;
+        };
+        if let Some(call) = should_visit_by_elevator[busiest].queued_calls.remove(i) {
+            should_visit_by_elevator[id].queued_calls.push_back(call);
+        }
+    }
+
+    for (id, elevator) in should_visit_by_elevator.iter_mut().enumerate() {
+        if !free(elevator) || elevator.queued_calls.is_empty() {
+            continue;
+        }
+        // Calls answered by others in the meantime are dropped.
+        for call in std::mem::take(&mut elevator.queued_calls) {
+            let mut waiting = call_button_pressed_by_floor.remove(&call);
+            if elevator.accessible {
+                waiting |= accessible_calls.remove(&call);
+            }
+            if waiting {
+                elevator.should_visit.insert(call.0);
+                elevator.hall_calls.insert(call);
+            }
+        }
+        if elevator.is_idle() {
+            continue;
+        }
+        elevator.parking_at = None;
+        elevator.park_after = None;
+        if elevator.next_step().is_none() {
+            elevator.swap_direction()?;
+        }
+        sender.next_step(id, elevator).await?;
+    }
+    Ok(())
+}
+
+/// When the first elevator that was sent somewhere should have reached a floor or opened its
+/// doors, at the latest, or `None` if no elevator is expected to or there is no `timeout`.
+fn progress_deadline(
+    should_visit_by_elevator: &[ElevatorButtonsInfo],
+    timeout: Option<Duration>,
+) -> Option<Instant> {
+    let timeout = timeout?;
+    should_visit_by_elevator
+        .iter()
+        .filter_map(|elevator| elevator.commanded_at)
+        .min()
+        .map(|commanded_at| commanded_at + timeout)
 }
 
-fn print_state(
+/// Draw the shaft diagram: one row per floor, top floor first, with the elevators' positions
+/// and the floors where a call is waiting.
+fn render_state(
     floors_count: usize,
     should_visit_by_elevator: &[ElevatorButtonsInfo],
     call_button_pressed_by_floor: &HashSet<(FloorId, Direction)>,
-) {
+    floor_labels: Option<&FloorLabels>,
+) -> String {
     let mut print_matrix: Vec<Vec<bool>> =
         vec![vec![false; should_visit_by_elevator.len()]; floors_count];
     let called_floors: HashSet<FloorId> = call_button_pressed_by_floor
@@ -140,7 +1255,7 @@ fn print_state(
         print_matrix[elevator.position][id] = true;
     }
 
-    let to_print = print_matrix
+    print_matrix
         .into_iter()
         .enumerate()
         .rev()
@@ -151,7 +1266,11 @@ fn print_state(
                 "|   |"
             }
             .to_owned();
-            button_press
+            let label = floor_labels
+                .map(|labels| format!("{:>4} ", labels.label(floor_level)))
+                .unwrap_or_default();
+            label
+                + &button_press
                 + &floor
                     .into_iter()
                     .map(|has_elevator| if has_elevator { " X " } else { "   " })
@@ -159,74 +1278,2522 @@ fn print_state(
                     .join("|")
         })
         .collect::<Vec<_>>()
-        .join("\n");
-    println!("{}", to_print);
+        .join("\n")
 }
 
-pub async fn controller(
-    elevator_count: usize,
+/// Send an idle elevator to its rest floor, or else to the closest parking floor that no other
+/// idle elevator has taken.
+async fn park(
+    elevator_id: ElevatorId,
     floors_count: usize,
-    mut events_rx: broadcast::Receiver<BuildingEvent>,
-    building_cmd_tx: mpsc::Sender<BuildingCommand>,
-) {
-    let mut should_visit_by_elevator: Vec<ElevatorButtonsInfo> =
-        vec![ElevatorButtonsInfo::default(); elevator_count];
-    let mut call_button_pressed_by_floor: HashSet<(FloorId, Direction)> = HashSet::new();
-
-    let sender = Arc::new(building_cmd_tx.clone());
-    let send_go_to_floor = |elevator_id: ElevatorId, to: FloorId| {
-        let sender = sender.clone();
-        async move {
-            sender
-                .send(BuildingCommand::GoToFloor(elevator_id, to))
-                .await
-                .unwrap();
-        }
-    };
-
-    while let Ok(evt) = events_rx.recv().await {
-        match evt {
-            BuildingEvent::CallButtonPressed(at, direction) => {
-                call_button_pressed_by_floor.insert((at, direction));
-            }
-            BuildingEvent::FloorButtonPressed(elevator_id, destination) => {
-                let elevator = should_visit_by_elevator.get_mut(elevator_id).unwrap();
-                elevator.should_visit.insert(destination);
-                elevator.passenger_count += 1;
-                let elevator = should_visit_by_elevator.get_mut(elevator_id).unwrap();
-                if elevator.next_step().is_none() {
-                    elevator.swap_direction();
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    sender: &CommandSender<'_>,
+    policy: Option<&ParkingPolicy>,
+    stagger: Duration,
+    gravity: Option<Duration>,
+) -> Result<(), Error> {
+    let free_floors = match (should_visit_by_elevator[elevator_id].rest_floor, policy) {
+        // The elevator's own floor, whoever else waits there.
+        (Some(rest_floor), _) => vec![rest_floor],
+        (None, Some(policy)) => {
+            // Elevators with rest floors don't take the policy's floors.
+            let parked_count = should_visit_by_elevator
+                .iter()
+                .filter(|elevator| elevator.rest_floor.is_none())
+                .count();
+            let mut free_floors = policy.parking_floors(floors_count, parked_count);
+            for (id, elevator) in should_visit_by_elevator.iter().enumerate() {
+                if id == elevator_id || !elevator.is_idle() || elevator.rest_floor.is_some() {
+                    continue;
                 }
-                send_go_to_floor(elevator_id, elevator.next_step().unwrap()).await;
+                if let Some(taken) = elevator.parking_at {
+                    if let Some(i) = free_floors.iter().position(|&floor| floor == taken) {
+                        free_floors.swap_remove(i);
+                    }
+                }
+            }
+            free_floors
+        }
+        (None, None) => {
+            let elevator = &mut should_visit_by_elevator[elevator_id];
+            let Some(gravity) = gravity else {
+                return Ok(());
+            };
+            // The elevator stops where it is, so it never drifts up.
+            let target = elevator
+                .nearest_stop(elevator.position / 2, floors_count)
+                .unwrap_or(elevator.position);
+            if target < elevator.position {
+                elevator.parking_at = Some(target);
+                elevator.park_after = Some(Instant::now() + gravity);
             }
-            BuildingEvent::AtFloor(elevator_id, floor) => {
-                let elevator = should_visit_by_elevator.get_mut(elevator_id).unwrap();
-                elevator.should_visit.remove(&floor);
-                elevator.position = floor;
+            return Ok(());
+        }
+    };
 
-                if elevator.next_step().is_none() && !elevator.is_idle() {
-                    elevator.swap_direction();
-                }
+    let elevator = &mut should_visit_by_elevator[elevator_id];
+    // The elevator may not stop at every floor it would park at.
+    let Some(target) = free_floors
+        .into_iter()
+        .filter(|&floor| elevator.stops_at(floor))
+        .min_by_key(|floor| floor.abs_diff(elevator.position))
+    else {
+        return Ok(());
+    };
+    elevator.parking_at = Some(target);
+    if target == elevator.position {
+        return Ok(());
+    }
+    if stagger.is_zero() {
+        sender.go_to_floor(elevator_id, elevator, target).await?;
+    } else {
+        let delay = match &mut elevator.stagger_rng {
+            Some(rng) => rng.gen_range(Duration::ZERO..=stagger),
+            None => rand::thread_rng().gen_range(Duration::ZERO..=stagger),
+        };
+        elevator.park_after = Some(Instant::now() + delay);
+    }
+    Ok(())
+}
 
-                let elevator = should_visit_by_elevator.get_mut(elevator_id).unwrap();
-                if !elevator.is_idle() {
-                    send_go_to_floor(elevator_id, elevator.next_step().unwrap()).await;
-                } else {
-                    elevator.direction = None;
-                }
+/// Send the idle elevators whose staggered move to park is due to their parking floors.
+async fn park_due(
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    sender: &CommandSender<'_>,
+) -> Result<(), Error> {
+    let now = Instant::now();
+    for (id, elevator) in should_visit_by_elevator.iter_mut().enumerate() {
+        if elevator.park_after.is_none_or(|at| at > now) {
+            continue;
+        }
+        elevator.park_after = None;
+        // The elevator may have been given something to do since.
+        match elevator.parking_at {
+            Some(target) if elevator.is_idle() => {
+                sender.go_to_floor(id, elevator, target).await?;
             }
             _ => {}
         }
+    }
+    Ok(())
+}
+
+/// Turn around the elevators whose `ControllerConfig::reversal_grace` is over, and send them
+/// on.
+async fn reverse_due(
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    sender: &CommandSender<'_>,
+) -> Result<(), Error> {
+    let now = Instant::now();
+    for (id, elevator) in should_visit_by_elevator.iter_mut().enumerate() {
+        if elevator.reverse_after.is_none_or(|at| at > now) {
+            continue;
+        }
+        elevator.reverse_after = None;
+        // No call came in the way the elevator was going.
+        if elevator.next_step().is_none() && !elevator.is_idle() {
+            elevator.swap_direction()?;
+            sender.next_step(id, elevator).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the elevator is free to wait at `floor` for a prepositioning hint.
+fn may_preposition(elevator: &ElevatorButtonsInfo, floor: FloorId) -> bool {
+    elevator.is_idle()
+        && elevator.express_to.is_none()
+        && !elevator.out_of_service
+        && !elevator.standby
+        && elevator.stops_at(floor)
+}
+
+/// The idle elevator closest to `floor` that may wait there for a prepositioning hint.
+fn closest_to_preposition(
+    floor: FloorId,
+    should_visit_by_elevator: &[ElevatorButtonsInfo],
+) -> Option<ElevatorId> {
+    should_visit_by_elevator
+        .iter()
+        .enumerate()
+        .filter(|(_, elevator)| may_preposition(elevator, floor))
+        .min_by_key(|(_, elevator)| elevator.position.abs_diff(floor))
+        .map(|(id, _)| id)
+}
+
+/// When the closest idle elevator has to set off to be at `floor` `lead` before `at`.
+fn preposition_departure(
+    (at, floor): (Instant, FloorId),
+    should_visit_by_elevator: &[ElevatorButtonsInfo],
+    lead: Duration,
+) -> Instant {
+    let travel =
+        closest_to_preposition(floor, should_visit_by_elevator).map_or(Duration::ZERO, |id| {
+            let elevator = &should_visit_by_elevator[id];
+            elevator.floor_time * elevator.position.abs_diff(floor) as u32
+        });
+    at.checked_sub(lead + travel).unwrap_or_else(Instant::now)
+}
+
+/// Send the closest idle elevator to each floor whose prepositioning hint is due, and forget
+/// those hints.
+async fn preposition_due(
+    hints: &mut Vec<(Instant, FloorId)>,
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    sender: &CommandSender<'_>,
+    lead: Duration,
+) -> Result<(), Error> {
+    let now = Instant::now();
+    let (due, later): (Vec<_>, Vec<_>) = hints
+        .drain(..)
+        .partition(|&hint| preposition_departure(hint, should_visit_by_elevator, lead) <= now);
+    *hints = later;
+    for (_, floor) in due {
+        let covered = should_visit_by_elevator.iter().any(|elevator| {
+            may_preposition(elevator, floor)
+                && elevator.parking_at.unwrap_or(elevator.position) == floor
+        });
+        if covered {
+            continue;
+        }
+        let Some(id) = closest_to_preposition(floor, should_visit_by_elevator) else {
+            continue;
+        };
+        let elevator = &mut should_visit_by_elevator[id];
+        elevator.parking_at = Some(floor);
+        elevator.park_after = None;
+        sender.go_to_floor(id, elevator, floor).await?;
+    }
+    Ok(())
+}
+
+/// Rebuild the controller's view of the elevators and of the waiting calls from a snapshot of
+/// the building, as the controller starts or after missing events, then assign the calls, send
+/// the elevators on again and park those left idle.
+async fn resync(
+    floors_count: usize,
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    call_button_pressed_by_floor: &mut HashSet<(FloorId, Direction)>,
+    accessible_calls: &mut HashSet<(FloorId, Direction)>,
+    building_cmd_tx: &mpsc::Sender<BuildingCommand>,
+    config: &ControllerConfig,
+) -> Result<(), Error> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    building_cmd_tx
+        .send(BuildingCommand::Snapshot(reply_tx))
+        .await?;
+    let snapshot = reply_rx.await.map_err(|_| Error::BuildingStopped)?;
+
+    call_button_pressed_by_floor.clear();
+    accessible_calls.clear();
+    for (floor, direction, reduced_mobility) in snapshot.calls {
+        if reduced_mobility {
+            accessible_calls.insert((floor, direction));
+        } else {
+            call_button_pressed_by_floor.insert((floor, direction));
+        }
+    }
+    for (elevator, car) in should_visit_by_elevator.iter_mut().zip(snapshot.elevators) {
+        elevator.position = car.position;
+        elevator.passenger_count = car.passenger_destinations.len();
+        elevator.car_calls.clear();
+        for destination in car.passenger_destinations {
+            *elevator.car_calls.entry(destination).or_default() += 1;
+        }
+        // Hall calls are assigned again from scratch.
+        elevator.hall_calls.clear();
+        elevator.queued_calls.clear();
+        elevator.declined.clear();
+        // The destinations of everyone aboard are known now, pressed or not.
+        elevator.awaiting_press = 0;
+        elevator.should_visit = elevator.car_calls.keys().copied().collect();
+        elevator.express_to = car.destination.filter(|_| car.express);
+        elevator.should_visit.extend(elevator.express_to);
+        elevator.out_of_service = car.out_of_service;
+        elevator.announced = car.announced;
+        elevator.parking_at = None;
+        elevator.park_after = None;
+        elevator.reverse_after = None;
+        elevator.commanded_at = None;
+        elevator.at_speed = false;
+        match elevator.priority {
+            Some(lock) if lock.picked_up => {
+                elevator.should_visit.insert(lock.destination);
+            }
+            Some(lock) => {
+                call_button_pressed_by_floor.remove(&(lock.pickup, lock.direction()));
+                elevator.should_visit.insert(lock.pickup);
+                elevator.hall_calls.insert((lock.pickup, lock.direction()));
+            }
+            None => {}
+        }
+        elevator.direction = car
+            .destination
+            .filter(|&destination| destination != car.position)
+            .map(|destination| Direction::between(car.position, destination));
+    }
+
+    let sender = CommandSender {
+        tx: building_cmd_tx,
+        cause: None,
+        log: config.log_commands,
+        floors_count,
+    };
+    for (id, elevator) in should_visit_by_elevator.iter_mut().enumerate() {
+        if elevator.is_idle() || elevator.express_to.is_some() {
+            continue;
+        }
+        if elevator.next_step().is_none() {
+            elevator.swap_direction()?;
+        }
+        sender.next_step(id, elevator).await?;
+    }
+    assign_calls(
+        should_visit_by_elevator,
+        accessible_calls,
+        &sender,
+        config,
+        true,
+    )
+    .await?;
+    assign_calls(
+        should_visit_by_elevator,
+        call_button_pressed_by_floor,
+        &sender,
+        config,
+        false,
+    )
+    .await?;
+    park_idle(floors_count, should_visit_by_elevator, &sender, config).await
+}
+
+/// Park every elevator in service with nowhere to go, as each would have once it stopped, for
+/// example as the controller starts with every elevator idle.
+async fn park_idle(
+    floors_count: usize,
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    sender: &CommandSender<'_>,
+    config: &ControllerConfig,
+) -> Result<(), Error> {
+    for id in 0..should_visit_by_elevator.len() {
+        let elevator = &should_visit_by_elevator[id];
+        if elevator.is_idle() && !elevator.out_of_service && !elevator.standby {
+            park(
+                id,
+                floors_count,
+                should_visit_by_elevator,
+                sender,
+                config.parking.as_ref(),
+                config.park_stagger,
+                config.idle_gravity,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Lock the closest elevator with nobody aboard to the priority call `lock`, see
+/// `ControllerQuery::PriorityCall`. Returns the elevator, or `None` if no elevator is free.
+async fn lock_for_priority_call(
+    lock: PriorityLock,
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    call_button_pressed_by_floor: &mut HashSet<(FloorId, Direction)>,
+    accessible_calls: &mut HashSet<(FloorId, Direction)>,
+    sender: &CommandSender<'_>,
+) -> Result<Option<ElevatorId>, Error> {
+    let call = (lock.pickup, lock.direction());
+    let Some(elevator_id) = should_visit_by_elevator
+        .iter()
+        .enumerate()
+        .filter(|(_, elevator)| {
+            elevator.passenger_count == 0
+                && can_answer(elevator, lock.pickup, lock.direction())
+                && elevator.stops_at(lock.destination)
+        })
+        .min_by_key(|&(id, elevator)| (elevator.position.abs_diff(lock.pickup), id))
+        .map(|(id, _)| id)
+    else {
+        return Ok(None);
+    };
+    let elevator = &mut should_visit_by_elevator[elevator_id];
+    elevator.hand_over_hall_calls(call_button_pressed_by_floor, accessible_calls);
+    call_button_pressed_by_floor.remove(&call);
+    // Nobody is aboard, so the elevator only had hall calls to answer.
+    elevator.should_visit = BTreeSet::from([lock.pickup]);
+    elevator.hall_calls.insert(call);
+    elevator.direction = Some(match lock.pickup.cmp(&elevator.position) {
+        Ordering::Greater => Direction::Up,
+        Ordering::Less => Direction::Down,
+        Ordering::Equal => lock.direction(),
+    });
+    elevator.parking_at = None;
+    elevator.park_after = None;
+    elevator.reverse_after = None;
+    elevator.picking_up = false;
+    elevator.priority = Some(lock);
+    info!(
+        elevator = elevator_id,
+        call = lock.id,
+        pickup = lock.pickup,
+        destination = lock.destination,
+        "elevator locked to priority call"
+    );
+    sender
+        .go_to_floor(elevator_id, elevator, lock.pickup)
+        .await?;
+    Ok(Some(elevator_id))
+}
+
+/// Free the elevator from its priority call, if it is locked to one, because it can't finish
+/// it. The priority passenger's call is answered like any other.
+fn release_priority_call(elevator_id: ElevatorId, elevator: &mut ElevatorButtonsInfo) {
+    if let Some(lock) = elevator.priority.take() {
+        warn!(
+            elevator = elevator_id,
+            call = lock.id,
+            "elevator released from priority call before delivering it"
+        );
+    }
+}
+
+/// Release the calls at `floor` that elevators other than `answered_by` are heading to answer,
+/// now that `answered_by` has opened its doors there with its lantern showing `announced`, and
+/// send those elevators on to their other stops. The up and down calls at a floor may be
+/// answered by different elevators, as only passengers going the way of the lantern board.
+async fn release_answered_calls(
+    answered_by: ElevatorId,
+    floor: FloorId,
+    announced: Option<Direction>,
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    sender: &CommandSender<'_>,
+) -> Result<(), Error> {
+    let accessible = should_visit_by_elevator[answered_by].accessible;
+    let answered = |&(at, direction): &(FloorId, Direction)| {
+        at == floor && announced.is_none_or(|lit| lit == direction)
+    };
+    for (id, elevator) in should_visit_by_elevator.iter_mut().enumerate() {
+        // Passengers with reduced mobility keep waiting for an accessible elevator, and a
+        // priority passenger for theirs.
+        if id == answered_by
+            || (elevator.accessible && !accessible)
+            || elevator.priority.is_some()
+            || !elevator.hall_calls.iter().any(answered)
+        {
+            continue;
+        }
+        elevator.hall_calls.retain(|call| !answered(call));
+        // An elevator with nowhere else to go carries on and waits there, as does one still
+        // answering the call the other way.
+        if elevator.hall_calls.iter().any(|&(at, _)| at == floor)
+            || elevator.car_calls.contains_key(&floor)
+            || elevator.express_to == Some(floor)
+            || elevator.should_visit.len() == 1
+        {
+            continue;
+        }
+        elevator.should_visit.remove(&floor);
+        // An elevator waiting to turn around goes on when it does.
+        if elevator.reverse_after.is_some() {
+            continue;
+        }
+        if elevator.next_step().is_none() {
+            elevator.swap_direction()?;
+        }
+        sender.next_step(id, elevator).await?;
+    }
+    Ok(())
+}
+
+/// Power up elevators on standby while fewer than `min_active` are in service, or while calls
+/// are waiting and no elevator in service is free, closest to the calls first. With no calls
+/// waiting, power down the idle elevators beyond the first `min_active`. See
+/// `ControllerConfig::min_active_cars`.
+fn manage_standby(
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    call_button_pressed_by_floor: &HashSet<(FloorId, Direction)>,
+    accessible_calls: &HashSet<(FloorId, Direction)>,
+    min_active: usize,
+) {
+    let active = |elevator: &ElevatorButtonsInfo| !elevator.standby && !elevator.out_of_service;
+    let free = |elevator: &ElevatorButtonsInfo| {
+        active(elevator)
+            && elevator.is_idle()
+            && elevator.express_to.is_none()
+            && elevator.priority.is_none()
+    };
+    let waiting: Vec<FloorId> = call_button_pressed_by_floor
+        .iter()
+        .chain(accessible_calls)
+        .map(|&(floor, _)| floor)
+        .collect();
+    loop {
+        let active_count = should_visit_by_elevator
+            .iter()
+            .filter(|elevator| active(elevator))
+            .count();
+        let any_free = should_visit_by_elevator.iter().any(free);
+        if active_count >= min_active && (waiting.is_empty() || any_free) {
+            break;
+        }
+        let Some((id, elevator)) = should_visit_by_elevator
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, elevator)| elevator.standby && !elevator.out_of_service)
+            .min_by_key(|(_, elevator)| {
+                waiting
+                    .iter()
+                    .map(|&floor| elevator.position.abs_diff(floor))
+                    .min()
+            })
+        else {
+            break;
+        };
+        elevator.standby = false;
+        info!(elevator = id, "elevator powered up");
+    }
+
+    if !waiting.is_empty() {
+        return;
+    }
+    let mut free_count = should_visit_by_elevator
+        .iter()
+        .filter(|elevator| free(elevator))
+        .count();
+    for (id, elevator) in should_visit_by_elevator.iter_mut().enumerate().rev() {
+        if free_count <= min_active {
+            break;
+        }
+        if free(elevator) {
+            elevator.standby = true;
+            elevator.parking_at = None;
+            elevator.park_after = None;
+            free_count -= 1;
+            info!(elevator = id, "elevator powered down");
+        }
+    }
+}
+
+/// Make sure an idle elevator is at the lobby or on its way there, sending the closest idle
+/// elevator if none is.
+async fn keep_car_at_lobby(
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    sender: &CommandSender<'_>,
+) -> Result<(), Error> {
+    let available = |elevator: &ElevatorButtonsInfo| {
+        elevator.is_idle()
+            && elevator.express_to.is_none()
+            && !elevator.out_of_service
+            && !elevator.standby
+            && elevator.stops_at(0)
+    };
+    let covered = should_visit_by_elevator.iter().any(|elevator| {
+        available(elevator) && elevator.parking_at.unwrap_or(elevator.position) == 0
+    });
+    if covered {
+        return Ok(());
+    }
+    let Some((id, elevator)) = should_visit_by_elevator
+        .iter_mut()
+        .enumerate()
+        .filter(|(_, elevator)| available(elevator))
+        .min_by_key(|(_, elevator)| elevator.position)
+    else {
+        return Ok(());
+    };
+    elevator.parking_at = Some(0);
+    elevator.park_after = None;
+    if elevator.position != 0 {
+        sender.go_to_floor(id, elevator, 0).await?;
+    }
+    Ok(())
+}
+
+/// Pass the controller's commands on to the building `latency` after they were sent, in
+/// order. See `ControllerConfig::latency`.
+async fn delay_commands(
+    mut cmd_rx: mpsc::Receiver<BuildingCommand>,
+    building_cmd_tx: mpsc::Sender<BuildingCommand>,
+    latency: Duration,
+) -> Result<(), Error> {
+    let mut delayed: VecDeque<(Instant, BuildingCommand)> = VecDeque::new();
+    loop {
+        let next_due = delayed.front().map(|&(at, _)| at);
+        tokio::select! {
+            cmd = cmd_rx.recv() => match cmd {
+                Some(cmd) => delayed.push_back((Instant::now() + latency, cmd)),
+                None => break,
+            },
+            _ = time::sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {
+                if let Some((_, cmd)) = delayed.pop_front() {
+                    building_cmd_tx.send(cmd).await?;
+                }
+            }
+        }
+    }
+    // Commands sent before the controller stopped still reach the building.
+    for (at, cmd) in delayed {
+        time::sleep_until(at).await;
+        building_cmd_tx.send(cmd).await?;
+    }
+    Ok(())
+}
+
+/// Update the controller's state with an event from the building and command the
+/// elevators accordingly.
+pub async fn handle_event(
+    evt: BuildingEvent,
+    floors_count: usize,
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    call_button_pressed_by_floor: &mut HashSet<(FloorId, Direction)>,
+    accessible_calls: &mut HashSet<(FloorId, Direction)>,
+    building_cmd_tx: &mpsc::Sender<BuildingCommand>,
+    config: &ControllerConfig,
+) -> Result<(), Error> {
+    let sender = CommandSender {
+        tx: building_cmd_tx,
+        cause: Some(&evt),
+        log: config.log_commands,
+        floors_count,
+    };
+    apply_event(
+        &evt,
+        floors_count,
+        should_visit_by_elevator,
+        call_button_pressed_by_floor,
+        accessible_calls,
+        &sender,
+        config,
+    )
+    .await?;
+    dispatch_calls(
+        should_visit_by_elevator,
+        call_button_pressed_by_floor,
+        accessible_calls,
+        &sender,
+        config,
+    )
+    .await
+}
+
+/// Update the controller's state with an event from the building, and move on the elevators
+/// it concerns. Waiting calls are left for `dispatch_calls`.
+async fn apply_event(
+    evt: &BuildingEvent,
+    floors_count: usize,
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    call_button_pressed_by_floor: &mut HashSet<(FloorId, Direction)>,
+    accessible_calls: &mut HashSet<(FloorId, Direction)>,
+    sender: &CommandSender<'_>,
+    config: &ControllerConfig,
+) -> Result<(), Error> {
+    match *evt {
+        // Calls from every entrance of a floor are answered by the same elevator.
+        BuildingEvent::CallButtonPressed(at, direction, _) => {
+            // A priority call already has its elevator on the way, and so does a call pressed
+            // again before its elevator gets there: neither waits for another elevator.
+            let answered = should_visit_by_elevator.iter().any(|elevator| {
+                elevator.hall_calls.contains(&(at, direction))
+                    || elevator.priority.is_some_and(|lock| {
+                        !lock.picked_up && lock.pickup == at && lock.direction() == direction
+                    })
+            });
+            if !answered {
+                call_button_pressed_by_floor.insert((at, direction));
+            }
+        }
+        BuildingEvent::AccessibleCallButtonPressed(at, direction) => {
+            accessible_calls.insert((at, direction));
+        }
+        BuildingEvent::PassengerBoarded(elevator_id, boarded_at) => {
+            should_visit_by_elevator[elevator_id].awaiting_press += 1;
+            // Someone boarded, so elevators that were refused here may be useful again.
+            for elevator in should_visit_by_elevator.iter_mut() {
+                elevator.declined.retain(|&(floor, _)| floor != boarded_at);
+            }
+        }
+        BuildingEvent::FloorButtonPressed(elevator_id, destination) => {
+            let elevator = &mut should_visit_by_elevator[elevator_id];
+            // Only someone who just boarded presses a button, so any other press would count
+            // a passenger who isn't there.
+            if elevator.awaiting_press == 0 {
+                warn!(
+                    elevator = elevator_id,
+                    floor = destination,
+                    "floor button pressed, but nobody aboard has yet to press one"
+                );
+                return Ok(());
+            }
+            elevator.awaiting_press -= 1;
+            // A passenger pressing the floor the elevator is stopped at has nowhere to go: the
+            // doors open again for them to get off, and the elevator carries on as it was.
+            if destination == elevator.position && !elevator.at_speed {
+                return sender.reopen_doors(elevator_id, elevator).await;
+            }
+            elevator.should_visit.insert(destination);
+            *elevator.car_calls.entry(destination).or_default() += 1;
+            elevator.passenger_count += 1;
+            // Start toward where most of the work is, so that the elevator doesn't have to
+            // turn around right after picking passengers up going both ways.
+            if elevator.picking_up {
+                if let Some(direction) = elevator.busiest_direction(call_button_pressed_by_floor) {
+                    elevator.direction = Some(direction);
+                }
+            }
+            if elevator.next_step().is_none() {
+                elevator.swap_direction()?;
+            }
+            // Passengers aboard don't wait for the elevator to turn around.
+            elevator.reverse_after = None;
+            sender.next_step(elevator_id, elevator).await?;
+        }
+        BuildingEvent::AtFloor(elevator_id, floor) => {
+            let elevator = &mut should_visit_by_elevator[elevator_id];
+            elevator.commanded_at = None;
+            elevator.position = floor;
+            elevator.picking_up = false;
+            elevator.at_speed = !elevator.should_visit.contains(&floor);
+        }
+        BuildingEvent::ForcedUnload(elevator_id, _) => {
+            // Only the hall calls are left to answer.
+            let elevator = &mut should_visit_by_elevator[elevator_id];
+            elevator.car_calls.clear();
+            elevator.passenger_count = 0;
+            elevator.awaiting_press = 0;
+            elevator.reverse_after = None;
+            elevator.should_visit = elevator
+                .hall_calls
+                .iter()
+                .map(|&(floor, _)| floor)
+                .collect();
+            if elevator.is_idle() {
+                elevator.direction = None;
+            } else {
+                if elevator.next_step().is_none() {
+                    elevator.swap_direction()?;
+                }
+                sender.next_step(elevator_id, elevator).await?;
+            }
+        }
+        BuildingEvent::TripAborted(elevator_id, floor) => {
+            // Everyone aboard got off, and whoever wasn't there yet calls again, so the
+            // elevator starts over with nothing to do and its hall calls go to others.
+            let elevator = &mut should_visit_by_elevator[elevator_id];
+            release_priority_call(elevator_id, elevator);
+            elevator.hand_over_hall_calls(call_button_pressed_by_floor, accessible_calls);
+            elevator.car_calls.clear();
+            elevator.passenger_count = 0;
+            elevator.awaiting_press = 0;
+            elevator.should_visit.clear();
+            elevator.queued_calls.clear();
+            elevator.direction = None;
+            elevator.express_to = None;
+            elevator.parking_at = None;
+            elevator.park_after = None;
+            elevator.reverse_after = None;
+            elevator.position = floor;
+        }
+        BuildingEvent::OutOfService(elevator_id) => {
+            let elevator = &mut should_visit_by_elevator[elevator_id];
+            release_priority_call(elevator_id, elevator);
+            elevator.hand_over_hall_calls(call_button_pressed_by_floor, accessible_calls);
+            elevator.should_visit.clear();
+            elevator.direction = None;
+            elevator.parking_at = None;
+            elevator.park_after = None;
+            elevator.reverse_after = None;
+            elevator.express_to = None;
+            // Other elevators answer the calls queued for this one.
+            elevator.queued_calls.clear();
+            elevator.out_of_service = true;
+        }
+        BuildingEvent::BackInService(elevator_id) => {
+            should_visit_by_elevator[elevator_id].out_of_service = false;
+        }
+        BuildingEvent::ExpressModeEngaged(elevator_id, lobby) => {
+            let elevator = &mut should_visit_by_elevator[elevator_id];
+            release_priority_call(elevator_id, elevator);
+            elevator.hand_over_hall_calls(call_button_pressed_by_floor, accessible_calls);
+            elevator.should_visit = elevator.car_calls.keys().copied().collect();
+            elevator.should_visit.insert(lobby);
+            elevator.express_to = Some(lobby);
+            // The building turns the lantern off, so everyone boards at the lobby.
+            elevator.announced = None;
+            elevator.direction = Some(Direction::between(elevator.position, lobby));
+            elevator.parking_at = None;
+            elevator.park_after = None;
+            elevator.reverse_after = None;
+            elevator.picking_up = false;
+        }
+        BuildingEvent::DoorsOpened(elevator_id, floor) => {
+            let announced = should_visit_by_elevator[elevator_id].announced;
+            release_answered_calls(
+                elevator_id,
+                floor,
+                announced,
+                should_visit_by_elevator,
+                sender,
+            )
+            .await?;
+            let elevator = &mut should_visit_by_elevator[elevator_id];
+            elevator.commanded_at = None;
+            if elevator.express_to == Some(floor) {
+                elevator.express_to = None;
+            }
+            elevator.reverse_after = None;
+            elevator.should_visit.remove(&floor);
+            elevator.at_speed = false;
+            // Everyone going to this floor gets off at once.
+            let alighting = elevator.car_calls.remove(&floor).unwrap_or_default();
+            elevator.passenger_count -= alighting;
+            elevator.position = floor;
+            // Everyone waiting here and going the way of the lantern boards, except
+            // passengers with reduced mobility who wait for an accessible elevator, so the
+            // calls that way on this floor are answered. A call the other way is answered on
+            // the way back.
+            let answers = |&(at, direction): &(FloorId, Direction)| {
+                at == floor && announced.is_none_or(|lit| lit == direction)
+            };
+            let answered = elevator.hall_calls.iter().any(answers);
+            elevator.hall_calls.retain(|call| !answers(call));
+            call_button_pressed_by_floor.retain(|call| !answers(call));
+            if elevator.accessible {
+                accessible_calls.retain(|call| !answers(call));
+            }
+            if elevator.hall_calls.iter().any(|&(at, _)| at == floor) {
+                elevator.should_visit.insert(floor);
+            }
+            match elevator.priority {
+                // Take the priority passenger to their floor, whether or not they have pressed
+                // its button yet.
+                Some(ref mut lock) if !lock.picked_up && floor == lock.pickup => {
+                    lock.picked_up = true;
+                    elevator.should_visit.insert(lock.destination);
+                    elevator.direction = Some(lock.direction());
+                }
+                Some(lock) if lock.picked_up && floor == lock.destination => {
+                    info!(
+                        elevator = elevator_id,
+                        call = lock.id,
+                        "priority call delivered, elevator back in normal service"
+                    );
+                    elevator.priority = None;
+                }
+                _ => {}
+            }
+
+            if elevator.next_step().is_none() && !elevator.is_idle() {
+                // Wait a little for calls the way the elevator was going, unless passengers
+                // aboard are waiting for it to turn around.
+                if !config.reversal_grace.is_zero()
+                    && elevator.direction.is_some()
+                    && elevator.car_calls.is_empty()
+                {
+                    elevator.reverse_after = Some(Instant::now() + config.reversal_grace);
+                    return Ok(());
+                }
+                elevator.swap_direction()?;
+            }
+
+            if !elevator.is_idle() {
+                sender.next_step(elevator_id, elevator).await?;
+            } else {
+                elevator.direction = None;
+                elevator.picking_up = true;
+                // Passengers boarding go on with the trip that came for them.
+                elevator.sent_out &= answered;
+                park(
+                    elevator_id,
+                    floors_count,
+                    should_visit_by_elevator,
+                    sender,
+                    config.parking.as_ref(),
+                    config.park_stagger,
+                    config.idle_gravity,
+                )
+                .await?;
+            }
+        }
+        BuildingEvent::GhostStop(_, floor) => {
+            // Nobody is waiting here, so the calls still held for the floor are stale, such
+            // as accessible calls a non-accessible elevator leaves alone.
+            accessible_calls.retain(|&(at, _)| at != floor);
+            for elevator in should_visit_by_elevator.iter_mut() {
+                elevator.queued_calls.retain(|&(at, _)| at != floor);
+            }
+        }
+        BuildingEvent::BoardingDeclined(elevator_id, at, direction) => {
+            should_visit_by_elevator[elevator_id]
+                .declined
+                .insert((at, direction));
+            call_button_pressed_by_floor.insert((at, direction));
+        }
+        BuildingEvent::UnreachableDestination(elevator_id, _) => {
+            // The passenger pressed a button, if one the elevator doesn't answer.
+            let elevator = &mut should_visit_by_elevator[elevator_id];
+            elevator.awaiting_press = elevator.awaiting_press.saturating_sub(1);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Assign the waiting calls to elevators, accessible calls first.
+async fn dispatch_calls(
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    call_button_pressed_by_floor: &mut HashSet<(FloorId, Direction)>,
+    accessible_calls: &mut HashSet<(FloorId, Direction)>,
+    sender: &CommandSender<'_>,
+    config: &ControllerConfig,
+) -> Result<(), Error> {
+    if config.queue_unmatched {
+        answer_queued_calls(
+            should_visit_by_elevator,
+            call_button_pressed_by_floor,
+            accessible_calls,
+            sender,
+        )
+        .await?;
+    }
+    if let Some(min_active) = config.min_active_cars {
+        manage_standby(
+            should_visit_by_elevator,
+            call_button_pressed_by_floor,
+            accessible_calls,
+            min_active,
+        );
+    }
+    assign_calls(
+        should_visit_by_elevator,
+        accessible_calls,
+        sender,
+        config,
+        true,
+    )
+    .await?;
+    assign_calls(
+        should_visit_by_elevator,
+        call_button_pressed_by_floor,
+        sender,
+        config,
+        false,
+    )
+    .await?;
+    if config.up_peak_bias {
+        keep_car_at_lobby(should_visit_by_elevator, sender).await?;
+    }
+    Ok(())
+}
+
+/// Handle `evt` along with every event already waiting in `events_rx`, then assign the
+/// waiting calls once for all of them. See `ControllerConfig::batch_events`. `simulated` is set
+/// from any `BuildingEvent::Clock` among them.
+#[allow(clippy::too_many_arguments)]
+async fn handle_events_batch(
+    evt: BuildingEvent,
+    events_rx: &mut broadcast::Receiver<BuildingEvent>,
+    simulated: &mut Duration,
+    floors_count: usize,
+    should_visit_by_elevator: &mut [ElevatorButtonsInfo],
+    call_button_pressed_by_floor: &mut HashSet<(FloorId, Direction)>,
+    accessible_calls: &mut HashSet<(FloorId, Direction)>,
+    building_cmd_tx: &mpsc::Sender<BuildingCommand>,
+    config: &ControllerConfig,
+) -> Result<(), Error> {
+    let mut next = Some(evt);
+    while let Some(evt) = next.take() {
+        let sender = CommandSender {
+            tx: building_cmd_tx,
+            cause: Some(&evt),
+            log: config.log_commands,
+            floors_count,
+        };
+        apply_event(
+            &evt,
+            floors_count,
+            should_visit_by_elevator,
+            call_button_pressed_by_floor,
+            accessible_calls,
+            &sender,
+            config,
+        )
+        .await?;
+        match events_rx.try_recv() {
+            Ok(evt) => {
+                if let BuildingEvent::Clock(ms) = evt {
+                    *simulated = Duration::from_millis(ms);
+                }
+                next = Some(evt);
+            }
+            Err(TryRecvError::Lagged(missed)) => {
+                warn!(missed, "controller missed events, resynchronizing");
+                // Resynchronizing assigns the calls too.
+                return resync(
+                    floors_count,
+                    should_visit_by_elevator,
+                    call_button_pressed_by_floor,
+                    accessible_calls,
+                    building_cmd_tx,
+                    config,
+                )
+                .await;
+            }
+            // A closed channel is noticed by the next `recv`.
+            Err(TryRecvError::Empty | TryRecvError::Closed) => {}
+        }
+    }
+    let sender = CommandSender {
+        tx: building_cmd_tx,
+        cause: None,
+        log: config.log_commands,
+        floors_count,
+    };
+    dispatch_calls(
+        should_visit_by_elevator,
+        call_button_pressed_by_floor,
+        accessible_calls,
+        &sender,
+        config,
+    )
+    .await
+}
+
+pub async fn controller(
+    elevator_configs: Vec<ElevatorConfig>,
+    floors_count: usize,
+    mut events_rx: broadcast::Receiver<BuildingEvent>,
+    building_cmd_tx: mpsc::Sender<BuildingCommand>,
+    mut queries_rx: mpsc::Receiver<ControllerQuery>,
+    mut config: ControllerConfig,
+) -> Result<(), Error> {
+    let building_cmd_tx = if config.latency.is_zero() {
+        building_cmd_tx
+    } else {
+        let (delayed_tx, delayed_rx) = mpsc::channel(building_cmd_tx.max_capacity());
+        tokio::spawn(delay_commands(delayed_rx, building_cmd_tx, config.latency));
+        delayed_tx
+    };
+    let mut should_visit_by_elevator: Vec<ElevatorButtonsInfo> = elevator_configs
+        .into_iter()
+        .enumerate()
+        .map(|(id, elevator_config)| ElevatorButtonsInfo {
+            stop_pattern: elevator_config.stop_pattern,
+            accessible: elevator_config.accessible,
+            rest_floor: elevator_config.rest_floor,
+            floor_time: elevator_config.floor_time,
+            forbidden_floors: elevator_config.forbidden_floors,
+            // Each elevator draws its own delays, so that they don't depend on the order the
+            // elevators become idle in.
+            stagger_rng: config
+                .seed
+                .map(|seed| StdRng::seed_from_u64(seed.wrapping_add(id as u64))),
+            ..Default::default()
+        })
+        .collect();
+    let mut call_button_pressed_by_floor: HashSet<(FloorId, Direction)> = HashSet::new();
+    let mut accessible_calls: HashSet<(FloorId, Direction)> = HashSet::new();
+    // When to act on each prepositioning hint, and the floor to send an elevator to.
+    let mut preposition_hints: Vec<(Instant, FloorId)> = Vec::new();
+    // The building may start with passengers aboard and calls waiting, see
+    // `Building::with_passenger_aboard`. A building that has already stopped has nothing for
+    // the controller to do.
+    match resync(
+        floors_count,
+        &mut should_visit_by_elevator,
+        &mut call_button_pressed_by_floor,
+        &mut accessible_calls,
+        &building_cmd_tx,
+        &config,
+    )
+    .await
+    {
+        Err(Error::BuildingStopped) => return Ok(()),
+        result => result?,
+    }
+    // While it is night, the night's policy is in `config` and the day's is kept in its place.
+    // The time is the building's, as of its last `Clock`.
+    let mut simulated = Duration::ZERO;
+    let mut night = false;
+
+    loop {
+        let switched = config
+            .night
+            .as_ref()
+            .is_some_and(|policy| policy.is_night(simulated) != night);
+        if switched {
+            night = !night;
+            if let Some(mut policy) = config.night.take() {
+                policy.swap(&mut config);
+                config.night = Some(policy);
+            }
+            info!(
+                policy = if night { "night" } else { "day" },
+                "controller switched policy"
+            );
+            // Without a minimum, every elevator is kept in service.
+            if config.min_active_cars.is_none() {
+                for (id, elevator) in should_visit_by_elevator.iter_mut().enumerate() {
+                    if elevator.standby {
+                        elevator.standby = false;
+                        info!(elevator = id, "elevator powered up");
+                    }
+                }
+            }
+            let sender = CommandSender {
+                tx: &building_cmd_tx,
+                cause: None,
+                log: config.log_commands,
+                floors_count,
+            };
+            dispatch_calls(
+                &mut should_visit_by_elevator,
+                &mut call_button_pressed_by_floor,
+                &mut accessible_calls,
+                &sender,
+                &config,
+            )
+            .await?;
+        }
+        let next_wake = should_visit_by_elevator
+            .iter()
+            .flat_map(|elevator| {
+                elevator
+                    .park_after
+                    .into_iter()
+                    .chain(elevator.reverse_after)
+            })
+            .chain(preposition_hints.iter().map(|&hint| {
+                preposition_departure(hint, &should_visit_by_elevator, config.preposition_lead)
+            }))
+            .chain(progress_deadline(
+                &should_visit_by_elevator,
+                config.progress_timeout,
+            ))
+            .min();
+        let wake_timer = time::sleep_until(next_wake.unwrap_or_else(Instant::now));
+        tokio::select! {
+            _ = wake_timer, if next_wake.is_some() => {
+                let sender = CommandSender {
+                    tx: &building_cmd_tx,
+                    cause: None,
+                    log: config.log_commands,
+                    floors_count,
+                };
+                park_due(&mut should_visit_by_elevator, &sender).await?;
+                reverse_due(&mut should_visit_by_elevator, &sender).await?;
+                preposition_due(
+                    &mut preposition_hints,
+                    &mut should_visit_by_elevator,
+                    &sender,
+                    config.preposition_lead,
+                )
+                .await?;
+                let stuck = progress_deadline(&should_visit_by_elevator, config.progress_timeout)
+                    .is_some_and(|deadline| deadline <= Instant::now());
+                if stuck {
+                    warn!("an elevator made no progress, resynchronizing");
+                    resync(
+                        floors_count,
+                        &mut should_visit_by_elevator,
+                        &mut call_button_pressed_by_floor,
+                        &mut accessible_calls,
+                        &building_cmd_tx,
+                        &config,
+                    )
+                    .await?;
+                }
+            }
+            evt = events_rx.recv() => {
+                let evt = match evt {
+                    Ok(evt) => evt,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!(missed, "controller missed events, resynchronizing");
+                        resync(
+                            floors_count,
+                            &mut should_visit_by_elevator,
+                            &mut call_button_pressed_by_floor,
+                            &mut accessible_calls,
+                            &building_cmd_tx,
+                            &config,
+                        )
+                        .await?;
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if let BuildingEvent::Clock(ms) = evt {
+                    simulated = Duration::from_millis(ms);
+                }
+                if config.batch_events {
+                    handle_events_batch(
+                        evt,
+                        &mut events_rx,
+                        &mut simulated,
+                        floors_count,
+                        &mut should_visit_by_elevator,
+                        &mut call_button_pressed_by_floor,
+                        &mut accessible_calls,
+                        &building_cmd_tx,
+                        &config,
+                    )
+                    .await?;
+                } else {
+                    handle_event(
+                        evt,
+                        floors_count,
+                        &mut should_visit_by_elevator,
+                        &mut call_button_pressed_by_floor,
+                        &mut accessible_calls,
+                        &building_cmd_tx,
+                        &config,
+                    )
+                    .await?;
+                }
+                let diagram = render_state(
+                    floors_count,
+                    &should_visit_by_elevator,
+                    &call_button_pressed_by_floor,
+                    config.floor_labels.as_ref(),
+                );
+                #[cfg(not(feature = "tui"))]
+                println!("{}", diagram);
+                #[cfg(feature = "tui")]
+                {
+                    let frame = tui::render(
+                        &diagram,
+                        &should_visit_by_elevator,
+                        config.floor_labels.is_some(),
+                    );
+                    // A frame that couldn't be drawn is replaced by the next one anyway.
+                    let _ = tui::draw(&mut std::io::stdout(), &frame);
+                }
+                if let Some(dashboard) = &config.dashboard {
+                    // Nobody may be watching yet.
+                    let _ = dashboard.send(diagram);
+                }
+            }
+            Some(query) = queries_rx.recv() => match query {
+                ControllerQuery::Assignment(floor, direction, reply) => {
+                    // Nobody to tell if the asker has gone away.
+                    let _ = reply.send(assigned_elevator(&should_visit_by_elevator, floor, direction));
+                }
+                ControllerQuery::Preposition(floor, at) => {
+                    preposition_hints.push((at, floor));
+                }
+                ControllerQuery::PriorityCall(id, pickup, destination, reply) => {
+                    let sender = CommandSender {
+                        tx: &building_cmd_tx,
+                        cause: None,
+                        log: config.log_commands,
+                        floors_count,
+                    };
+                    let lock = PriorityLock {
+                        id,
+                        pickup,
+                        destination,
+                        picked_up: false,
+                    };
+                    let elevator_id = lock_for_priority_call(
+                        lock,
+                        &mut should_visit_by_elevator,
+                        &mut call_button_pressed_by_floor,
+                        &mut accessible_calls,
+                        &sender,
+                    )
+                    .await?;
+                    // Nobody to tell if the asker has gone away.
+                    let _ = reply.send(elevator_id);
+                }
+                ControllerQuery::State(reply) => {
+                    // Nobody to tell if the asker has gone away.
+                    let _ = reply.send(ControllerState {
+                        elevators: should_visit_by_elevator.clone(),
+                        waiting_calls: call_button_pressed_by_floor.clone(),
+                        accessible_calls: accessible_calls.clone(),
+                    });
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::building::Building;
+    use crate::testing::Harness;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// An idle elevator at `position`.
+    fn idle_at(position: FloorId) -> ElevatorButtonsInfo {
+        ElevatorButtonsInfo::new(position, None, BTreeSet::new())
+    }
+
+    /// Assign `calls` to `elevators` as `process_waiting_list` does, returning the calls left
+    /// waiting and the commands sent, leaving out those setting lanterns.
+    async fn assign(
+        floors_count: usize,
+        elevators: &mut [ElevatorButtonsInfo],
+        calls: &[(FloorId, Direction)],
+        config: &ControllerConfig,
+    ) -> (HashSet<(FloorId, Direction)>, Vec<BuildingCommand>) {
+        let (building_cmd_tx, mut building_cmd_rx) = mpsc::channel(100);
+        let mut waiting = calls.iter().copied().collect();
         process_waiting_list(
-            &mut should_visit_by_elevator,
-            &mut call_button_pressed_by_floor,
+            floors_count,
+            elevators,
+            &mut waiting,
+            &building_cmd_tx,
+            config,
+        )
+        .await
+        .unwrap();
+        drop(building_cmd_tx);
+        let mut commands = Vec::new();
+        while let Some(cmd) = building_cmd_rx.recv().await {
+            if !matches!(cmd, BuildingCommand::SetDirection(..)) {
+                commands.push(cmd);
+            }
+        }
+        (waiting, commands)
+    }
+
+    #[tokio::test]
+    async fn no_more_than_max_cars_head_to_the_same_floor() {
+        let calls = [(5, Direction::Up), (5, Direction::Down)];
+        let config = ControllerConfig {
+            max_cars_per_floor: Some(1),
+            ..Default::default()
+        };
+        let mut elevators = vec![idle_at(9), idle_at(8), idle_at(0), idle_at(1)];
+        let (waiting, commands) = assign(10, &mut elevators, &calls, &config).await;
+        let heading_to_5 = elevators
+            .iter()
+            .filter(|elevator| elevator.hall_calls.iter().any(|&(fl, _)| fl == 5))
+            .count();
+        assert_eq!(heading_to_5, 1);
+        assert_eq!(waiting.len(), 1);
+        assert_eq!(commands.len(), 1);
+
+        // Without the limit, each call gets its own elevator.
+        let mut elevators = vec![idle_at(9), idle_at(8), idle_at(0), idle_at(1)];
+        let (waiting, _) = assign(10, &mut elevators, &calls, &Default::default()).await;
+        assert!(waiting.is_empty());
+    }
+
+    /// A random elevator in a building with `floors_count` floors: idle, or travelling to a
+    /// few random stops.
+    fn random_elevator(rng: &mut StdRng, floors_count: usize) -> ElevatorButtonsInfo {
+        let position = rng.gen_range(0..floors_count);
+        if rng.gen_bool(0.3) {
+            return idle_at(position);
+        }
+        let should_visit: BTreeSet<FloorId> = (0..rng.gen_range(1..4))
+            .map(|_| rng.gen_range(0..floors_count))
+            .collect();
+        let direction = if rng.gen() {
+            Direction::Up
+        } else {
+            Direction::Down
+        };
+        let mut elevator = ElevatorButtonsInfo::new(position, Some(direction), should_visit);
+        elevator.stop_pattern = match rng.gen_range(0..4) {
+            0 => StopPattern::Odd,
+            1 => StopPattern::Even,
+            _ => StopPattern::All,
+        };
+        elevator
+    }
+
+    #[test]
+    fn index_finds_the_same_elevator_as_the_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(105);
+        for _ in 0..200 {
+            let floors_count = rng.gen_range(2..60);
+            let elevators: Vec<_> = (0..rng.gen_range(1..80))
+                .map(|_| random_elevator(&mut rng, floors_count))
+                .collect();
+            let index = ElevatorIndex::new(&elevators);
+            for floor in 0..floors_count {
+                for direction in [Direction::Up, Direction::Down] {
+                    assert_eq!(
+                        index.find_best_elevator_match(floor, direction, &elevators),
+                        find_nearest_elevator_on_route(floor, direction, &elevators),
+                        "call at floor {} going {}",
+                        floor,
+                        direction
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn never_turns_around_with_passengers_going_on() {
+        let mut elevator = ElevatorButtonsInfo::new(3, Some(Direction::Up), BTreeSet::from([1, 7]));
+        elevator.car_calls.insert(7, 1);
+        elevator.swap_direction().unwrap();
+        assert_eq!(elevator.direction, Some(Direction::Up));
+
+        // With only the hall call left behind it, the elevator turns around.
+        elevator.car_calls.clear();
+        elevator.swap_direction().unwrap();
+        assert_eq!(elevator.direction, Some(Direction::Down));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn passenger_aboard_is_taken_up_before_a_call_below() {
+        let mut harness = Harness::start(Building::new(10, 1), Default::default());
+        harness.arrive(4, 8).await;
+        harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::PassengerBoarded(0, 4)))
+            .await;
+        harness.arrive(2, 0).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::PassengerDelivered(8))
+            .await;
+        let (events, _) = harness.halt().await;
+        let boarded = events
+            .iter()
+            .position(|evt| *evt == BuildingEvent::PassengerBoarded(0, 4))
+            .unwrap();
+        let delivered = events
+            .iter()
+            .position(|evt| *evt == BuildingEvent::PassengerDelivered(8))
+            .unwrap();
+        let floors: Vec<_> = events[boarded..delivered]
+            .iter()
+            .filter_map(|evt| match *evt {
+                BuildingEvent::AtFloor(0, fl) => Some(fl),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(floors, [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn spread_parks_elevators_up_the_shaft() {
+        assert_eq!(ParkingPolicy::Spread.parking_floors(9, 3), [0, 3, 6]);
+        assert_eq!(ParkingPolicy::AllLobby.parking_floors(9, 3), [0, 0, 0]);
+        let weights = vec![3.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+        assert_eq!(
+            ParkingPolicy::Weighted(weights).parking_floors(6, 3),
+            [0, 0, 4]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spread_idle_elevators_park_on_distinct_floors() {
+        let config = ControllerConfig {
+            parking: Some(ParkingPolicy::Spread),
+            ..Default::default()
+        };
+        let mut harness = Harness::start(Building::new(9, 3), config);
+        for (at, destination) in [(8, 7), (8, 1), (2, 5)] {
+            harness.arrive(at, destination).await;
+        }
+        harness.wait_for_deliveries(3).await;
+        harness.run_for(Duration::from_secs(10)).await;
+        let state = harness.state().await;
+        let mut positions: Vec<_> = state.elevators.iter().map(|el| el.position()).collect();
+        positions.sort();
+        assert_eq!(positions, [0, 3, 6]);
+        harness.halt().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spread_parks_elevators_idle_from_the_start() {
+        let config = ControllerConfig {
+            parking: Some(ParkingPolicy::Spread),
+            ..Default::default()
+        };
+        let mut harness = Harness::start(Building::new(9, 3), config);
+        harness.run_for(Duration::from_secs(10)).await;
+        let state = harness.state().await;
+        let mut positions: Vec<_> = state.elevators.iter().map(|el| el.position()).collect();
+        positions.sort();
+        assert_eq!(positions, [0, 3, 6]);
+        harness.halt().await;
+    }
+
+    #[tokio::test]
+    async fn sending_to_a_stopped_building_fails() {
+        let (building_cmd_tx, building_cmd_rx) = mpsc::channel(1);
+        drop(building_cmd_rx);
+        let mut waiting = HashSet::from([(4, Direction::Up)]);
+        let result = process_waiting_list(
+            10,
+            &mut [idle_at(0)],
+            &mut waiting,
             &building_cmd_tx,
+            &Default::default(),
         )
         .await;
-        print_state(
-            floors_count,
-            &should_visit_by_elevator,
-            &call_button_pressed_by_floor,
+        assert_eq!(result, Err(Error::BuildingStopped));
+    }
+
+    #[test]
+    fn picking_a_direction_with_nowhere_to_go_fails() {
+        assert_eq!(idle_at(3).swap_direction(), Err(Error::EmptyShouldVisit));
+    }
+
+    #[tokio::test]
+    async fn elevator_is_never_sent_beyond_the_top_floor() {
+        let (building_cmd_tx, mut building_cmd_rx) = mpsc::channel(2);
+        let sender = CommandSender {
+            tx: &building_cmd_tx,
+            cause: None,
+            log: false,
+            floors_count: 10,
+        };
+        // Corrupted state, with a floor the building doesn't have.
+        let mut elevator = ElevatorButtonsInfo::new(5, Some(Direction::Up), BTreeSet::from([15]));
+        sender.next_step(3, &mut elevator).await.unwrap();
+        let mut cmd = building_cmd_rx.recv().await.unwrap();
+        // The lantern is set first.
+        if let BuildingCommand::SetDirection(..) = cmd {
+            cmd = building_cmd_rx.recv().await.unwrap();
+        }
+        assert!(
+            matches!(cmd, BuildingCommand::GoToFloor(3, 9)),
+            "sent {:?}",
+            cmd
+        );
+    }
+
+    #[tokio::test]
+    async fn next_step_with_no_floor_ahead_fails() {
+        let (building_cmd_tx, _building_cmd_rx) = mpsc::channel(1);
+        let sender = CommandSender {
+            tx: &building_cmd_tx,
+            cause: None,
+            log: false,
+            floors_count: 10,
+        };
+        let mut elevator = ElevatorButtonsInfo::new(5, Some(Direction::Up), BTreeSet::from([2]));
+        assert_eq!(
+            sender.next_step(4, &mut elevator).await,
+            Err(Error::NoNextStep(4))
+        );
+    }
+
+    /// Log lines written by a `tracing` subscriber.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn logged_commands_carry_the_elevator_and_floor() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let config = ControllerConfig {
+            log_commands: true,
+            ..Default::default()
+        };
+        let mut elevators = vec![idle_at(0), idle_at(9)];
+        let (_, commands) = assign(10, &mut elevators, &[(7, Direction::Down)], &config).await;
+        assert!(matches!(commands[..], [BuildingCommand::GoToFloor(1, 7)]));
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let line = logs
+            .lines()
+            .find(|line| line.contains("Elevator 1 sent to floor 7"))
+            .expect("command not logged");
+        assert!(line.contains(" INFO "));
+        assert!(line.contains("elevator=1"));
+        assert!(line.contains("floor=7"));
+        assert!(line.contains("position=9"));
+    }
+
+    #[test]
+    fn busiest_direction_counts_stops_and_waiting_calls() {
+        let elevator = ElevatorButtonsInfo::new(5, None, BTreeSet::from([6, 7, 0]));
+        assert_eq!(
+            elevator.busiest_direction(&HashSet::new()),
+            Some(Direction::Up)
+        );
+        let waiting = HashSet::from([(1, Direction::Up), (2, Direction::Down)]);
+        assert_eq!(elevator.busiest_direction(&waiting), Some(Direction::Down));
+        let waiting = HashSet::from([(1, Direction::Up)]);
+        assert_eq!(elevator.busiest_direction(&waiting), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn picks_the_busier_direction_after_picking_up_both_ways() {
+        let mut harness = Harness::start(Building::new(10, 1), Default::default());
+        // Going up first takes 2 + 7 floors, going down first 5 + 7.
+        for (at, destination) in [(5, 0), (5, 6), (5, 7)] {
+            harness.arrive(at, destination).await;
+        }
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::DoorsOpened(0, 5))
+            .await;
+        let next = harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::AtFloor(0, _)))
+            .await;
+        assert_eq!(next, BuildingEvent::AtFloor(0, 6));
+        harness.wait_for_deliveries(3).await;
+        harness.halt().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn assignment_query_names_the_elevator_on_its_way() {
+        let mut harness = Harness::start(Building::new(10, 2), Default::default());
+        harness.arrive(8, 2).await;
+        let BuildingEvent::AtFloor(el, _) = harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::AtFloor(_, 1)))
+            .await
+        else {
+            unreachable!();
+        };
+        let assignment =
+            |floor, direction| move |reply| ControllerQuery::Assignment(floor, direction, reply);
+        assert_eq!(
+            harness.query(assignment(8, Direction::Down)).await,
+            Some(el)
         );
+        assert_eq!(harness.query(assignment(8, Direction::Up)).await, None);
+        assert_eq!(harness.query(assignment(3, Direction::Down)).await, None);
+        harness.wait_for_deliveries(1).await;
+        // Once answered, the call has nobody assigned.
+        assert_eq!(harness.query(assignment(8, Direction::Down)).await, None);
+        harness.halt().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn call_during_park_stagger_cancels_the_park_move() {
+        let config = ControllerConfig {
+            parking: Some(ParkingPolicy::AllLobby),
+            park_stagger: Duration::from_secs(5),
+            seed: Some(121),
+            ..Default::default()
+        };
+        let mut harness = Harness::start(Building::new(10, 1), config);
+        harness.arrive(0, 8).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::PassengerDelivered(8))
+            .await;
+        // The elevator is waiting to park at the lobby when the call comes.
+        let state = harness.state().await;
+        assert!(state.elevators[0].park_after.is_some());
+        harness.arrive(9, 0).await;
+        let next = harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::AtFloor(0, _)))
+            .await;
+        assert_eq!(next, BuildingEvent::AtFloor(0, 9));
+        harness.wait_for_deliveries(1).await;
+        harness.halt().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn up_peak_bias_brings_a_car_back_to_the_lobby_after_every_trip() {
+        let config = ControllerConfig {
+            up_peak_bias: true,
+            ..Default::default()
+        };
+        let mut harness = Harness::start(Building::new(10, 1), config);
+        for destination in [5, 8, 3] {
+            harness.arrive(0, destination).await;
+            harness.wait_for_deliveries(1).await;
+            harness.run_for(Duration::from_secs(60)).await;
+            let state = harness.state().await;
+            assert_eq!(
+                state.elevators[0].position(),
+                0,
+                "after a trip to {}",
+                destination
+            );
+        }
+        harness.halt().await;
+    }
+
+    #[tokio::test]
+    async fn prediction_picks_a_car_about_to_turn_around_over_a_car_on_route() {
+        // Elevator 0 is going up to its last stop and will come straight back down, while
+        // elevator 1 is idle far away. Many idle elevators further still make the controller
+        // consider an index.
+        let about_to_turn = || ElevatorButtonsInfo::new(5, Some(Direction::Up), [6].into());
+        let elevators = || {
+            let mut elevators = vec![about_to_turn(), idle_at(15)];
+            elevators.extend((0..INDEXED_MATCH_THRESHOLD).map(|_| idle_at(39)));
+            elevators
+        };
+        let call = (3, Direction::Down);
+        assert_eq!(
+            find_nearest_elevator_on_route(call.0, call.1, &elevators()),
+            Some(1)
+        );
+        assert_eq!(
+            find_best_elevator_match(call.0, call.1, &elevators(), TieBreak::LowestId),
+            Some(0)
+        );
+
+        let mut predicted = elevators();
+        assign(40, &mut predicted, &[call], &Default::default()).await;
+        assert!(predicted[0].hall_calls.contains(&call));
+
+        let config = ControllerConfig {
+            strategy: Some(Arc::new(strategy::NearestCar)),
+            ..Default::default()
+        };
+        let mut nearest = elevators();
+        assign(40, &mut nearest, &[call], &config).await;
+        assert!(nearest[1].hall_calls.contains(&call));
+    }
+
+    #[test]
+    fn each_tie_break_picks_its_elevator() {
+        // Every elevator can pick the passenger up two floors away.
+        let mut busy = ElevatorButtonsInfo::new(3, Some(Direction::Up), [9].into());
+        busy.passenger_count = 2;
+        busy.car_calls = [(9, 2)].into();
+        let elevators: Vec<_> = [(busy, 1), (idle_at(7), 2), (idle_at(3), 0), (idle_at(7), 3)]
+            .into_iter()
+            .map(|(elevator, trips)| ElevatorButtonsInfo { trips, ..elevator })
+            .collect();
+        for (tie_break, expected) in [
+            (TieBreak::LowestId, 0),
+            (TieBreak::HighestId, 3),
+            (TieBreak::Balanced, 1),
+            (TieBreak::FewestTrips, 2),
+        ] {
+            assert_eq!(
+                find_best_elevator_match(5, Direction::Up, &elevators, tie_break),
+                Some(expected),
+                "{:?}",
+                tie_break
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn prepositioned_car_is_at_the_floor_ahead_of_time() {
+        let second = Duration::from_secs(1);
+        let config = ControllerConfig {
+            preposition_lead: 2 * second,
+            ..Default::default()
+        };
+        // A floor takes a second, so the trip up takes 15 seconds.
+        let building = Building::new(20, 1).with_floor_times(1000, 1000);
+        let mut harness = Harness::start(building, config);
+        let start = Instant::now();
+        let at = start + 30 * second;
+        harness.tell(ControllerQuery::Preposition(15, at)).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::AtFloor(0, 1))
+            .await;
+        let set_off = start.elapsed();
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::AtFloor(0, 15))
+            .await;
+        let arrived = Instant::now();
+        assert!(
+            arrived <= at - 2 * second,
+            "arrived {:?} late",
+            arrived - (at - 2 * second)
+        );
+        // It didn't set off right away either.
+        assert!(set_off >= 12 * second, "set off after {:?}", set_off);
+        harness.halt().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn controller_that_falls_behind_catches_up_with_the_building() {
+        // The controller's queue holds so few events that it can't keep up.
+        let building = Building::new(10, 2).with_events_capacity(2);
+        let mut harness = Harness::start(building, ControllerConfig::default());
+        let arrivals = [
+            (0, 9),
+            (9, 0),
+            (3, 7),
+            (7, 2),
+            (5, 1),
+            (1, 5),
+            (8, 4),
+            (2, 6),
+        ];
+        for (at, destination) in arrivals {
+            harness.arrive(at, destination).await;
+        }
+        harness.wait_for_deliveries(arrivals.len()).await;
+        harness.run_for(Duration::from_secs(30)).await;
+        let state = harness.state().await;
+        let (reply_tx, reply_rx) = oneshot::channel();
+        harness.command(BuildingCommand::Snapshot(reply_tx)).await;
+        let snapshot = reply_rx.await.unwrap();
+        for (elevator, car) in state.elevators.iter().zip(&snapshot.elevators) {
+            assert_eq!(elevator.position(), car.position);
+            assert!(elevator.should_visit().is_empty());
+        }
+        assert!(state.waiting_calls.is_empty());
+        assert!(snapshot.calls.is_empty());
+        let (_, summary) = harness.halt().await;
+        assert!(
+            summary.dropped_events > 0,
+            "the controller never fell behind"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn work_spreads_over_cars_waiting_at_the_lobby() {
+        let config = ControllerConfig {
+            parking: Some(ParkingPolicy::AllLobby),
+            ..Default::default()
+        };
+        let mut harness = Harness::start(Building::new(10, 3), config);
+        for _ in 0..6 {
+            harness.arrive(0, 5).await;
+            harness.wait_for_deliveries(1).await;
+            // Back to the lobby.
+            harness.run_for(Duration::from_secs(30)).await;
+        }
+        let state = harness.state().await;
+        let trips: Vec<_> = state
+            .elevators
+            .iter()
+            .map(|elevator| elevator.trips)
+            .collect();
+        let (events, _) = harness.halt().await;
+        let mut boarded = [0; 3];
+        for evt in events {
+            if let BuildingEvent::PassengerBoarded(el, _) = evt {
+                boarded[el] += 1;
+            }
+        }
+        assert_eq!(boarded, [2, 2, 2]);
+        // Each call and each move back to the lobby is a trip, and the cars took turns.
+        assert!(trips.iter().all(|&trips| trips >= 4), "{:?}", trips);
+        assert!(
+            trips.iter().max().unwrap() - trips.iter().min().unwrap() <= 1,
+            "{:?}",
+            trips
+        );
+    }
+
+    /// Deliver a burst of passengers arriving at once, and count the commands the controller
+    /// sent the building for it, with the building paused so that nothing else happens.
+    async fn commands_for_burst(batch_events: bool) -> usize {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let counted = sent.clone();
+        let mut harness =
+            Harness::start_with(Building::new(20, 4), |events_rx, cmd_tx, queries_rx| {
+                let (counting_tx, mut counting_rx) = mpsc::channel(16);
+                tokio::spawn(async move {
+                    while let Some(cmd) = counting_rx.recv().await {
+                        counted.fetch_add(1, Ordering::Relaxed);
+                        if cmd_tx.send(cmd).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+                let config = ControllerConfig {
+                    batch_events,
+                    ..Default::default()
+                };
+                (events_rx, counting_tx, queries_rx, config)
+            });
+        // Nothing moves while the building is paused, so only the burst is counted.
+        harness.command(BuildingCommand::Pause).await;
+        harness.wait_for(|evt| *evt == BuildingEvent::Paused).await;
+        let arrivals: Vec<_> = (1..20).map(|at| (at, (at + 7) % 20)).collect();
+        for &(at, destination) in &arrivals {
+            harness.arrive(at, destination).await;
+        }
+        harness.run_for(Duration::from_secs(1)).await;
+        let for_burst = sent.load(Ordering::Relaxed);
+        harness.command(BuildingCommand::Resume).await;
+        harness.wait_for_deliveries(arrivals.len()).await;
+        let (_, summary) = harness.halt().await;
+        assert_eq!(summary.delivered, arrivals.len());
+        for_burst
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batched_events_deliver_everyone_with_no_more_commands() {
+        let per_event = commands_for_burst(false).await;
+        let batched = commands_for_burst(true).await;
+        assert!(
+            batched <= per_event,
+            "batched {} commands, per event {}",
+            batched,
+            per_event
+        );
+    }
+
+    #[test]
+    fn diagram_shows_floor_labels_in_their_rows() {
+        let labels = FloorLabels::conventional(15, true);
+        let elevators = [idle_at(1), idle_at(14)];
+        let calls = HashSet::from([(0, Direction::Up)]);
+        let diagram = render_state(15, &elevators, &calls, Some(&labels));
+        let rows: Vec<_> = diagram.lines().collect();
+        assert_eq!(rows.len(), 15);
+        // Top floor first, with 13 skipped.
+        assert_eq!(rows[0], "  14 |   |   | X ");
+        assert_eq!(rows[1], "  12 |   |   |   ");
+        assert_eq!(rows[13], "   M |   | X |   ");
+        assert_eq!(rows[14], "   G | . |   |   ");
+        assert_eq!(
+            labels.labelled(&BuildingEvent::AtFloor(0, 1)).to_string(),
+            "Elevator 0 arrived at floor M"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_cars_return_to_their_own_rest_floors() {
+        let building = Building::new(10, 2)
+            .with_rest_floor(0, 0)
+            .with_rest_floor(1, 6);
+        // The rest floors win over the parking policy.
+        let config = ControllerConfig {
+            parking: Some(ParkingPolicy::AllLobby),
+            ..Default::default()
+        };
+        let mut harness = Harness::start(building, config);
+        harness.run_for(Duration::from_secs(60)).await;
+        // Each call is nearest one of the rest floors.
+        harness.arrive(1, 4).await;
+        harness.arrive(7, 2).await;
+        harness.wait_for_deliveries(2).await;
+        harness.run_for(Duration::from_secs(60)).await;
+        let state = harness.state().await;
+        let positions: Vec<_> = state
+            .elevators
+            .iter()
+            .map(|elevator| (elevator.position, elevator.is_idle()))
+            .collect();
+        assert_eq!(positions, [(0, true), (6, true)]);
+        let (events, _) = harness.halt().await;
+        for el in 0..2 {
+            assert!(
+                events
+                    .iter()
+                    .any(|evt| matches!(evt, BuildingEvent::PassengerBoarded(e, _) if *e == el)),
+                "elevator {} never left its rest floor",
+                el
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn idle_car_beyond_the_divert_cap_leaves_the_call_to_a_closer_busy_car() {
+        // The idle car is closer in time than the busy one, which goes up to 15 first.
+        let elevators = || {
+            vec![
+                idle_at(14),
+                ElevatorButtonsInfo::new(5, Some(Direction::Up), BTreeSet::from([15])),
+            ]
+        };
+        let call = (3, Direction::Up);
+        for (max_idle_divert, answering) in [(None, 0), (Some(12), 0), (Some(10), 1)] {
+            let config = ControllerConfig {
+                max_idle_divert,
+                ..Default::default()
+            };
+            let mut elevators = elevators();
+            let (waiting, _) = assign(20, &mut elevators, &[call], &config).await;
+            assert!(waiting.is_empty());
+            let answered: Vec<_> = elevators
+                .iter()
+                .map(|elevator| elevator.hall_calls.contains(&call))
+                .collect();
+            assert!(
+                answered[answering],
+                "cap {:?}: {:?}",
+                max_idle_divert, answered
+            );
+            assert!(
+                !answered[1 - answering],
+                "cap {:?}: {:?}",
+                max_idle_divert,
+                answered
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn call_right_ahead_of_an_elevator_at_speed_is_deferred() {
+        let call = (6, Direction::Up);
+        for (adjacent_stops, at_speed, deferred) in [
+            (AdjacentStops::Always, true, false),
+            (AdjacentStops::Defer, true, true),
+            // Starting from a stop, the elevator isn't at speed yet.
+            (AdjacentStops::Defer, false, false),
+        ] {
+            let mut elevator =
+                ElevatorButtonsInfo::new(5, Some(Direction::Up), BTreeSet::from([9]));
+            elevator.at_speed = at_speed;
+            let config = ControllerConfig {
+                adjacent_stops,
+                ..Default::default()
+            };
+            let mut elevators = [elevator];
+            let (waiting, _) = assign(10, &mut elevators, &[call], &config).await;
+            assert_eq!(
+                waiting.contains(&call),
+                deferred,
+                "{:?} at speed {}",
+                adjacent_stops,
+                at_speed
+            );
+            assert_eq!(elevators[0].should_visit.contains(&6), !deferred);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn decline_outlasts_others_boarding_in_the_same_tick() {
+        let building = Building::new(10, 2).with_stop_pattern(0, StopPattern::Odd);
+        let mut harness = Harness::start(building, Default::default());
+        // The first passenger fits the odd-floor elevator; the second waits for the other one.
+        harness.arrive(3, 5).await;
+        harness.arrive(3, 4).await;
+        harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::FloorButtonPressed(0, _)))
+            .await;
+        let state = harness.state().await;
+        assert!(state.elevators[0].declined.contains(&(3, Direction::Up)));
+        harness.wait_for_deliveries(2).await;
+        let (events, _) = harness.halt().await;
+        let boarded = events
+            .iter()
+            .position(|evt| *evt == BuildingEvent::PassengerBoarded(0, 3))
+            .unwrap();
+        let declined: Vec<_> = events
+            .iter()
+            .enumerate()
+            .filter(|(_, evt)| matches!(evt, BuildingEvent::BoardingDeclined(0, 3, _)))
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(
+            declined.len(),
+            1,
+            "elevator 0 came back for the call it was refused"
+        );
+        assert!(boarded < declined[0]);
+    }
+
+    #[tokio::test]
+    async fn only_an_empty_car_claims_a_call_the_other_way() {
+        let elevators = || {
+            let mut loaded = ElevatorButtonsInfo::new(3, Some(Direction::Up), BTreeSet::from([9]));
+            loaded.car_calls.insert(9, 1);
+            loaded.passenger_count = 1;
+            let mut empty = ElevatorButtonsInfo::new(1, Some(Direction::Up), BTreeSet::from([9]));
+            empty.hall_calls.insert((9, Direction::Down));
+            vec![loaded, empty]
+        };
+        let call = (5, Direction::Down);
+        for (opposite_calls, answering) in [(OppositeCalls::Any, 0), (OppositeCalls::EmptyOnly, 1)]
+        {
+            let config = ControllerConfig {
+                opposite_calls,
+                ..Default::default()
+            };
+            let mut elevators = elevators();
+            assign(10, &mut elevators, &[call], &config).await;
+            let claimed: Vec<_> = elevators
+                .iter()
+                .map(|elevator| elevator.hall_calls.contains(&call))
+                .collect();
+            assert!(claimed[answering], "{:?}: {:?}", opposite_calls, claimed);
+            assert!(
+                !claimed[1 - answering],
+                "{:?}: {:?}",
+                opposite_calls,
+                claimed
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_cars_never_park_at_a_forbidden_floor() {
+        // Spread over 26 floors, the second car would park at 13.
+        let building = Building::new(26, 3)
+            .with_forbidden_floor(13)
+            .with_rest_floor(2, 13);
+        let config = ControllerConfig {
+            parking: Some(ParkingPolicy::Spread),
+            ..Default::default()
+        };
+        let mut harness = Harness::start(building, config);
+        harness.arrive(0, 20).await;
+        harness.wait_for_deliveries(1).await;
+        harness.run_for(Duration::from_secs(60)).await;
+        let state = harness.state().await;
+        for elevator in &state.elevators {
+            assert_ne!(elevator.parking_at, Some(13));
+            assert_ne!(elevator.position, 13);
+        }
+        harness.halt().await;
+    }
+
+    /// Floors travelled and mean journey, in milliseconds, of a fixed scenario run with
+    /// `comfort_factor`. The journey includes the wait.
+    async fn comfort_scenario(comfort_factor: f64) -> (u64, f64) {
+        let config = ControllerConfig {
+            comfort_factor: Some(comfort_factor),
+            ..Default::default()
+        };
+        let mut harness = Harness::start(Building::new(20, 2), config);
+        // The calls down come in as an elevator sets off up to the top floor, past them,
+        // while the other waits idle at the lobby.
+        let arrivals = [(0, 19), (15, 0), (16, 1), (17, 3)];
+        for (at, destination) in arrivals {
+            harness.arrive(at, destination).await;
+            harness.run_for(Duration::from_millis(500)).await;
+        }
+        let delivered = harness
+            .log
+            .iter()
+            .filter(|evt| matches!(evt, BuildingEvent::PassengerDelivered(_)))
+            .count();
+        harness
+            .wait_for_deliveries(arrivals.len() - delivered)
+            .await;
+        let (_, summary) = harness.halt().await;
+        (summary.floors_travelled, summary.mean_journey_ms)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn favouring_energy_travels_less_and_waits_longer() {
+        let (eager_floors, eager_journey) = comfort_scenario(1.0).await;
+        let (frugal_floors, frugal_journey) = comfort_scenario(0.0).await;
+        assert!(frugal_floors < eager_floors);
+        assert!(frugal_journey > eager_journey);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn locked_car_answers_ordinary_calls_only_after_the_priority_call() {
+        let mut harness = Harness::start(Building::new(10, 1), ControllerConfig::default());
+        let locked = harness
+            .query(|reply| ControllerQuery::PriorityCall(174, 5, 9, reply))
+            .await;
+        assert_eq!(locked, Some(0));
+        harness.arrive(5, 9).await;
+        // An ordinary call on the way, which the locked car goes past.
+        harness.arrive(7, 8).await;
+        harness.wait_for_deliveries(2).await;
+        let (events, _) = harness.halt().await;
+        let stops: Vec<_> = events
+            .iter()
+            .filter_map(|evt| match *evt {
+                BuildingEvent::DoorsOpened(0, floor) => Some(floor),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stops, [5, 9, 7, 8]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spurious_floor_button_press_is_ignored() {
+        let (mut harness, inject) =
+            Harness::start_injecting(Building::new(10, 2), ControllerConfig::default());
+        // Nobody boarded elevator 0.
+        inject
+            .send(BuildingEvent::FloorButtonPressed(0, 7))
+            .unwrap();
+        harness.run_for(Duration::from_secs(30)).await;
+        let state = harness.state().await;
+        let elevator = &state.elevators[0];
+        assert_eq!(elevator.passenger_count(), 0);
+        assert!(elevator.should_visit().is_empty());
+        assert!(elevator.car_calls.is_empty());
+        assert!(!harness
+            .log
+            .iter()
+            .any(|evt| matches!(evt, BuildingEvent::AtFloor(..))));
+        // Real passengers are still served.
+        harness.arrive(2, 5).await;
+        harness.wait_for_deliveries(1).await;
+        harness.halt().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn call_ahead_within_the_reversal_grace_keeps_the_car_going() {
+        let config = ControllerConfig {
+            reversal_grace: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let mut harness = Harness::start(Building::new(10, 1), config);
+        harness.arrive(5, 6).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::AtFloor(0, 3))
+            .await;
+        // A call behind the car, which would turn around for it at floor 6.
+        harness.arrive(2, 0).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::PassengerDelivered(6))
+            .await;
+        // A call ahead, while the car waits to turn around.
+        harness.arrive(8, 9).await;
+        harness.wait_for_deliveries(2).await;
+        let (events, _) = harness.halt().await;
+        let stops: Vec<_> = events
+            .iter()
+            .filter_map(|evt| match *evt {
+                BuildingEvent::DoorsOpened(0, floor) => Some(floor),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stops, [5, 6, 8, 9, 2, 0]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn call_pressed_again_gets_no_second_elevator() {
+        let mut harness = Harness::start(Building::new(10, 2), ControllerConfig::default());
+        harness.arrive(7, 2).await;
+        harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::AtFloor(_, 1)))
+            .await;
+        // Someone else going the same way presses the button again.
+        harness.arrive(7, 0).await;
+        harness.wait_for_deliveries(2).await;
+        let (events, _) = harness.halt().await;
+        let stops_at_7 = events
+            .iter()
+            .filter(|evt| matches!(evt, BuildingEvent::DoorsOpened(_, 7)))
+            .count();
+        assert_eq!(stops_at_7, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn state_shows_the_call_an_elevator_was_sent_to() {
+        let mut harness = Harness::start(Building::new(10, 2), ControllerConfig::default());
+        harness.arrive(7, 2).await;
+        harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::CallButtonPressed(7, ..)))
+            .await;
+        let state = harness.state().await;
+        // The call went to one elevator, which is on its way up to it.
+        assert!(state.waiting_calls.is_empty());
+        let sent: Vec<_> = state
+            .elevators
+            .iter()
+            .filter(|elevator| elevator.hall_calls().contains(&(7, Direction::Down)))
+            .collect();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].should_visit().contains(&7));
+        assert_eq!(sent[0].direction(), Some(Direction::Up));
+        // Pressed again before the elevator gets there, the call stays with it rather than
+        // waiting for another.
+        harness.arrive(7, 0).await;
+        harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::CallButtonPressed(7, ..)))
+            .await;
+        let state = harness.state().await;
+        assert!(state.waiting_calls.is_empty());
+        let sent_again: Vec<_> = state
+            .elevators
+            .iter()
+            .filter(|elevator| elevator.hall_calls().contains(&(7, Direction::Down)))
+            .collect();
+        assert_eq!(sent_again.len(), 1);
+        harness.halt().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn calls_both_ways_at_a_floor_are_answered_by_the_cars_going_each_way() {
+        let mut harness = Harness::start(Building::new(10, 2), ControllerConfig::default());
+        // One car goes up to the top floor, the other stays at the lobby.
+        harness.arrive(0, 9).await;
+        harness.wait_for_deliveries(1).await;
+        harness.arrive(5, 9).await;
+        harness.arrive(5, 0).await;
+        harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::CallButtonPressed(5, Direction::Down, _)))
+            .await;
+        let state = harness.state().await;
+        let sent_to = |direction| {
+            (0..2)
+                .filter(|&el| state.elevators[el].hall_calls().contains(&(5, direction)))
+                .collect::<Vec<_>>()
+        };
+        let (up, down) = (sent_to(Direction::Up), sent_to(Direction::Down));
+        assert_eq!(up.len(), 1);
+        assert_eq!(down.len(), 1);
+        assert_ne!(up, down);
+        harness.wait_for_deliveries(2).await;
+        // Each passenger boarded the car that came their way.
+        let boarded: Vec<_> = harness
+            .log
+            .iter()
+            .filter_map(|evt| match *evt {
+                BuildingEvent::PassengerBoarded(el, 5) => Some(el),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(boarded.len(), 2);
+        assert_ne!(boarded[0], boarded[1]);
+        harness.halt().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_cars_drift_down_to_the_lobby() {
+        let config = ControllerConfig {
+            idle_gravity: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let mut harness = Harness::start(Building::new(20, 1), config);
+        harness.arrive(0, 16).await;
+        harness.wait_for_deliveries(1).await;
+        harness.run_for(Duration::from_secs(5 * 60)).await;
+        let stops: Vec<_> = harness
+            .log
+            .iter()
+            .filter_map(|evt| match *evt {
+                BuildingEvent::DoorsOpened(0, floor) => Some(floor),
+                _ => None,
+            })
+            .collect();
+        // Halfway down after each idle period, with nobody calling.
+        assert_eq!(stops, [0, 16, 8, 4, 2, 1, 0]);
+        harness.halt().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn low_traffic_keeps_the_minimum_of_cars_in_service() {
+        let config = ControllerConfig {
+            min_active_cars: Some(2),
+            ..Default::default()
+        };
+        let mut harness = Harness::start(Building::new(10, 4), config);
+        let active = |state: &ControllerState| {
+            state
+                .elevators
+                .iter()
+                .filter(|elevator| !elevator.standby && !elevator.out_of_service)
+                .count()
+        };
+        for (at, destination) in [(0, 5), (7, 2), (3, 9), (9, 0)] {
+            harness.arrive(at, destination).await;
+            for _ in 0..10 {
+                harness.run_for(Duration::from_secs(2)).await;
+                assert!(active(&harness.state().await) >= 2);
+            }
+        }
+        // With nobody calling, the spare cars are powered down.
+        harness.run_for(Duration::from_secs(30)).await;
+        assert_eq!(active(&harness.state().await), 2);
+        let (_, summary) = harness.halt().await;
+        assert!(summary.all_delivered());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn commands_reach_the_building_after_the_latency() {
+        let latency = Duration::from_millis(700);
+        let mut sent = None;
+        let mut harness =
+            Harness::start_with(Building::new(10, 1), |events_rx, cmd_tx, queries_rx| {
+                // Note when the building gets each elevator's destination.
+                let (controller_cmd_tx, mut controller_cmd_rx) = mpsc::channel(16);
+                let (sent_tx, sent_rx) = mpsc::unbounded_channel();
+                sent = Some(sent_rx);
+                tokio::spawn(async move {
+                    while let Some(cmd) = controller_cmd_rx.recv().await {
+                        if let BuildingCommand::GoToFloor(el, fl) = cmd {
+                            let _ = sent_tx.send((Instant::now(), el, fl));
+                        }
+                        if cmd_tx.send(cmd).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+                let config = ControllerConfig {
+                    latency,
+                    ..Default::default()
+                };
+                (events_rx, controller_cmd_tx, queries_rx, config)
+            });
+        // Let the controller catch up with the building first.
+        harness.run_for(Duration::from_secs(2)).await;
+        harness.arrive(6, 0).await;
+        harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::CallButtonPressed(6, ..)))
+            .await;
+        let called_at = Instant::now();
+        let (sent_at, el, fl) = sent.unwrap().recv().await.unwrap();
+        assert_eq!((el, fl), (0, 6));
+        assert_eq!(sent_at - called_at, latency);
+        harness.wait_for_deliveries(1).await;
+        harness.halt().await;
+    }
+
+    #[tokio::test]
+    async fn calls_no_busy_car_can_take_are_queued_by_load_and_answered_once_free() {
+        let config = ControllerConfig {
+            queue_unmatched: true,
+            ..Default::default()
+        };
+        // Every car is on its way to the top, too far to foresee it coming back down.
+        let busy = |should_visit: &[FloorId]| {
+            ElevatorButtonsInfo::new(
+                0,
+                Some(Direction::Up),
+                should_visit.iter().copied().collect(),
+            )
+        };
+        let mut elevators = vec![busy(&[90]), busy(&[90]), busy(&[80, 85, 90])];
+        let calls: Vec<_> = (40..46).map(|floor| (floor, Direction::Down)).collect();
+        let (mut waiting, commands) = assign(100, &mut elevators, &calls, &config).await;
+        assert_eq!(waiting.len(), calls.len());
+        assert!(commands.is_empty());
+        // The busiest car gets the fewest calls.
+        let queued: Vec<_> = elevators
+            .iter()
+            .map(|elevator| elevator.queued_calls.len())
+            .collect();
+        assert_eq!(queued, [3, 2, 1]);
+
+        // Once a car is free, it takes the call queued for it and leaves the others alone.
+        let call = elevators[2].queued_calls[0];
+        elevators[2] = ElevatorButtonsInfo {
+            queued_calls: elevators[2].queued_calls.clone(),
+            ..idle_at(90)
+        };
+        let (building_cmd_tx, _building_cmd_rx) = mpsc::channel(4);
+        let sender = CommandSender {
+            tx: &building_cmd_tx,
+            cause: None,
+            log: false,
+            floors_count: 100,
+        };
+        answer_queued_calls(&mut elevators, &mut waiting, &mut HashSet::new(), &sender)
+            .await
+            .unwrap();
+        assert_eq!(elevators[2].hall_calls, HashSet::from([call]));
+        assert!(!waiting.contains(&call));
+        assert_eq!(waiting.len(), calls.len() - 1);
+        assert_eq!(elevators[0].queued_calls.len(), 3);
+        assert_eq!(elevators[1].queued_calls.len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pressing_the_floor_the_car_is_stopped_at_reopens_its_doors() {
+        let (mut harness, inject) =
+            Harness::start_injecting(Building::new(10, 1), ControllerConfig::default());
+        harness.arrive(0, 3).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::DoorsOpened(0, 3))
+            .await;
+        // Someone boards and presses the floor the elevator is stopped at.
+        inject.send(BuildingEvent::PassengerBoarded(0, 3)).unwrap();
+        inject
+            .send(BuildingEvent::FloorButtonPressed(0, 3))
+            .unwrap();
+        let from = harness.log.len();
+        // The press adds no stop, not even one at the floor the elevator is at.
+        harness.run_for(Duration::from_millis(1)).await;
+        let state = harness.state().await;
+        let elevator = &state.elevators[0];
+        assert!(elevator.car_calls.is_empty());
+        assert_eq!(elevator.passenger_count(), 0);
+        assert!(elevator.should_visit().is_empty());
+        harness.run_for(Duration::from_secs(30)).await;
+        let after = &harness.log[from..];
+        assert!(after.contains(&BuildingEvent::DoorsOpened(0, 3)));
+        // The elevator never left the floor.
+        assert!(!after
+            .iter()
+            .any(|evt| matches!(evt, BuildingEvent::AtFloor(_, floor) if *floor != 3)));
+        harness.halt().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn car_sent_to_a_floor_whose_caller_left_makes_a_ghost_stop_and_carries_on() {
+        let mut harness = Harness::start(Building::new(10, 2), ControllerConfig::default());
+        let locked = harness
+            .query(|reply| ControllerQuery::PriorityCall(1, 7, 6, reply))
+            .await;
+        assert_eq!(locked, Some(0));
+        harness.arrive(7, 6).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::AtFloor(0, 5))
+            .await;
+        // The call goes to the free elevator, but the locked one stops there first, on its
+        // way to let the priority passenger off, and the caller boards it.
+        harness.arrive(6, 0).await;
+        harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::CallButtonPressed(6, ..)))
+            .await;
+        let state = harness.state().await;
+        assert!(state.elevators[1]
+            .hall_calls()
+            .contains(&(6, Direction::Down)));
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::PassengerBoarded(0, 6))
+            .await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::GhostStop(1, 6))
+            .await;
+        harness
+            .wait_for(|evt| matches!(evt, BuildingEvent::DoorsClosed(1, 6, _)))
+            .await;
+        let state = harness.state().await;
+        assert!(state.waiting_calls.is_empty());
+        assert!(state.elevators[1].hall_calls().is_empty());
+        assert!(state.elevators[1].should_visit().is_empty());
+        // The elevator isn't stuck at the floor.
+        harness.arrive(2, 8).await;
+        harness.wait_for_deliveries(2).await;
+        harness.halt().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn night_policy_switches_on_the_building_clock() {
+        const HOUR: Duration = Duration::from_secs(60 * 60);
+        const MINUTE: Duration = Duration::from_secs(60);
+        // The building starts a minute before the night, which lasts two minutes.
+        let config = ControllerConfig {
+            night: Some(NightPolicy {
+                starts_at: 22 * HOUR,
+                ends_at: 22 * HOUR + 2 * MINUTE,
+                start_time: 22 * HOUR - MINUTE,
+                parking: None,
+                idle_gravity: None,
+                min_active_cars: Some(1),
+            }),
+            ..Default::default()
+        };
+        // Five times faster than real time, which doesn't change the building's clock.
+        let building = Building::new(10, 3).with_time_scale(5.0);
+        let mut harness = Harness::start(building, config);
+        let active = |state: &ControllerState| {
+            state
+                .elevators
+                .iter()
+                .filter(|elevator| !elevator.standby)
+                .count()
+        };
+        harness.run_for(Duration::from_secs(10)).await;
+        assert_eq!(active(&harness.state().await), 3);
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::Clock(60 * 1000))
+            .await;
+        harness.run_for(Duration::from_secs(1)).await;
+        assert_eq!(active(&harness.state().await), 1);
+        // Calls are still answered at night.
+        harness.arrive(4, 0).await;
+        harness.wait_for_deliveries(1).await;
+        harness
+            .wait_for(|evt| *evt == BuildingEvent::Clock(3 * 60 * 1000))
+            .await;
+        harness.run_for(Duration::from_secs(1)).await;
+        assert_eq!(active(&harness.state().await), 3);
+        harness.halt().await;
     }
 }
@@ -0,0 +1,193 @@
+//! Dispatch strategies decide which elevator answers each hall call. They can be picked by
+//! name at runtime with `strategy_by_name`, for example to compare them.
+
+use std::fmt;
+
+use super::ElevatorButtonsInfo;
+use crate::building::{Direction, FloorId};
+use crate::error::Error;
+
+/// A DispatchStrategy rates how well each elevator suits a call. The elevator with the lowest
+/// cost answers it, ties being broken by `ControllerConfig::tie_break`.
+pub trait DispatchStrategy: fmt::Debug + Send + Sync {
+    /// The cost of `elevator` answering the call at `floor` going in `direction`, or `None`
+    /// if it shouldn't answer it for now. Elevators that can't answer the call at all, for
+    /// example because they don't stop at the floor, are never asked.
+    fn cost(
+        &self,
+        elevator: &ElevatorButtonsInfo,
+        floor: FloorId,
+        direction: Direction,
+    ) -> Option<usize>;
+
+    /// Returns true if the strategy picks the same elevators as
+    /// `find_nearest_elevator_on_route`, so that an `ElevatorIndex` can look its matches up
+    /// when there are many elevators.
+    fn matches_nearest_on_route(&self) -> bool {
+        false
+    }
+}
+
+/// Predictive sends the elevator predicted to pick the passenger up soonest, following its
+/// stops for up to `PREDICTION_HORIZON` steps. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Predictive;
+
+impl DispatchStrategy for Predictive {
+    fn cost(
+        &self,
+        elevator: &ElevatorButtonsInfo,
+        floor: FloorId,
+        direction: Direction,
+    ) -> Option<usize> {
+        elevator.steps_until_pickup(floor, direction)
+    }
+}
+
+/// NearestCar sends the closest elevator that is either idle or already passing the floor in
+/// the call's direction on its current trip. Other calls wait.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NearestCar;
+
+impl DispatchStrategy for NearestCar {
+    fn cost(
+        &self,
+        elevator: &ElevatorButtonsInfo,
+        floor: FloorId,
+        direction: Direction,
+    ) -> Option<usize> {
+        let on_route = elevator
+            .current_trip()
+            .is_some_and(|trip| trip.contains(&floor))
+            && elevator.direction == Some(direction);
+        (elevator.is_idle() || on_route).then(|| elevator.position.abs_diff(floor))
+    }
+
+    fn matches_nearest_on_route(&self) -> bool {
+        true
+    }
+}
+
+/// Scan has every elevator sweep to the last of its stops in one direction before turning
+/// around, and sends the elevator that would reach the call soonest on its sweeps.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Scan;
+
+impl DispatchStrategy for Scan {
+    fn cost(
+        &self,
+        elevator: &ElevatorButtonsInfo,
+        floor: FloorId,
+        direction: Direction,
+    ) -> Option<usize> {
+        let position = elevator.position;
+        let Some(moving) = elevator.direction.filter(|_| !elevator.is_idle()) else {
+            return Some(position.abs_diff(floor));
+        };
+        // Where the elevator turns around at each end of its sweeps, counting the call.
+        let top = elevator
+            .should_visit
+            .last()
+            .map_or(position, |&last| last.max(position));
+        let bottom = elevator
+            .should_visit
+            .first()
+            .map_or(position, |&first| first.min(position));
+        let (top, bottom) = (top.max(floor), bottom.min(floor));
+        let cost = match (moving, direction) {
+            (Direction::Up, Direction::Up) if floor >= position => floor - position,
+            (Direction::Down, Direction::Down) if floor <= position => position - floor,
+            (Direction::Up, Direction::Down) => (top - position) + (top - floor),
+            (Direction::Down, Direction::Up) => (position - bottom) + (floor - bottom),
+            (Direction::Up, Direction::Up) => (top - position) + (top - bottom) + (floor - bottom),
+            (Direction::Down, Direction::Down) => {
+                (position - bottom) + (top - bottom) + (top - floor)
+            }
+        };
+        Some(cost)
+    }
+}
+
+/// Comfort weighs the cost of another strategy, which stands for the passenger's wait,
+/// against the floors that the elevator would travel beyond the stops it already has, which
+/// stand for energy. See `ControllerConfig::comfort_factor`.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Comfort<'a> {
+    pub strategy: &'a dyn DispatchStrategy,
+    /// Weight of the wait, from 0 to 1. The rest of the weight goes to energy.
+    pub comfort_factor: f64,
+}
+
+/// Costs weighed by `Comfort` are scaled up by this much, so that rounding them to whole
+/// numbers keeps them apart.
+const COMFORT_COST_SCALE: f64 = 100.0;
+
+impl DispatchStrategy for Comfort<'_> {
+    fn cost(
+        &self,
+        elevator: &ElevatorButtonsInfo,
+        floor: FloorId,
+        direction: Direction,
+    ) -> Option<usize> {
+        let wait = self.strategy.cost(elevator, floor, direction)?;
+        // An elevator whose stops already take it past the floor only has to stop there,
+        // while any other has to go further, or turn around for it.
+        let position = elevator.position;
+        let lowest = elevator
+            .should_visit
+            .first()
+            .map_or(position, |&first| first.min(position));
+        let highest = elevator
+            .should_visit
+            .last()
+            .map_or(position, |&last| last.max(position));
+        let extra_floors = lowest.saturating_sub(floor) + floor.saturating_sub(highest);
+        let cost =
+            self.comfort_factor * wait as f64 + (1.0 - self.comfort_factor) * extra_floors as f64;
+        Some((cost * COMFORT_COST_SCALE).round() as usize)
+    }
+}
+
+/// Creates a strategy.
+type Constructor = fn() -> Box<dyn DispatchStrategy>;
+
+/// The strategies that can be picked by name, with how to create each.
+const STRATEGIES: [(&str, Constructor); 3] = [
+    ("predictive", || Box::new(Predictive)),
+    ("nearest_car", || Box::new(NearestCar)),
+    ("scan", || Box::new(Scan)),
+];
+
+/// Names of the strategies that `strategy_by_name` knows.
+pub fn strategy_names() -> Vec<&'static str> {
+    STRATEGIES.iter().map(|&(name, _)| name).collect()
+}
+
+/// Create the strategy with the given name. Fails with the list of known names if there is
+/// no such strategy.
+pub fn strategy_by_name(name: &str) -> Result<Box<dyn DispatchStrategy>, Error> {
+    STRATEGIES
+        .iter()
+        .find(|&&(known, _)| known == name)
+        .map(|&(_, create)| create())
+        .ok_or_else(|| Error::UnknownStrategy(name.to_owned(), strategy_names()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strategies_are_found_by_name() {
+        let nearest = strategy_by_name("nearest_car").unwrap();
+        assert_eq!(format!("{:?}", nearest), "NearestCar");
+        assert!(nearest.matches_nearest_on_route());
+        let scan = strategy_by_name("scan").unwrap();
+        assert_eq!(format!("{:?}", scan), "Scan");
+        assert_eq!(
+            strategy_by_name("bogus").unwrap_err(),
+            Error::UnknownStrategy("bogus".to_owned(), strategy_names())
+        );
+        assert!(strategy_names().contains(&"predictive"));
+    }
+}
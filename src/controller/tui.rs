@@ -0,0 +1,71 @@
+//! A text interface that redraws the state of the elevators in place, instead of printing a
+//! new shaft diagram for every event. Enabled by the `tui` feature.
+
+use std::io::{self, Write};
+
+use crossterm::cursor::MoveTo;
+use crossterm::style::Print;
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::QueueableCommand;
+
+use super::ElevatorButtonsInfo;
+
+/// Render one frame: the shaft diagram drawn by `render_state`, then a row with the number of
+/// passengers in each elevator, under its column. `labelled` tells whether the diagram was
+/// drawn with floor labels.
+pub fn render(
+    diagram: &str,
+    should_visit_by_elevator: &[ElevatorButtonsInfo],
+    labelled: bool,
+) -> String {
+    // The load goes under the elevators, past the labels and the call buttons.
+    let margin = if labelled { 9 } else { 4 };
+    let load: Vec<_> = should_visit_by_elevator
+        .iter()
+        .map(|elevator| format!("{:>3}", elevator.passenger_count))
+        .collect();
+    format!("{}\n{:<margin$}|{}\n", diagram, "load", load.join("|"))
+}
+
+/// Draw `frame` over the previous one, from the top-left corner, clearing whatever was
+/// printed below the last frame.
+pub fn draw(out: &mut impl Write, frame: &str) -> io::Result<()> {
+    out.queue(MoveTo(0, 0))?
+        .queue(Print(frame))?
+        .queue(Clear(ClearType::FromCursorDown))?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::building::{Direction, FloorLabels};
+    use crate::controller::render_state;
+    use std::collections::{BTreeSet, HashSet};
+
+    #[test]
+    fn frame_shows_floors_calls_and_load() {
+        let mut busy = ElevatorButtonsInfo::new(2, Some(Direction::Up), BTreeSet::from([3]));
+        busy.passenger_count = 4;
+        let elevators = [ElevatorButtonsInfo::new(0, None, BTreeSet::new()), busy];
+        let calls = HashSet::from([(1, Direction::Up)]);
+        let labels = FloorLabels::conventional(4, false);
+        let diagram = render_state(4, &elevators, &calls, Some(&labels));
+        let frame = render(&diagram, &elevators, true);
+        let mut out = Vec::new();
+        draw(&mut out, &frame).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(&frame));
+        let rows: Vec<_> = frame.lines().collect();
+        assert_eq!(
+            rows,
+            [
+                "   3 |   |   |   ",
+                "   2 |   |   | X ",
+                "   1 | . |   |   ",
+                "   G |   | X |   ",
+                "load     |  0|  4",
+            ]
+        );
+    }
+}
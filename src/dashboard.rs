@@ -0,0 +1,84 @@
+//! Streams the controller's shaft diagram to remote terminals over TCP, so the simulation can
+//! be watched live, for example with `nc localhost 7000`.
+
+use std::io;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Move the cursor to the top-left corner, so each diagram is drawn over the previous one.
+const CURSOR_HOME: &str = "\x1b[H";
+/// Clear the rest of the screen, in case the previous diagram was longer.
+const CLEAR_BELOW: &str = "\x1b[J";
+
+/// Accept connections on `listener` and stream every diagram sent on `diagrams` to each of
+/// them until they disconnect. Pass the same sender as `ControllerConfig::dashboard`.
+pub async fn serve(listener: TcpListener, diagrams: broadcast::Sender<String>) -> io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(stream_diagrams(stream, diagrams.subscribe()));
+    }
+}
+
+async fn stream_diagrams(mut stream: TcpStream, mut diagrams: broadcast::Receiver<String>) {
+    loop {
+        let diagram = match diagrams.recv().await {
+            Ok(diagram) => diagram,
+            // Only the latest diagram matters to a slow terminal.
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return,
+        };
+        let frame = format!("{}{}\n{}", CURSOR_HOME, diagram, CLEAR_BELOW);
+        if stream.write_all(frame.as_bytes()).await.is_err() {
+            // The terminal went away; stop streaming to it.
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    /// Connect to the dashboard at `addr` and wait until it streams to the connection.
+    async fn connect(
+        addr: std::net::SocketAddr,
+        diagrams: &broadcast::Sender<String>,
+    ) -> TcpStream {
+        let subscribers = diagrams.receiver_count();
+        let client = TcpStream::connect(addr).await.unwrap();
+        while diagrams.receiver_count() == subscribers {
+            tokio::task::yield_now().await;
+        }
+        client
+    }
+
+    /// Read from `client` until a whole frame has arrived.
+    async fn read_frame(client: &mut TcpStream) -> String {
+        let mut frame = Vec::new();
+        while !frame.ends_with(CLEAR_BELOW.as_bytes()) {
+            let mut buf = [0; 256];
+            let read = client.read(&mut buf).await.unwrap();
+            assert_ne!(read, 0, "dashboard closed the connection");
+            frame.extend_from_slice(&buf[..read]);
+        }
+        String::from_utf8(frame).unwrap()
+    }
+
+    #[tokio::test]
+    async fn clients_get_whole_frames_and_may_leave() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (diagrams, _) = broadcast::channel(16);
+        tokio::spawn(serve(listener, diagrams.clone()));
+
+        let leaving = connect(addr, &diagrams).await;
+        drop(leaving);
+        let mut client = connect(addr, &diagrams).await;
+        diagrams.send("| . | X |".to_owned()).unwrap();
+        assert_eq!(read_frame(&mut client).await, "\x1b[H| . | X |\n\x1b[J");
+        diagrams.send("|   | X |".to_owned()).unwrap();
+        assert_eq!(read_frame(&mut client).await, "\x1b[H|   | X |\n\x1b[J");
+    }
+}
@@ -1,61 +1,457 @@
 //! The driver controls when and where passengers arrive.
 
-use crate::building::{Building, BuildingEvent, DriverCommand};
-use rand::Rng;
-use tokio::sync::{broadcast, mpsc};
-
-/// Create a new building to be driven by this driver.
-pub fn make_building() -> Building {
-    // Set num elevators to more than 1 for question 2.
-    Building::new(30, 10)
+use crate::building::{
+    Building, BuildingEvent, Direction, DriverCommand, FloorId, SimulationSummary,
+};
+use crate::controller::{self, ControllerConfig};
+use crate::error::Error;
+use crate::traffic::{TrafficPattern, Until};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, watch};
+use tracing::warn;
+
+/// Create a new building to be driven by this driver, with elevators that each carry at most
+/// `capacity` passengers.
+pub fn make_building(num_floors: usize, num_elevators: usize, capacity: usize) -> Building {
+    Building::new(num_floors, num_elevators).with_capacity(capacity)
 }
 
-/// Simulate people arriving at the ground floor and going to the first floor, one by one.
+/// Simulate up to `passengers_count` people arriving as the traffic `pattern` has them.
+/// Passengers arrive `time_scale` times faster than real time, to match
+/// `Building::with_time_scale`. The same `seed` gives the same arrivals.
 // ----------- Solution 1 -----------
 pub async fn driver(
-    num_floors: usize,
     passengers_count: usize,
+    mut pattern: impl TrafficPattern + Send + 'static,
+    time_scale: f64,
+    seed: u64,
     mut events_rx: broadcast::Receiver<BuildingEvent>,
     driver_cmd_tx: mpsc::Sender<DriverCommand>,
-) {
+) -> Result<(), Error> {
     let sender = driver_cmd_tx.clone();
-    tokio::spawn(async move {
+    let (paused_tx, mut paused_rx) = watch::channel(false);
+    let (expected_tx, mut expected_rx) = watch::channel(passengers_count);
+    let mut arrivals = tokio::spawn(async move {
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut idx = 0;
+        // Simulated time of the last arrival, which doesn't count pauses.
+        let mut simulated = Duration::ZERO;
         while idx < passengers_count {
-            let (at, destination, wait_time_ms, high_traffic) = {
-                let mut rng = rand::thread_rng();
-                let high_traffic = rng.gen_range(0..100) >= 95; // 5% chance of high traffic
-                let at = rng.gen_range(0..num_floors);
-                let destination = rng.gen_range(0..num_floors);
-                let wait_time_ms = rng.gen_range(1..=300);
-                (at, destination, wait_time_ms, high_traffic)
+            let arrival = pattern.next_arrival(simulated, &mut rng);
+            let Some((at, destination, wait_time)) = arrival else {
+                // Nobody else comes.
+                expected_tx.send_replace(idx);
+                break;
             };
-            tokio::time::sleep(tokio::time::Duration::from_millis(wait_time_ms)).await;
+            simulated += wait_time;
+            if !wait_time.is_zero() {
+                tokio::time::sleep(wait_time.div_f64(time_scale)).await;
+            }
+            // No one arrives while the building is paused.
+            while *paused_rx.borrow() {
+                if paused_rx.changed().await.is_err() {
+                    break;
+                }
+            }
             // ----------- End solution 1 -----------
             // A passenger has arrived..
-            let send_amount = if high_traffic {
-                10.min(passengers_count - idx)
-            } else {
-                1
-            };
-            for _ in 0..send_amount {
-                idx += 1;
-                sender
-                    .send(DriverCommand::PassengerArrived { at, destination })
-                    .await
-                    .unwrap();
-            }
+            idx += 1;
+            sender
+                .send(DriverCommand::PassengerArrived {
+                    at,
+                    destination,
+                    reduced_mobility: false,
+                    entrance: 0,
+                })
+                .await?;
         }
+        Ok::<(), Error>(())
     });
-    // Wait until they are delivered..
+    // Wait until they are delivered.. With nobody to wait for, the building is halted
+    // straight away.
     let mut delivered_count = 0;
-    while let Ok(evt) = events_rx.recv().await {
-        if let BuildingEvent::PassengerDelivered(_) = evt {
+    let mut expected_count = passengers_count;
+    let mut arrived = false;
+    while delivered_count < expected_count {
+        tokio::select! {
+            evt = events_rx.recv() => {
+                let evt = match evt {
+                    Ok(evt) => evt,
+                    // As in `driver_saturate`, deliveries among the missed events go uncounted.
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!(missed, "driver missed events");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                match evt {
+                    // Passengers turned away, or who stay where they are, won't be delivered.
+                    BuildingEvent::PassengerDelivered(_)
+                    | BuildingEvent::PassengerStayed(_)
+                    | BuildingEvent::CallRejected(..) => {
+                        delivered_count += 1;
+                    }
+                    BuildingEvent::Paused => {
+                        paused_tx.send_replace(true);
+                    }
+                    BuildingEvent::Resumed => {
+                        paused_tx.send_replace(false);
+                    }
+                    _ => {}
+                }
+            }
+            Ok(()) = expected_rx.changed() => {
+                expected_count = *expected_rx.borrow();
+            }
+            // Passengers can't arrive once the building has stopped.
+            result = &mut arrivals, if !arrived => {
+                arrived = true;
+                result.expect("arrival task panicked")?;
+            }
+        }
+    }
+    driver_cmd_tx.send(DriverCommand::Halt).await?;
+    if !arrived {
+        arrivals.await.expect("arrival task panicked")?;
+    }
+    Ok(())
+}
+
+/// Run `building` under a controller with `config`, with passengers arriving as the traffic
+/// `pattern` has them for `duration` of simulated time. Once nobody else arrives, wait for
+/// everyone to be delivered, then stop the building and return its summary. Randomness is
+/// drawn from `seed`, unless `config` has a seed of its own.
+pub async fn run_for(
+    building: Building,
+    pattern: impl TrafficPattern + Send + 'static,
+    duration: Duration,
+    config: ControllerConfig,
+    seed: u64,
+) -> Result<SimulationSummary, Error> {
+    run(
+        building,
+        usize::MAX,
+        Until::new(pattern, duration),
+        config,
+        seed,
+    )
+    .await
+}
+
+/// Run `building` under a controller with `config` until `passengers_count` passengers, arriving
+/// as the traffic `pattern` has them, are delivered, then stop the building and return its
+/// summary. Randomness is drawn from `seed`, unless `config` has a seed of its own.
+pub async fn run(
+    building: Building,
+    passengers_count: usize,
+    pattern: impl TrafficPattern + Send + 'static,
+    config: ControllerConfig,
+    seed: u64,
+) -> Result<SimulationSummary, Error> {
+    let num_floors = building.num_floors();
+    let time_scale = building.time_scale();
+    let elevator_configs = building.elevator_configs();
+    let (building_task, events_rx, building_cmd_tx, driver_cmd_tx) =
+        building.with_seed(seed).start();
+    let driver_task = tokio::spawn(driver(
+        passengers_count,
+        pattern,
+        time_scale,
+        seed,
+        events_rx.resubscribe(),
+        driver_cmd_tx,
+    ));
+    // Nothing queries the controller.
+    let (_queries_tx, queries_rx) = mpsc::channel(1);
+    tokio::spawn(controller::controller(
+        elevator_configs,
+        num_floors,
+        events_rx,
+        building_cmd_tx,
+        queries_rx,
+        ControllerConfig {
+            seed: config.seed.or(Some(seed)),
+            ..config
+        },
+    ));
+    let summary = building_task.await.expect("building task panicked")?;
+    driver_task.await.expect("driver task panicked")?;
+    Ok(summary)
+}
+
+/// Call elevators in each of the `directions` at every one of the `floors` floors at once, to
+/// load the controller with as many calls as possible. Each passenger goes to a random floor
+/// that way, drawn from `seed`. Halts the building once everyone is delivered.
+pub async fn driver_saturate(
+    floors: usize,
+    directions: &[Direction],
+    seed: u64,
+    mut events_rx: broadcast::Receiver<BuildingEvent>,
+    driver_cmd_tx: mpsc::Sender<DriverCommand>,
+) -> Result<(), Error> {
+    let arrivals = saturating_arrivals(floors, directions, &mut StdRng::seed_from_u64(seed));
+    let passengers_count = arrivals.len();
+    for (at, destination) in arrivals {
+        driver_cmd_tx
+            .send(DriverCommand::PassengerArrived {
+                at,
+                destination,
+                reduced_mobility: false,
+                entrance: 0,
+            })
+            .await?;
+    }
+    let mut delivered_count = 0;
+    while delivered_count < passengers_count {
+        let evt = match events_rx.recv().await {
+            Ok(evt) => evt,
+            // Deliveries among the missed events go uncounted, so the building should keep
+            // enough events for the driver, see `Building::with_events_capacity`.
+            Err(RecvError::Lagged(missed)) => {
+                warn!(missed, "driver missed events");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if let BuildingEvent::PassengerDelivered(_)
+        | BuildingEvent::PassengerStayed(_)
+        | BuildingEvent::CallRejected(..) = evt
+        {
             delivered_count += 1;
-            if delivered_count == passengers_count {
-                break;
+        }
+    }
+    driver_cmd_tx.send(DriverCommand::Halt).await?;
+    Ok(())
+}
+
+/// Where the passengers of `driver_saturate` arrive and where they go.
+fn saturating_arrivals(
+    floors: usize,
+    directions: &[Direction],
+    rng: &mut impl Rng,
+) -> Vec<(FloorId, FloorId)> {
+    let mut arrivals = Vec::new();
+    for at in 0..floors {
+        for &direction in directions {
+            // Nobody goes down from the ground floor or up from the top floor.
+            let destination = match direction {
+                Direction::Up if at + 1 < floors => rng.gen_range(at + 1..floors),
+                Direction::Down if at > 0 => rng.gen_range(0..at),
+                _ => continue,
+            };
+            arrivals.push((at, destination));
+        }
+    }
+    arrivals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn make_building_has_the_given_size() {
+        let building = make_building(5, 2, 8);
+        assert_eq!(building.num_floors(), 5);
+        assert_eq!(building.num_elevators(), 2);
+    }
+
+    /// Fixed arrivals, one second apart.
+    struct Fixed(Vec<(FloorId, FloorId)>);
+
+    impl TrafficPattern for Fixed {
+        fn next_arrival(
+            &mut self,
+            _now: Duration,
+            _rng: &mut impl Rng,
+        ) -> Option<(FloorId, FloorId, Duration)> {
+            (!self.0.is_empty()).then(|| {
+                let (at, destination) = self.0.remove(0);
+                (at, destination, Duration::from_secs(1))
+            })
+        }
+    }
+
+    /// The arrivals the driver sends for up to `count` passengers of `pattern`, drawn from
+    /// `seed`, delivering each passenger at once until the driver halts the building.
+    async fn sent_arrivals(
+        pattern: impl TrafficPattern + Send + 'static,
+        count: usize,
+        seed: u64,
+    ) -> Vec<(FloorId, FloorId)> {
+        let (events_tx, events_rx) = broadcast::channel(16);
+        let (driver_cmd_tx, mut driver_cmd_rx) = mpsc::channel(16);
+        let driver = tokio::spawn(driver(count, pattern, 1.0, seed, events_rx, driver_cmd_tx));
+        let mut sent = Vec::new();
+        while let Some(cmd) = driver_cmd_rx.recv().await {
+            match cmd {
+                DriverCommand::PassengerArrived {
+                    at, destination, ..
+                } => {
+                    sent.push((at, destination));
+                    events_tx
+                        .send(BuildingEvent::PassengerDelivered(destination))
+                        .unwrap();
+                }
+                DriverCommand::Halt => break,
+                cmd => panic!("unexpected {:?}", cmd),
+            }
+        }
+        driver.await.unwrap().unwrap();
+        sent
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn driver_sends_the_arrivals_of_its_pattern() {
+        let arrivals = vec![(0, 3), (5, 1), (2, 9)];
+        let sent = sent_arrivals(Fixed(arrivals.clone()), 10, 138).await;
+        assert_eq!(sent, arrivals);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_same_seed_gives_the_same_arrivals() {
+        let uniform = || crate::traffic::Uniform::new(20);
+        let arrivals = sent_arrivals(uniform(), 20, 171).await;
+        assert_eq!(arrivals.len(), 20);
+        assert_eq!(sent_arrivals(uniform(), 20, 171).await, arrivals);
+        assert_ne!(sent_arrivals(uniform(), 20, 172).await, arrivals);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn every_saturating_call_is_served() {
+        let floors = 8;
+        let building = make_building(floors, 2, 4);
+        let elevator_configs = building.elevator_configs();
+        let (building_task, events_rx, building_cmd_tx, driver_cmd_tx) = building.start();
+        let (_queries_tx, queries_rx) = mpsc::channel(1);
+        tokio::spawn(controller::controller(
+            elevator_configs,
+            floors,
+            events_rx.resubscribe(),
+            building_cmd_tx,
+            queries_rx,
+            ControllerConfig::default(),
+        ));
+        let directions = [Direction::Up, Direction::Down];
+        driver_saturate(floors, &directions, 158, events_rx, driver_cmd_tx)
+            .await
+            .unwrap();
+        let summary = building_task.await.unwrap().unwrap();
+        // Every floor calls both ways, but the ground floor and the top floor.
+        assert_eq!(summary.delivered, 2 * floors - 2);
+        assert!(summary.undelivered.is_empty());
+    }
+
+    /// A passenger going from the lobby to floor 5 every ten seconds, for ever.
+    struct Periodic;
+
+    impl TrafficPattern for Periodic {
+        fn next_arrival(
+            &mut self,
+            _now: Duration,
+            _rng: &mut impl Rng,
+        ) -> Option<(FloorId, FloorId, Duration)> {
+            Some((0, 5, Duration::from_secs(10)))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn run_ends_once_the_duration_is_over_and_everyone_is_delivered() {
+        let duration = Duration::from_secs(2 * 60);
+        let summary = run_for(
+            make_building(10, 1, 8),
+            Periodic,
+            duration,
+            ControllerConfig::default(),
+            177,
+        )
+        .await
+        .unwrap();
+        // A passenger every ten seconds, the last one right at the end.
+        assert_eq!(summary.delivered, 12);
+        assert!(summary.all_delivered());
+        // The last passenger only had five floors to go.
+        let simulated = Duration::from_millis(summary.simulated_ms);
+        assert!(simulated >= duration, "ended after {:?}", simulated);
+        assert!(
+            simulated < duration + Duration::from_secs(10),
+            "ended after {:?}",
+            simulated
+        );
+        assert!(summary.throughput_per_hour() > 0.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn run_with_no_passengers_halts_at_once() {
+        let building = make_building(5, 1, 4);
+        let (building_task, events_rx, _building_cmd_tx, driver_cmd_tx) = building.start();
+        let arrivals = Fixed(vec![(0, 3)]);
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            driver(0, arrivals, 1.0, 182, events_rx, driver_cmd_tx),
+        )
+        .await
+        .expect("driver still waiting")
+        .unwrap();
+        let summary = tokio::time::timeout(Duration::from_secs(1), building_task)
+            .await
+            .expect("building still running")
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.delivered, 0);
+        assert!(summary.all_delivered());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn driver_keeps_counting_deliveries_after_missing_events() {
+        let arrivals = vec![(0, 3), (5, 1), (2, 9)];
+        let (events_tx, events_rx) = broadcast::channel(2);
+        let (driver_cmd_tx, mut driver_cmd_rx) = mpsc::channel(16);
+        let driver = tokio::spawn(driver(
+            10,
+            Fixed(arrivals.clone()),
+            1.0,
+            158,
+            events_rx,
+            driver_cmd_tx,
+        ));
+        let mut sent = Vec::new();
+        while let Some(cmd) = driver_cmd_rx.recv().await {
+            match cmd {
+                DriverCommand::PassengerArrived {
+                    at, destination, ..
+                } => {
+                    sent.push((at, destination));
+                    // More events than the driver keeps, so that it misses some.
+                    for ms in 0..5 {
+                        events_tx.send(BuildingEvent::LongestWait(ms)).unwrap();
+                    }
+                    events_tx
+                        .send(BuildingEvent::PassengerDelivered(destination))
+                        .unwrap();
+                }
+                DriverCommand::Halt => break,
+                cmd => panic!("unexpected {:?}", cmd),
             }
         }
+        driver.await.unwrap().unwrap();
+        assert_eq!(sent, arrivals);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn driver_fails_once_arrivals_can_no_longer_be_sent() {
+        let (_events_tx, events_rx) = broadcast::channel(16);
+        let (driver_cmd_tx, driver_cmd_rx) = mpsc::channel(16);
+        drop(driver_cmd_rx);
+        let result = tokio::time::timeout(
+            Duration::from_secs(60),
+            driver(3, Fixed(vec![(0, 3)]), 1.0, 158, events_rx, driver_cmd_tx),
+        )
+        .await
+        .expect("driver still waiting");
+        assert!(result.is_err());
     }
-    driver_cmd_tx.send(DriverCommand::Halt).await.unwrap();
 }
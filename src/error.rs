@@ -0,0 +1,58 @@
+//! Errors that stop the building, the controller or the driver.
+
+use std::fmt;
+
+use tokio::sync::{broadcast, mpsc};
+
+use crate::building::{BuildingCommand, BuildingEvent, DriverCommand, ElevatorId};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The building is no longer accepting commands.
+    BuildingStopped,
+    /// The building has an event to report but nobody is listening.
+    NoEventSubscribers,
+    /// The controller was asked to pick a direction for an elevator with nowhere to go.
+    EmptyShouldVisit,
+    /// The given elevator has floors to visit but none of them lies ahead of it.
+    NoNextStep(ElevatorId),
+    /// No dispatch strategy has the given name. Gives the names of the strategies there are.
+    UnknownStrategy(String, Vec<&'static str>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BuildingStopped => write!(f, "the building is no longer accepting commands"),
+            Error::NoEventSubscribers => write!(f, "nobody is listening to building events"),
+            Error::EmptyShouldVisit => write!(f, "elevator has no floors to visit"),
+            Error::NoNextStep(el) => write!(f, "elevator {} has no floor to go to next", el),
+            Error::UnknownStrategy(name, available) => write!(
+                f,
+                "unknown dispatch strategy {:?}, expected one of: {}",
+                name,
+                available.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<mpsc::error::SendError<BuildingCommand>> for Error {
+    fn from(_: mpsc::error::SendError<BuildingCommand>) -> Self {
+        Error::BuildingStopped
+    }
+}
+
+impl From<mpsc::error::SendError<DriverCommand>> for Error {
+    fn from(_: mpsc::error::SendError<DriverCommand>) -> Self {
+        Error::BuildingStopped
+    }
+}
+
+impl From<broadcast::error::SendError<BuildingEvent>> for Error {
+    fn from(_: broadcast::error::SendError<BuildingEvent>) -> Self {
+        Error::NoEventSubscribers
+    }
+}
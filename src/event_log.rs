@@ -0,0 +1,174 @@
+//! Logs of building events, so that long simulations can be recorded and replayed. Events are
+//! written either in a compact binary format, with bincode and variable-length integers so
+//! that most fields take a single byte, or as JSON Lines for people to read.
+
+use std::io::{self, Read, Write};
+
+use bincode::Options;
+use serde_json::de::IoRead;
+use serde_json::StreamDeserializer;
+
+use crate::building::BuildingEvent;
+
+/// The encoding of bincode logs.
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+/// Format of an event log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// One bincode-encoded event after the other.
+    #[default]
+    Binary,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+/// EventLogWriter writes building events to a log.
+pub struct EventLogWriter<W> {
+    writer: W,
+    format: Format,
+}
+
+impl<W: Write> EventLogWriter<W> {
+    /// Write a binary log.
+    pub fn new(writer: W) -> Self {
+        Self::with_format(writer, Format::Binary)
+    }
+
+    pub fn with_format(writer: W, format: Format) -> Self {
+        Self { writer, format }
+    }
+
+    /// Append an event to the log.
+    pub fn write(&mut self, evt: &BuildingEvent) -> io::Result<()> {
+        match self.format {
+            Format::Binary => bincode_options()
+                .serialize_into(&mut self.writer, evt)
+                .map_err(|err| bincode_error(*err)),
+            Format::JsonLines => {
+                serde_json::to_writer(&mut self.writer, evt)?;
+                self.writer.write_all(b"\n")
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Get back the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// EventLogReader reads back the events written by an `EventLogWriter`, in order.
+pub struct EventLogReader<R: Read> {
+    decoder: Decoder<R>,
+}
+
+enum Decoder<R: Read> {
+    Binary(R),
+    JsonLines(StreamDeserializer<'static, IoRead<R>, BuildingEvent>),
+}
+
+impl<R: Read> EventLogReader<R> {
+    /// Read a binary log.
+    pub fn new(reader: R) -> Self {
+        Self::with_format(reader, Format::Binary)
+    }
+
+    pub fn with_format(reader: R, format: Format) -> Self {
+        let decoder = match format {
+            Format::Binary => Decoder::Binary(reader),
+            Format::JsonLines => {
+                Decoder::JsonLines(serde_json::Deserializer::from_reader(reader).into_iter())
+            }
+        };
+        Self { decoder }
+    }
+
+    /// Read the next event, or `None` at the end of the log.
+    pub fn read(&mut self) -> io::Result<Option<BuildingEvent>> {
+        match &mut self.decoder {
+            Decoder::Binary(reader) => {
+                // The log may only end between events.
+                let mut first = [0];
+                if reader.read(&mut first)? == 0 {
+                    return Ok(None);
+                }
+                bincode_options()
+                    .deserialize_from(first.chain(reader))
+                    .map(Some)
+                    .map_err(|err| bincode_error(*err))
+            }
+            Decoder::JsonLines(events) => events.next().transpose().map_err(io::Error::from),
+        }
+    }
+}
+
+impl<R: Read> Iterator for EventLogReader<R> {
+    type Item = io::Result<BuildingEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+fn bincode_error(err: bincode::ErrorKind) -> io::Error {
+    match err {
+        bincode::ErrorKind::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::building::Direction;
+
+    fn events() -> Vec<BuildingEvent> {
+        vec![
+            BuildingEvent::CallButtonPressed(3, Direction::Up, 1),
+            BuildingEvent::AtFloor(0, 300),
+            BuildingEvent::DoorsClosed(1, 4, vec![2, 7]),
+            BuildingEvent::LongestWait(u64::MAX),
+            BuildingEvent::Bunching(vec![]),
+            BuildingEvent::Paused,
+        ]
+    }
+
+    #[test]
+    fn events_read_back_as_written() {
+        for format in [Format::Binary, Format::JsonLines] {
+            let mut writer = EventLogWriter::with_format(Vec::new(), format);
+            for evt in &events() {
+                writer.write(evt).unwrap();
+            }
+            let log = writer.into_inner();
+            let read: Vec<_> = EventLogReader::with_format(&log[..], format)
+                .collect::<io::Result<_>>()
+                .unwrap();
+            assert_eq!(read, events(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn binary_log_is_compact() {
+        let mut writer = EventLogWriter::new(Vec::new());
+        writer.write(&BuildingEvent::AtFloor(1, 5)).unwrap();
+        assert_eq!(writer.into_inner().len(), 3);
+    }
+
+    #[test]
+    fn truncated_log_is_an_error() {
+        let mut writer = EventLogWriter::new(Vec::new());
+        writer.write(&BuildingEvent::AtFloor(1, 300)).unwrap();
+        let mut log = writer.into_inner();
+        log.pop();
+        let err = EventLogReader::new(&log[..]).read().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,399 @@
+//! Adapters that turn building events into a `Stream`, which keeps going when the reader
+//! falls behind, which `broadcast::Receiver::recv` reports as an error.
+//!
+//! Subscribers that can't afford to miss events, such as a recorder, can instead subscribe
+//! with `Building::subscribe` and `Backpressure::Block`, which makes the building wait for
+//! them.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+
+use crate::building::{BuildingEvent, ElevatorId, FloorId};
+
+/// StreamItem is an item read from an `EventStream`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamItem {
+    Event(BuildingEvent),
+    /// The reader fell behind and this many events were dropped before the next one.
+    Lagged(u64),
+}
+
+impl Display for StreamItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamItem::Event(evt) => write!(f, "{}", evt),
+            StreamItem::Lagged(count) => write!(f, "Missed {} events", count),
+        }
+    }
+}
+
+/// Backpressure is what happens when a subscriber falls behind the building, see
+/// `Building::subscribe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    /// The subscriber misses the oldest events it hasn't received, and the stream reports how
+    /// many. The building goes on at full speed. Fine for people watching a run.
+    #[default]
+    Drop,
+    /// The building waits for the subscriber, which misses nothing. Needed to record a run.
+    Block,
+}
+
+/// EventStream wraps a receiver of building events. A stream from the building's broadcast
+/// channel drops the oldest events when the reader falls behind, while one subscribed with
+/// `Backpressure::Block` holds the building back instead and never lags.
+pub struct EventStream {
+    events_rx: Source,
+}
+
+/// Source is the channel an `EventStream` reads from.
+enum Source {
+    Dropping(BroadcastStream<BuildingEvent>),
+    Blocking(mpsc::Receiver<BuildingEvent>),
+}
+
+impl EventStream {
+    pub fn new(events_rx: broadcast::Receiver<BuildingEvent>) -> Self {
+        Self {
+            events_rx: Source::Dropping(BroadcastStream::new(events_rx)),
+        }
+    }
+
+    /// Wait for the next item, or `None` once the building has stopped.
+    pub async fn next(&mut self) -> Option<StreamItem> {
+        future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for EventStream {
+    type Item = StreamItem;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<StreamItem>> {
+        match &mut self.events_rx {
+            Source::Dropping(events_rx) => Pin::new(events_rx).poll_next(cx).map(|item| {
+                item.map(|item| match item {
+                    Ok(evt) => StreamItem::Event(evt),
+                    Err(BroadcastStreamRecvError::Lagged(count)) => StreamItem::Lagged(count),
+                })
+            }),
+            Source::Blocking(events_rx) => events_rx
+                .poll_recv(cx)
+                .map(|evt| evt.map(StreamItem::Event)),
+        }
+    }
+}
+
+impl From<broadcast::Receiver<BuildingEvent>> for EventStream {
+    fn from(events_rx: broadcast::Receiver<BuildingEvent>) -> Self {
+        Self::new(events_rx)
+    }
+}
+
+impl From<mpsc::Receiver<BuildingEvent>> for EventStream {
+    fn from(events_rx: mpsc::Receiver<BuildingEvent>) -> Self {
+        Self {
+            events_rx: Source::Blocking(events_rx),
+        }
+    }
+}
+
+/// CoalescedEvents passes on the items of an `EventStream`, except events repeating the state
+/// an elevator is already known to be in, such as arriving at the floor it is already at.
+/// Useful for dashboards that only redraw on changes.
+pub struct CoalescedEvents {
+    events: EventStream,
+    cars: HashMap<ElevatorId, CarState>,
+}
+
+/// CarState is what the events seen so far say about an elevator.
+#[derive(Default)]
+struct CarState {
+    floor: Option<FloorId>,
+    /// Floor the elevator opened its doors at, if it hasn't closed them since.
+    doors_opened_at: Option<FloorId>,
+    out_of_service: bool,
+}
+
+impl CarState {
+    /// Apply the event to the state, returning false if it changes nothing.
+    fn apply(&mut self, evt: &BuildingEvent) -> bool {
+        match *evt {
+            BuildingEvent::AtFloor(_, fl) => {
+                if self.floor == Some(fl) {
+                    return false;
+                }
+                self.floor = Some(fl);
+                self.doors_opened_at = None;
+            }
+            BuildingEvent::DoorsOpened(_, fl) => {
+                if self.doors_opened_at == Some(fl) {
+                    return false;
+                }
+                self.floor = Some(fl);
+                self.doors_opened_at = Some(fl);
+            }
+            BuildingEvent::DoorsClosed(..) => self.doors_opened_at = None,
+            BuildingEvent::OutOfService(_) | BuildingEvent::BackInService(_) => {
+                let out_of_service = matches!(evt, BuildingEvent::OutOfService(_));
+                if self.out_of_service == out_of_service {
+                    return false;
+                }
+                self.out_of_service = out_of_service;
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+impl CoalescedEvents {
+    pub fn new(events: EventStream) -> Self {
+        Self {
+            events,
+            cars: HashMap::new(),
+        }
+    }
+
+    /// Wait for the next item that changes something, or `None` once the building has
+    /// stopped.
+    pub async fn next(&mut self) -> Option<StreamItem> {
+        future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for CoalescedEvents {
+    type Item = StreamItem;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<StreamItem>> {
+        let this = &mut *self;
+        loop {
+            let Some(item) = std::task::ready!(Pin::new(&mut this.events).poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+            match &item {
+                StreamItem::Event(evt) => {
+                    let changed =
+                        car_of(evt).is_none_or(|el| this.cars.entry(el).or_default().apply(evt));
+                    if !changed {
+                        continue;
+                    }
+                }
+                // The missed events may have changed anything.
+                StreamItem::Lagged(_) => this.cars.clear(),
+            }
+            return Poll::Ready(Some(item));
+        }
+    }
+}
+
+/// Subscribe to the events about elevator `el` only, from now on, alongside `events_rx`.
+pub fn subscribe_elevator(
+    events_rx: &broadcast::Receiver<BuildingEvent>,
+    el: ElevatorId,
+) -> ElevatorEvents {
+    ElevatorEvents::new(EventStream::new(events_rx.resubscribe()), el)
+}
+
+/// ElevatorEvents passes on the items of an `EventStream` about a single elevator, see
+/// `BuildingEvent::elevator`. Missed events are still reported, since some may have been
+/// about the elevator.
+pub struct ElevatorEvents {
+    events: EventStream,
+    elevator: ElevatorId,
+}
+
+impl ElevatorEvents {
+    pub fn new(events: EventStream, elevator: ElevatorId) -> Self {
+        Self { events, elevator }
+    }
+
+    /// Wait for the next item about the elevator, or `None` once the building has stopped.
+    pub async fn next(&mut self) -> Option<StreamItem> {
+        future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for ElevatorEvents {
+    type Item = StreamItem;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<StreamItem>> {
+        loop {
+            let Some(item) = std::task::ready!(Pin::new(&mut self.events).poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+            if let StreamItem::Event(evt) = &item {
+                if evt.elevator() != Some(self.elevator) {
+                    continue;
+                }
+            }
+            return Poll::Ready(Some(item));
+        }
+    }
+}
+
+/// The elevator whose state the event describes, if it describes one.
+fn car_of(evt: &BuildingEvent) -> Option<ElevatorId> {
+    match *evt {
+        BuildingEvent::AtFloor(el, _)
+        | BuildingEvent::DoorsOpened(el, _)
+        | BuildingEvent::DoorsClosed(el, ..)
+        | BuildingEvent::OutOfService(el)
+        | BuildingEvent::BackInService(el) => Some(el),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn stream_reports_lag_and_goes_on() {
+        let (events_tx, events_rx) = broadcast::channel(2);
+        for fl in 0..4 {
+            events_tx.send(BuildingEvent::AtFloor(0, fl)).unwrap();
+        }
+        let items: Vec<_> = EventStream::new(events_rx).take(3).collect().await;
+        assert_eq!(
+            items,
+            [
+                StreamItem::Lagged(2),
+                StreamItem::Event(BuildingEvent::AtFloor(0, 2)),
+                StreamItem::Event(BuildingEvent::AtFloor(0, 3)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_compose_and_end_with_the_building() {
+        let (events_tx, events_rx) = broadcast::channel(16);
+        let events = subscribe_elevator(&events_rx, 1);
+        for evt in [
+            BuildingEvent::AtFloor(0, 1),
+            BuildingEvent::AtFloor(1, 1),
+            BuildingEvent::PassengerDelivered(1),
+            BuildingEvent::AtFloor(1, 1),
+            BuildingEvent::DoorsOpened(1, 1),
+        ] {
+            events_tx.send(evt).unwrap();
+        }
+        drop(events_tx);
+        let floors: Vec<_> = CoalescedEvents::new(EventStream::new(events_rx))
+            .filter_map(|item| match item {
+                StreamItem::Event(BuildingEvent::AtFloor(_, fl)) => Some(fl),
+                _ => None,
+            })
+            .collect()
+            .await;
+        assert_eq!(floors, [1, 1]);
+        let items: Vec<_> = events.collect().await;
+        assert_eq!(
+            items,
+            [
+                StreamItem::Event(BuildingEvent::AtFloor(1, 1)),
+                StreamItem::Event(BuildingEvent::AtFloor(1, 1)),
+                StreamItem::Event(BuildingEvent::DoorsOpened(1, 1)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_changes_pass_the_coalescer() {
+        let (events_tx, events_rx) = broadcast::channel(16);
+        let sent = [
+            BuildingEvent::AtFloor(0, 1),
+            BuildingEvent::AtFloor(0, 1),
+            BuildingEvent::DoorsOpened(0, 1),
+            BuildingEvent::DoorsOpened(0, 1),
+            BuildingEvent::DoorsClosed(0, 1, vec![]),
+            BuildingEvent::DoorsOpened(0, 1),
+            BuildingEvent::OutOfService(0),
+            BuildingEvent::OutOfService(0),
+            BuildingEvent::PassengerDelivered(1),
+            BuildingEvent::PassengerDelivered(1),
+        ];
+        for evt in sent.iter().cloned() {
+            events_tx.send(evt).unwrap();
+        }
+        drop(events_tx);
+        let passed: Vec<_> = CoalescedEvents::new(EventStream::new(events_rx))
+            .collect()
+            .await;
+        let expected: Vec<_> = [0, 2, 4, 5, 6, 8, 9]
+            .into_iter()
+            .map(|i| StreamItem::Event(sent[i].clone()))
+            .collect();
+        assert_eq!(passed, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn elevator_subscription_gets_only_the_floors_of_its_elevator() {
+        use crate::building::{Building, DriverCommand};
+        use crate::controller::{self, ControllerConfig};
+
+        let building = Building::new(10, 2);
+        let elevator_configs = building.elevator_configs();
+        let (task, mut events_rx, building_cmd_tx, driver_cmd_tx) = building.start();
+        let elevator = subscribe_elevator(&events_rx, 1);
+        let (_queries_tx, queries_rx) = mpsc::channel(1);
+        tokio::spawn(controller::controller(
+            elevator_configs,
+            10,
+            events_rx.resubscribe(),
+            building_cmd_tx,
+            queries_rx,
+            ControllerConfig::default(),
+        ));
+        // Both elevators go up, one for each passenger.
+        for (at, destination) in [(0, 9), (4, 7)] {
+            driver_cmd_tx
+                .send(DriverCommand::PassengerArrived {
+                    at,
+                    destination,
+                    reduced_mobility: false,
+                    entrance: 0,
+                })
+                .await
+                .unwrap();
+        }
+        let mut delivered = 0;
+        let mut floors_by_elevator = HashMap::<ElevatorId, Vec<FloorId>>::new();
+        while let Ok(evt) = events_rx.recv().await {
+            match evt {
+                BuildingEvent::AtFloor(el, fl) => {
+                    floors_by_elevator.entry(el).or_default().push(fl)
+                }
+                BuildingEvent::PassengerDelivered(_) => {
+                    delivered += 1;
+                    if delivered == 2 {
+                        driver_cmd_tx.send(DriverCommand::Halt).await.unwrap();
+                    }
+                }
+                _ => {}
+            }
+        }
+        task.await.unwrap().unwrap();
+        assert_eq!(floors_by_elevator.len(), 2);
+        let items: Vec<_> = elevator.collect().await;
+        assert!(items
+            .iter()
+            .all(|item| matches!(item, StreamItem::Event(evt) if evt.elevator() == Some(1))));
+        let floors: Vec<_> = items
+            .iter()
+            .filter_map(|item| match item {
+                StreamItem::Event(BuildingEvent::AtFloor(_, fl)) => Some(*fl),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(floors, floors_by_elevator[&1]);
+    }
+}
@@ -0,0 +1,187 @@
+//! Faults injected between the building and the controller, to check that the controller
+//! copes with a building that doesn't always do as it's told. Enabled by the `faults`
+//! feature.
+
+use std::time::Duration;
+
+use crate::building::{BuildingCommand, BuildingEvent};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time;
+use tracing::{info, warn};
+
+/// How many events may wait for the controller after passing through the injector.
+const EVENTS_CAPACITY: usize = 1024;
+
+/// FaultRule is one kind of fault, each applied with the given probability.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaultRule {
+    /// Drop a `GoToFloor` command, as if the building never received it.
+    DropGoToFloor(f64),
+    /// Hold an `AtFloor` event back for the given time, letting later events overtake it.
+    DelayAtFloor(f64, Duration),
+    /// Stall the elevator that a `GoToFloor` command is for, holding the command back for the
+    /// given time.
+    StallElevator(f64, Duration),
+}
+
+/// FaultInjector sits between the building and the controller and applies its fault rules to
+/// the commands and events passing through. The same seed injects the same faults into the
+/// same sequence of commands and events.
+#[derive(Debug, Clone)]
+pub struct FaultInjector {
+    rules: Vec<FaultRule>,
+    seed: u64,
+}
+
+impl FaultInjector {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rules: Vec::new(),
+            seed,
+        }
+    }
+
+    /// Also inject the faults of `rule`.
+    pub fn with_rule(mut self, rule: FaultRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Pass the building's events and the controller's commands through the injector. Give
+    /// the controller the returned receiver and sender instead of the building's own.
+    pub fn wrap(
+        self,
+        mut events_rx: broadcast::Receiver<BuildingEvent>,
+        building_cmd_tx: mpsc::Sender<BuildingCommand>,
+    ) -> (
+        broadcast::Receiver<BuildingEvent>,
+        mpsc::Sender<BuildingCommand>,
+    ) {
+        let (faulty_events_tx, faulty_events_rx) = broadcast::channel(EVENTS_CAPACITY);
+        let (faulty_cmd_tx, mut faulty_cmd_rx) = mpsc::channel(building_cmd_tx.max_capacity());
+
+        // Commands and events each get their own generator so that the faults of one don't
+        // depend on how the two interleave.
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let rules = self.rules.clone();
+        tokio::spawn(async move {
+            while let Some(cmd) = faulty_cmd_rx.recv().await {
+                let BuildingCommand::GoToFloor(el, fl) = cmd else {
+                    if building_cmd_tx.send(cmd).await.is_err() {
+                        break;
+                    }
+                    continue;
+                };
+                let mut stall = Duration::ZERO;
+                let mut dropped = false;
+                for rule in &rules {
+                    match *rule {
+                        FaultRule::DropGoToFloor(p) if rng.gen_bool(p) => dropped = true,
+                        FaultRule::StallElevator(p, duration) if rng.gen_bool(p) => {
+                            stall = stall.max(duration)
+                        }
+                        _ => {}
+                    }
+                }
+                if dropped {
+                    info!(command = %cmd, "fault: command dropped");
+                    continue;
+                }
+                if stall.is_zero() {
+                    if building_cmd_tx.send(cmd).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                info!(
+                    elevator = el,
+                    stall_ms = stall.as_millis() as u64,
+                    "fault: elevator stalled"
+                );
+                let building_cmd_tx = building_cmd_tx.clone();
+                tokio::spawn(async move {
+                    time::sleep(stall).await;
+                    // The building may have stopped in the meantime.
+                    let _ = building_cmd_tx
+                        .send(BuildingCommand::GoToFloor(el, fl))
+                        .await;
+                });
+            }
+        });
+
+        let mut rng = StdRng::seed_from_u64(self.seed.wrapping_add(1));
+        let rules = self.rules;
+        tokio::spawn(async move {
+            loop {
+                let evt = match events_rx.recv().await {
+                    Ok(evt) => evt,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!(missed, "fault injector missed events");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let delay = match evt {
+                    BuildingEvent::AtFloor(..) => rules
+                        .iter()
+                        .filter_map(|rule| match *rule {
+                            FaultRule::DelayAtFloor(p, delay) if rng.gen_bool(p) => Some(delay),
+                            _ => None,
+                        })
+                        .max(),
+                    _ => None,
+                };
+                match delay {
+                    Some(delay) => {
+                        info!(event = %evt, delay_ms = delay.as_millis() as u64, "fault: event delayed");
+                        let faulty_events_tx = faulty_events_tx.clone();
+                        tokio::spawn(async move {
+                            time::sleep(delay).await;
+                            // The controller may have stopped.
+                            let _ = faulty_events_tx.send(evt);
+                        });
+                    }
+                    None => {
+                        // The controller may have stopped.
+                        let _ = faulty_events_tx.send(evt);
+                    }
+                }
+            }
+        });
+
+        (faulty_events_rx, faulty_cmd_tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::building::Building;
+    use crate::controller::ControllerConfig;
+    use crate::testing::Harness;
+
+    #[tokio::test(start_paused = true)]
+    async fn controller_recovers_from_dropped_commands() {
+        let injector = FaultInjector::new(154).with_rule(FaultRule::DropGoToFloor(0.5));
+        let mut harness =
+            Harness::start_with(Building::new(10, 1), |events_rx, cmd_tx, queries_rx| {
+                let (events_rx, cmd_tx) = injector.wrap(events_rx, cmd_tx);
+                let config = ControllerConfig {
+                    progress_timeout: Some(Duration::from_secs(5)),
+                    ..Default::default()
+                };
+                (events_rx, cmd_tx, queries_rx, config)
+            });
+        let arrivals = [(0, 7), (3, 1), (9, 0), (5, 6)];
+        for (at, destination) in arrivals {
+            harness.arrive(at, destination).await;
+        }
+        // Nobody is left stranded.
+        harness.wait_for_deliveries(arrivals.len()).await;
+        let (_, summary) = harness.halt().await;
+        assert_eq!(summary.delivered, arrivals.len());
+    }
+}
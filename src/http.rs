@@ -0,0 +1,139 @@
+//! Serves the summary of the simulation so far over HTTP, so long runs can be monitored, for
+//! example with `curl localhost:8080/stats`. Enabled by the `http` feature.
+
+use std::io;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::building::BuildingCommand;
+
+/// Accept connections on `listener` and answer `GET /stats` with the building's
+/// `SimulationSummary` so far, as JSON. Every other request gets a 404.
+pub async fn serve(
+    listener: TcpListener,
+    building_cmd_tx: mpsc::Sender<BuildingCommand>,
+) -> io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(answer(stream, building_cmd_tx.clone()));
+    }
+}
+
+async fn answer(stream: TcpStream, building_cmd_tx: mpsc::Sender<BuildingCommand>) {
+    let mut stream = BufReader::new(stream);
+    let mut request_line = String::new();
+    if stream.read_line(&mut request_line).await.is_err() {
+        return;
+    }
+    // The headers don't matter, but the client may wait until they are read.
+    let mut header = String::new();
+    while let Ok(n) = stream.read_line(&mut header).await {
+        if n == 0 || header.trim().is_empty() {
+            break;
+        }
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/stats")) => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let sent = building_cmd_tx
+                .send(BuildingCommand::Summary(reply_tx))
+                .await;
+            match (sent, reply_rx.await) {
+                (Ok(()), Ok(summary)) => {
+                    response("200 OK", "application/json", &(summary.to_json() + "\n"))
+                }
+                _ => response(
+                    "503 Service Unavailable",
+                    "text/plain",
+                    "the building has stopped\n",
+                ),
+            }
+        }
+        _ => response("404 Not Found", "text/plain", "not found\n"),
+    };
+    // The client may have gone away.
+    let _ = stream.get_mut().write_all(response.as_bytes()).await;
+}
+
+fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::building::{Building, BuildingEvent, DriverCommand};
+    use crate::controller::{self, ControllerConfig};
+    use tokio::io::AsyncReadExt;
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stats_are_served_while_the_simulation_runs() {
+        let building = Building::new(10, 1);
+        let elevator_configs = building.elevator_configs();
+        let (task, mut events_rx, building_cmd_tx, driver_cmd_tx) = building.start();
+        let (_queries_tx, queries_rx) = mpsc::channel(1);
+        tokio::spawn(controller::controller(
+            elevator_configs,
+            10,
+            events_rx.resubscribe(),
+            building_cmd_tx.clone(),
+            queries_rx,
+            ControllerConfig::default(),
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, building_cmd_tx.clone()));
+        for (at, destination) in [(0, 1), (9, 0)] {
+            driver_cmd_tx
+                .send(DriverCommand::PassengerArrived {
+                    at,
+                    destination,
+                    reduced_mobility: false,
+                    entrance: 0,
+                })
+                .await
+                .unwrap();
+        }
+        while !matches!(
+            events_rx.recv().await.unwrap(),
+            BuildingEvent::PassengerDelivered(_)
+        ) {}
+        // The clock moves on whenever the test waits for the connection, so the building is
+        // paused for the summary to stay put.
+        building_cmd_tx.send(BuildingCommand::Pause).await.unwrap();
+        while events_rx.recv().await.unwrap() != BuildingEvent::Paused {}
+
+        let response = get(addr, "/stats").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let stats: serde_json::Value = serde_json::from_str(body).unwrap();
+        // The other passenger has a long way to go yet.
+        assert_eq!(stats["delivered"], 1);
+        assert_eq!(stats["undelivered"].as_array().unwrap().len(), 1);
+        assert_eq!(stats["calls_by_floor"][0], 1);
+        assert_eq!(stats["calls_by_floor"][9], 1);
+        assert!(get(addr, "/other").await.starts_with("HTTP/1.1 404"));
+
+        driver_cmd_tx.send(DriverCommand::Halt).await.unwrap();
+        task.await.unwrap().unwrap();
+    }
+}
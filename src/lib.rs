@@ -0,0 +1,21 @@
+//! A simulation of elevators in a building, along with a controller that
+//! dispatches them and a driver that generates passenger traffic.
+
+pub mod analysis;
+pub mod building;
+pub mod compare;
+pub mod controller;
+pub mod dashboard;
+pub mod driver;
+pub mod error;
+pub mod event_log;
+pub mod event_stream;
+#[cfg(feature = "faults")]
+pub mod faults;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod observers;
+pub mod supervisor;
+#[cfg(test)]
+mod testing;
+pub mod traffic;
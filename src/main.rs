@@ -1,36 +1,164 @@
-use building::BuildingEvent;
-use tokio::sync::broadcast;
+use elevator::building::FloorLabels;
+use elevator::controller::strategy;
+use elevator::event_stream::Backpressure;
+#[cfg(not(feature = "tui"))]
+use elevator::event_stream::{EventStream, StreamItem};
+use elevator::{analysis, controller, dashboard, driver, traffic};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tracing_subscriber::filter::LevelFilter;
 
-mod building;
-mod controller;
-mod driver;
+/// Building used when the environment doesn't say otherwise.
+const DEFAULT_FLOORS: usize = 30;
+const DEFAULT_ELEVATORS: usize = 10;
+const DEFAULT_CAPACITY: usize = 10;
+const DEFAULT_TIME_SCALE: f64 = 1.0;
+const DEFAULT_PASSENGERS: usize = 1000;
+/// Events the printer and the recorder may fall behind by.
+#[cfg(not(feature = "tui"))]
+const PRINTED_EVENTS_CAPACITY: usize = 1024;
+const RECORDED_EVENTS_CAPACITY: usize = 1024;
+/// Simulated time between two clock events while recording. The recording times events by
+/// the building's clock, so it tells the time at every tick.
+const RECORDED_CLOCK_INTERVAL_MS: u64 = 100;
 
 #[tokio::main]
 async fn main() {
-    let building = driver::make_building();
+    // Log level, e.g. "debug" to also log every passenger arriving.
+    let log_level = std::env::var("ELEVATOR_LOG").map_or(LevelFilter::INFO, |level| {
+        level
+            .parse()
+            .unwrap_or_else(|_| panic!("ELEVATOR_LOG must be a log level, got {:?}", level))
+    });
+    tracing_subscriber::fmt().with_max_level(log_level).init();
+    let time_scale = env_or("ELEVATOR_TIME_SCALE", DEFAULT_TIME_SCALE);
+    // Run again with ELEVATOR_SEED set to this seed to reproduce the run.
+    let seed = env_or("ELEVATOR_SEED", rand::random());
+    println!("SEED {}", seed);
+    let mut building = driver::make_building(
+        env_or("ELEVATOR_FLOORS", DEFAULT_FLOORS),
+        env_or("ELEVATOR_COUNT", DEFAULT_ELEVATORS),
+        env_or("ELEVATOR_CAPACITY", DEFAULT_CAPACITY),
+    )
+    .with_time_scale(time_scale)
+    .with_seed(seed);
     let num_floors = building.num_floors();
-    let num_elevators = building.num_elevators();
+    let elevator_configs = building.elevator_configs();
+    // Printing may skip events rather than hold the simulation back. Events aren't printed
+    // while the TUI redraws the elevators in place.
+    #[cfg(not(feature = "tui"))]
+    let printed = building.subscribe(Backpressure::Drop, PRINTED_EVENTS_CAPACITY);
+    // Check every event for consistency once the run is over, if set. The building waits for
+    // the recording rather than have it miss events.
+    let check_events = std::env::var_os("ELEVATOR_CHECK_EVENTS").is_some();
+    if check_events {
+        building = building.with_clock_interval(RECORDED_CLOCK_INTERVAL_MS);
+    }
+    let recording = check_events.then(|| {
+        let recorded = building.subscribe(Backpressure::Block, RECORDED_EVENTS_CAPACITY);
+        tokio::spawn(analysis::record(recorded))
+    });
     let (building_task, events_rx, building_cmd_tx, driver_cmd_tx) = building.start();
 
-    tokio::spawn(print_events(events_rx.resubscribe()));
+    // Comma-separated floor names, starting from the ground floor, e.g. "G,M,1,2".
+    let floor_labels = std::env::var("ELEVATOR_FLOOR_LABELS")
+        .ok()
+        .map(|labels| FloorLabels::new(labels.split(',').map(str::to_owned).collect()));
+    #[cfg(not(feature = "tui"))]
+    tokio::spawn(print_events(
+        printed,
+        floor_labels.clone().unwrap_or_default(),
+    ));
+    // Send passengers for this many seconds of simulated time, if given, rather than a fixed
+    // number of them.
+    let (passengers_count, duration) = match std::env::var("ELEVATOR_DURATION_SECS") {
+        Ok(_) => (
+            usize::MAX,
+            Duration::from_secs(env_or("ELEVATOR_DURATION_SECS", 0)),
+        ),
+        Err(_) => (DEFAULT_PASSENGERS, Duration::MAX),
+    };
     let driver_handle = tokio::spawn(driver::driver(
-        num_floors,
-        1000,
+        passengers_count,
+        traffic::Until::new(traffic::Uniform::new(num_floors), duration),
+        time_scale,
+        seed,
         events_rx.resubscribe(),
         driver_cmd_tx,
     ));
+    let mut config = controller::ControllerConfig {
+        seed: Some(seed),
+        floor_labels,
+        ..Default::default()
+    };
+    if let Ok(name) = std::env::var("ELEVATOR_STRATEGY") {
+        let strategy = strategy::strategy_by_name(&name).unwrap_or_else(|err| panic!("{}", err));
+        config.strategy = Some(strategy.into());
+    }
+    // Stream the shaft diagram to terminals connecting to this address, if given.
+    if let Ok(addr) = std::env::var("ELEVATOR_DASHBOARD_ADDR") {
+        let listener = tokio::net::TcpListener::bind(&addr)
+            .await
+            .unwrap_or_else(|err| panic!("can't listen on {}: {}", addr, err));
+        let (diagrams_tx, _) = broadcast::channel(16);
+        tokio::spawn(dashboard::serve(listener, diagrams_tx.clone()));
+        config.dashboard = Some(diagrams_tx);
+    }
+    // Serve the summary so far to HTTP clients on this address, if given.
+    #[cfg(feature = "http")]
+    if let Ok(addr) = std::env::var("ELEVATOR_HTTP_ADDR") {
+        let listener = tokio::net::TcpListener::bind(&addr)
+            .await
+            .unwrap_or_else(|err| panic!("can't listen on {}: {}", addr, err));
+        tokio::spawn(elevator::http::serve(listener, building_cmd_tx.clone()));
+    }
+    // Nothing queries the controller in the demo.
+    let (_queries_tx, queries_rx) = mpsc::channel(1);
     tokio::spawn(controller::controller(
-        num_elevators,
+        elevator_configs,
         num_floors,
         events_rx,
         building_cmd_tx,
+        queries_rx,
+        config,
     ));
-    building_task.await.unwrap();
-    driver_handle.await.unwrap();
+    building_task
+        .await
+        .unwrap()
+        .expect("building stopped with an error");
+    driver_handle
+        .await
+        .unwrap()
+        .expect("driver stopped with an error");
+    if let Some(recording) = recording {
+        match analysis::validate_log(&recording.await.unwrap()) {
+            Ok(()) => println!("EVENTS CONSISTENT"),
+            Err(violations) => {
+                for violation in violations {
+                    println!("VIOLATION {}", violation);
+                }
+            }
+        }
+    }
+}
+
+/// Read a number from the environment variable `name`, or use `default` if it isn't set.
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be a number, got {:?}", name, value)),
+        Err(_) => default,
+    }
 }
 
-async fn print_events(mut events_rx: broadcast::Receiver<BuildingEvent>) {
-    while let Ok(evt) = events_rx.recv().await {
-        println!("BuildingEvent::{:?}", evt);
+#[cfg(not(feature = "tui"))]
+async fn print_events(mut events: EventStream, floor_labels: FloorLabels) {
+    while let Some(item) = events.next().await {
+        match item {
+            StreamItem::Event(evt) => println!("{}", floor_labels.labelled(&evt)),
+            item @ StreamItem::Lagged(_) => println!("{}", item),
+        }
     }
 }
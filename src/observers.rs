@@ -0,0 +1,194 @@
+//! Observers let code embedding the simulation react to particular kinds of building events,
+//! without having to receive and filter all of them.
+
+use std::collections::HashMap;
+use std::mem::{self, Discriminant};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::building::{BuildingEvent, ElevatorId, FloorId};
+use crate::event_stream::{EventStream, StreamItem};
+
+type Callback = Box<dyn FnMut(&BuildingEvent) + Send>;
+
+/// Observers holds callbacks by the kind of event they observe.
+#[derive(Default)]
+pub struct Observers {
+    callbacks: HashMap<Discriminant<BuildingEvent>, Vec<Callback>>,
+}
+
+impl Observers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call `callback` with every event of the same variant as `kind`, whatever its fields.
+    /// For example, `BuildingEvent::Paused` observes every pause and
+    /// `BuildingEvent::AtFloor(0, 0)` every elevator arriving at any floor.
+    pub fn on(
+        mut self,
+        kind: &BuildingEvent,
+        callback: impl FnMut(&BuildingEvent) + Send + 'static,
+    ) -> Self {
+        self.callbacks
+            .entry(mem::discriminant(kind))
+            .or_default()
+            .push(Box::new(callback));
+        self
+    }
+
+    /// Call `callback` with the floor of every passenger delivered.
+    pub fn on_delivered(self, mut callback: impl FnMut(FloorId) + Send + 'static) -> Self {
+        self.on(&BuildingEvent::PassengerDelivered(0), move |evt| {
+            if let BuildingEvent::PassengerDelivered(fl) = *evt {
+                callback(fl);
+            }
+        })
+    }
+
+    /// Call `callback` with the elevator and floor every time an elevator reaches a floor.
+    pub fn on_at_floor(
+        self,
+        mut callback: impl FnMut(ElevatorId, FloorId) + Send + 'static,
+    ) -> Self {
+        self.on(&BuildingEvent::AtFloor(0, 0), move |evt| {
+            if let BuildingEvent::AtFloor(el, fl) = *evt {
+                callback(el, fl);
+            }
+        })
+    }
+
+    /// Call `callback` with the elevator and floor every time an elevator opens its doors.
+    pub fn on_doors_opened(
+        self,
+        mut callback: impl FnMut(ElevatorId, FloorId) + Send + 'static,
+    ) -> Self {
+        self.on(&BuildingEvent::DoorsOpened(0, 0), move |evt| {
+            if let BuildingEvent::DoorsOpened(el, fl) = *evt {
+                callback(el, fl);
+            }
+        })
+    }
+
+    /// Call the callbacks observing this kind of event, in the order they were added.
+    pub fn dispatch(&mut self, evt: &BuildingEvent) {
+        if let Some(callbacks) = self.callbacks.get_mut(&mem::discriminant(evt)) {
+            for callback in callbacks {
+                callback(evt);
+            }
+        }
+    }
+
+    /// Dispatch every event until the building stops. Spawn this as a task.
+    pub async fn run(mut self, events_rx: broadcast::Receiver<BuildingEvent>) {
+        let mut events = EventStream::new(events_rx);
+        while let Some(item) = events.next().await {
+            match item {
+                StreamItem::Event(evt) => self.dispatch(&evt),
+                StreamItem::Lagged(count) => {
+                    warn!(missed = count, "observers missed events");
+                }
+            }
+        }
+    }
+}
+
+/// Follow elevator `el` until the building stops, giving its position over time as samples of
+/// the milliseconds of simulated time since the call and the floor it reached. The building
+/// reports every floor an elevator passes, so the samples follow it floor by floor. They are
+/// not interpolated: an elevator taking several ticks to travel from one floor to the next
+/// has no sample in between, and is at the floor it left until the sample of the next one.
+/// `time_scale` is the building's, see `Building::with_time_scale`.
+pub async fn trajectory(
+    events_rx: broadcast::Receiver<BuildingEvent>,
+    el: ElevatorId,
+    time_scale: f64,
+) -> Vec<(u64, FloorId)> {
+    let start = Instant::now();
+    let samples = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&samples);
+    Observers::new()
+        .on_at_floor(move |at_el, fl| {
+            if at_el == el {
+                let ms = start.elapsed().mul_f64(time_scale).as_millis() as u64;
+                recorded.lock().unwrap().push((ms, fl));
+            }
+        })
+        .run(events_rx)
+        .await;
+    let samples = mem::take(&mut *samples.lock().unwrap());
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::building::{Building, Direction, DriverCommand};
+    use crate::controller::{self, ControllerConfig};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn delivered_callback_fires_once_per_delivery() {
+        let (events_tx, events_rx) = broadcast::channel(16);
+        for evt in [
+            BuildingEvent::CallButtonPressed(0, Direction::Up, 0),
+            BuildingEvent::PassengerDelivered(3),
+            BuildingEvent::AtFloor(0, 4),
+            BuildingEvent::PassengerDelivered(4),
+            BuildingEvent::PassengerDelivered(4),
+            BuildingEvent::DoorsOpened(0, 4),
+        ] {
+            events_tx.send(evt).unwrap();
+        }
+        drop(events_tx);
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&delivered);
+        Observers::new()
+            .on_delivered(move |fl| recorded.lock().unwrap().push(fl))
+            .run(events_rx)
+            .await;
+        assert_eq!(*delivered.lock().unwrap(), [3, 4, 4]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn trajectory_follows_the_elevator_floor_by_floor() {
+        // Several ticks from one floor to the next.
+        let building = Building::new(10, 1).with_floor_times(300, 300);
+        let elevator_configs = building.elevator_configs();
+        let (task, mut events_rx, building_cmd_tx, driver_cmd_tx) = building.start();
+        let (_queries_tx, queries_rx) = mpsc::channel(1);
+        tokio::spawn(controller::controller(
+            elevator_configs,
+            10,
+            events_rx.resubscribe(),
+            building_cmd_tx,
+            queries_rx,
+            ControllerConfig::default(),
+        ));
+        let samples = tokio::spawn(trajectory(events_rx.resubscribe(), 0, 1.0));
+        driver_cmd_tx
+            .send(DriverCommand::PassengerArrived {
+                at: 0,
+                destination: 7,
+                reduced_mobility: false,
+                entrance: 0,
+            })
+            .await
+            .unwrap();
+        while !matches!(
+            events_rx.recv().await.unwrap(),
+            BuildingEvent::PassengerDelivered(_)
+        ) {}
+        driver_cmd_tx.send(DriverCommand::Halt).await.unwrap();
+        task.await.unwrap().unwrap();
+        let samples = samples.await.unwrap();
+        let floors: Vec<_> = samples.iter().map(|&(_, fl)| fl).collect();
+        assert_eq!(floors, (0..=7).collect::<Vec<_>>());
+        for pair in samples.windows(2) {
+            assert!(pair[1].0 >= pair[0].0 + 300, "{:?}", pair);
+        }
+    }
+}
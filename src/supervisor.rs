@@ -0,0 +1,373 @@
+//! Two-level group control for buildings split into banks of elevators. A supervisor routes
+//! each hall call to one of the banks serving its floor, and a controller for each bank picks
+//! which of its elevators answers it.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::warn;
+
+use crate::building::{
+    BuildingCommand, BuildingEvent, BuildingSnapshot, Direction, ElevatorConfig, ElevatorId,
+    FloorId,
+};
+use crate::controller::{self, ControllerConfig};
+use crate::error::Error;
+
+/// Events waiting for a bank's controller.
+const BANK_EVENTS_CAPACITY: usize = 100;
+
+/// Bank is a group of elevators run by a controller of its own. The bank serves every floor
+/// that one of its elevators stops at.
+#[derive(Debug, Clone, Default)]
+pub struct Bank {
+    pub elevators: Vec<ElevatorId>,
+    pub config: ControllerConfig,
+}
+
+/// Where the supervisor sends an event.
+enum Route {
+    /// To the bank of the given elevator.
+    Elevator(ElevatorId),
+    /// To the bank answering calls at the given floor going the given way, see `call_bank`.
+    Call(FloorId, Direction),
+    /// To the bank of the given elevator, if it is also the bank answering calls at the given
+    /// floor going the given way. A passenger declining an elevator of another bank waits
+    /// for their own bank, which already has the call.
+    Declined(ElevatorId, FloorId, Direction),
+    /// To every bank.
+    All,
+    /// To no bank.
+    Nowhere,
+}
+
+/// Run a controller for each of the `banks`, routing the building's events to them and their
+/// commands back to the building. Each bank's controller sees its elevators numbered from 0 in
+/// the order of `Bank::elevators`. Every elevator must belong to exactly one bank.
+pub async fn supervisor(
+    elevator_configs: Vec<ElevatorConfig>,
+    floors_count: usize,
+    mut events_rx: broadcast::Receiver<BuildingEvent>,
+    building_cmd_tx: mpsc::Sender<BuildingCommand>,
+    banks: Vec<Bank>,
+) -> Result<(), Error> {
+    // The bank of each elevator and its number within the bank.
+    let mut bank_of = vec![None; elevator_configs.len()];
+    for (bank_id, bank) in banks.iter().enumerate() {
+        for (local, &el) in bank.elevators.iter().enumerate() {
+            assert!(bank_of[el].is_none(), "elevator {} is in two banks", el);
+            bank_of[el] = Some((bank_id, local));
+        }
+    }
+    if let Some(el) = bank_of.iter().position(Option::is_none) {
+        panic!("elevator {} is in no bank", el);
+    }
+    let bank_of: Vec<(usize, ElevatorId)> = bank_of.into_iter().flatten().collect();
+
+    let served_floors: Arc<Vec<HashSet<FloorId>>> = Arc::new(
+        banks
+            .iter()
+            .map(|bank| {
+                (0..floors_count)
+                    .filter(|&fl| {
+                        bank.elevators
+                            .iter()
+                            .any(|&el| elevator_configs[el].stop_pattern.serves(fl))
+                    })
+                    .collect()
+            })
+            .collect(),
+    );
+    let mut bank_events = Vec::with_capacity(banks.len());
+    let mut controllers = Vec::with_capacity(banks.len());
+    // Nothing queries the banks' controllers.
+    let mut queries = Vec::with_capacity(banks.len());
+    for (bank_id, bank) in banks.into_iter().enumerate() {
+        let configs: Vec<ElevatorConfig> = bank
+            .elevators
+            .iter()
+            .map(|&el| elevator_configs[el].clone())
+            .collect();
+        let (events_tx, events_rx) = broadcast::channel(BANK_EVENTS_CAPACITY);
+        let (cmd_tx, cmd_rx) = mpsc::channel(building_cmd_tx.max_capacity());
+        let (queries_tx, queries_rx) = mpsc::channel(1);
+        tokio::spawn(forward_commands(
+            cmd_rx,
+            building_cmd_tx.clone(),
+            bank.elevators,
+            bank_id,
+            Arc::clone(&served_floors),
+        ));
+        controllers.push(tokio::spawn(controller::controller(
+            configs,
+            floors_count,
+            events_rx,
+            cmd_tx,
+            queries_rx,
+            bank.config,
+        )));
+        bank_events.push(events_tx);
+        queries.push(queries_tx);
+    }
+
+    loop {
+        let mut evt = match events_rx.recv().await {
+            Ok(evt) => evt,
+            Err(RecvError::Lagged(missed)) => {
+                warn!(missed, "supervisor missed events");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let banks: Vec<usize> = match route(&evt) {
+            Route::Elevator(el) => {
+                let (bank_id, local) = bank_of[el];
+                set_elevator(&mut evt, local);
+                vec![bank_id]
+            }
+            Route::Call(fl, direction) => call_bank(&served_floors, fl, direction)
+                .into_iter()
+                .collect(),
+            Route::Declined(el, fl, direction) => {
+                let (bank_id, local) = bank_of[el];
+                set_elevator(&mut evt, local);
+                (call_bank(&served_floors, fl, direction) == Some(bank_id))
+                    .then_some(bank_id)
+                    .into_iter()
+                    .collect()
+            }
+            Route::All => (0..bank_events.len()).collect(),
+            Route::Nowhere => Vec::new(),
+        };
+        for bank_id in banks {
+            // A bank whose controller has stopped has nothing left to do.
+            let _ = bank_events[bank_id].send(evt.clone());
+        }
+    }
+
+    // The banks' controllers stop once their events end.
+    drop(bank_events);
+    for controller in controllers {
+        controller.await.expect("bank controller panicked")?;
+    }
+    Ok(())
+}
+
+/// The bank answering calls at floor `fl` going in `direction`, given the floors each bank
+/// serves: the first bank serving the floor that also serves a floor that way, so that a call
+/// at a sky lobby goes to the bank the passenger is changing to. A call that no bank can take
+/// that way goes to the first bank serving its floor, and `None` if there is none.
+fn call_bank(
+    served_floors: &[HashSet<FloorId>],
+    fl: FloorId,
+    direction: Direction,
+) -> Option<usize> {
+    let serving = |bank_id: &usize| served_floors[*bank_id].contains(&fl);
+    let goes_that_way = |bank_id: &usize| {
+        served_floors[*bank_id].iter().any(|&to| match direction {
+            Direction::Up => to > fl,
+            Direction::Down => to < fl,
+        })
+    };
+    (0..served_floors.len())
+        .filter(serving)
+        .find(goes_that_way)
+        .or_else(|| (0..served_floors.len()).find(serving))
+}
+
+/// Pass the commands of bank `bank_id` on to the building, numbering its elevators as the
+/// building does.
+async fn forward_commands(
+    mut cmd_rx: mpsc::Receiver<BuildingCommand>,
+    building_cmd_tx: mpsc::Sender<BuildingCommand>,
+    elevators: Vec<ElevatorId>,
+    bank_id: usize,
+    served_floors: Arc<Vec<HashSet<FloorId>>>,
+) -> Result<(), Error> {
+    while let Some(cmd) = cmd_rx.recv().await {
+        let cmd = match cmd {
+            BuildingCommand::GoToFloor(el, fl) => BuildingCommand::GoToFloor(elevators[el], fl),
+            BuildingCommand::ExpressToLobby(el) => BuildingCommand::ExpressToLobby(elevators[el]),
+            BuildingCommand::AbortTrip(el) => BuildingCommand::AbortTrip(elevators[el]),
+            BuildingCommand::SetDirection(el, direction) => {
+                BuildingCommand::SetDirection(elevators[el], direction)
+            }
+            BuildingCommand::ScheduleMaintenance(el, start_ms, end_ms) => {
+                BuildingCommand::ScheduleMaintenance(elevators[el], start_ms, end_ms)
+            }
+            // The bank only sees its own elevators and the calls routed to it.
+            BuildingCommand::Snapshot(reply) => {
+                let (snapshot_tx, snapshot_rx) = oneshot::channel();
+                building_cmd_tx
+                    .send(BuildingCommand::Snapshot(snapshot_tx))
+                    .await?;
+                let Ok(snapshot) = snapshot_rx.await else {
+                    return Err(Error::BuildingStopped);
+                };
+                // Nobody to tell if the asker has gone away.
+                let _ = reply.send(BuildingSnapshot {
+                    elevators: elevators
+                        .iter()
+                        .map(|&el| snapshot.elevators[el].clone())
+                        .collect(),
+                    calls: snapshot
+                        .calls
+                        .into_iter()
+                        .filter(|&(fl, direction, _)| {
+                            call_bank(&served_floors, fl, direction) == Some(bank_id)
+                        })
+                        .collect(),
+                    calls_by_floor: snapshot.calls_by_floor,
+                });
+                continue;
+            }
+            cmd @ (BuildingCommand::Pause
+            | BuildingCommand::Resume
+            | BuildingCommand::Summary(_)) => cmd,
+        };
+        building_cmd_tx.send(cmd).await?;
+    }
+    Ok(())
+}
+
+/// Which banks should see the event.
+fn route(evt: &BuildingEvent) -> Route {
+    match *evt {
+        BuildingEvent::FloorButtonPressed(el, _)
+        | BuildingEvent::AtFloor(el, _)
+        | BuildingEvent::DoorsOpened(el, _)
+        | BuildingEvent::DoorsClosed(el, ..)
+        | BuildingEvent::DoorReopened(el)
+        | BuildingEvent::GhostStop(el, _)
+        | BuildingEvent::TripAborted(el, _)
+        | BuildingEvent::PassengerBoarded(el, _)
+        | BuildingEvent::UnreachableDestination(el, _)
+        | BuildingEvent::ForcedUnload(el, _)
+        | BuildingEvent::ExpressModeEngaged(el, _)
+        | BuildingEvent::OutOfService(el)
+        | BuildingEvent::BackInService(el)
+        | BuildingEvent::TooManyDestinations(el, _) => Route::Elevator(el),
+        BuildingEvent::BoardingDeclined(el, fl, direction) => Route::Declined(el, fl, direction),
+        BuildingEvent::CallButtonPressed(fl, direction, _)
+        | BuildingEvent::AccessibleCallButtonPressed(fl, direction)
+        | BuildingEvent::CallRejected(fl, direction)
+        | BuildingEvent::MissedBoarding(fl, direction)
+        | BuildingEvent::SlaViolated(fl, direction, _) => Route::Call(fl, direction),
+        BuildingEvent::PassengerDelivered(_)
+        | BuildingEvent::PassengerStayed(_)
+        | BuildingEvent::LongestWait(_)
+        | BuildingEvent::Clock(_)
+        | BuildingEvent::Paused
+        | BuildingEvent::Resumed
+        | BuildingEvent::StallDetected(..) => Route::All,
+        // No controller acts on bunching, which may span banks.
+        BuildingEvent::Bunching(_) => Route::Nowhere,
+    }
+}
+
+/// Change the elevator the event is about to `el`.
+fn set_elevator(evt: &mut BuildingEvent, el: ElevatorId) {
+    match evt {
+        BuildingEvent::FloorButtonPressed(id, _)
+        | BuildingEvent::AtFloor(id, _)
+        | BuildingEvent::DoorsOpened(id, _)
+        | BuildingEvent::DoorsClosed(id, ..)
+        | BuildingEvent::DoorReopened(id)
+        | BuildingEvent::GhostStop(id, _)
+        | BuildingEvent::TripAborted(id, _)
+        | BuildingEvent::PassengerBoarded(id, _)
+        | BuildingEvent::BoardingDeclined(id, ..)
+        | BuildingEvent::UnreachableDestination(id, _)
+        | BuildingEvent::ForcedUnload(id, _)
+        | BuildingEvent::ExpressModeEngaged(id, _)
+        | BuildingEvent::OutOfService(id)
+        | BuildingEvent::BackInService(id)
+        | BuildingEvent::TooManyDestinations(id, _) => *id = el,
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::building::{Building, DriverCommand, StopPattern};
+
+    #[test]
+    fn sky_lobby_calls_go_to_the_bank_that_goes_their_way() {
+        let served_floors = [(0..=10).collect(), (10..=19).collect()];
+        assert_eq!(call_bank(&served_floors, 5, Direction::Up), Some(0));
+        assert_eq!(call_bank(&served_floors, 15, Direction::Down), Some(1));
+        assert_eq!(call_bank(&served_floors, 10, Direction::Up), Some(1));
+        assert_eq!(call_bank(&served_floors, 10, Direction::Down), Some(0));
+        assert_eq!(call_bank(&served_floors, 20, Direction::Down), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn each_call_is_answered_by_one_bank() {
+        let building = Building::new(20, 2)
+            .with_stop_pattern(0, StopPattern::Bank(0, 10))
+            .with_stop_pattern(1, StopPattern::Bank(10, 19));
+        let elevator_configs = building.elevator_configs();
+        let (task, mut events_rx, building_cmd_tx, driver_cmd_tx) = building.start();
+        let banks = vec![
+            Bank {
+                elevators: vec![0],
+                ..Default::default()
+            },
+            Bank {
+                elevators: vec![1],
+                ..Default::default()
+            },
+        ];
+        tokio::spawn(supervisor(
+            elevator_configs,
+            20,
+            events_rx.resubscribe(),
+            building_cmd_tx,
+            banks,
+        ));
+        let arrivals = [(0, 5), (12, 18), (10, 15), (10, 3), (3, 15)];
+        for (at, destination) in arrivals {
+            driver_cmd_tx
+                .send(DriverCommand::PassengerArrived {
+                    at,
+                    destination,
+                    reduced_mobility: false,
+                    entrance: 0,
+                })
+                .await
+                .unwrap();
+        }
+        let mut events = Vec::new();
+        let mut delivered = 0;
+        while delivered < arrivals.len() {
+            let evt = events_rx.recv().await.unwrap();
+            if let BuildingEvent::PassengerDelivered(_) = evt {
+                delivered += 1;
+            }
+            events.push(evt);
+        }
+        driver_cmd_tx.send(DriverCommand::Halt).await.unwrap();
+        task.await.unwrap().unwrap();
+        // Elevators stopping at the sky lobby for one bank's calls aren't sent back there for
+        // the other bank's.
+        assert!(
+            !events
+                .iter()
+                .any(|evt| matches!(evt, BuildingEvent::GhostStop(..))),
+            "{:?}",
+            events
+        );
+        let boarded_at_lobby = |el| {
+            events
+                .iter()
+                .filter(|evt| **evt == BuildingEvent::PassengerBoarded(el, 10))
+                .count()
+        };
+        // Going up from the sky lobby, and changing banks there.
+        assert_eq!(boarded_at_lobby(1), 2);
+        assert_eq!(boarded_at_lobby(0), 1);
+    }
+}
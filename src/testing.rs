@@ -0,0 +1,225 @@
+//! Helpers for the tests: a building run under a controller, with every event it sends kept
+//! for the test to look at. Tests run with the clock paused, so simulated time passes as
+//! fast as the simulation can go.
+
+use std::time::Duration;
+
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+use crate::building::{
+    Building, BuildingCommand, BuildingEvent, BuildingTask, DriverCommand, FloorId,
+    SimulationSummary,
+};
+use crate::controller::{self, ControllerConfig, ControllerQuery, ControllerState};
+use crate::event_stream::{Backpressure, StreamItem};
+
+/// Longest a test waits for the simulation, in simulated time, before giving up.
+const TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Harness runs a building under a controller and records its events.
+pub struct Harness {
+    task: BuildingTask,
+    driver_cmd_tx: mpsc::Sender<DriverCommand>,
+    queries_tx: mpsc::Sender<ControllerQuery>,
+    building_cmd_tx: mpsc::Sender<BuildingCommand>,
+    /// Every event, as the building sent it, none dropped.
+    recorded_rx: mpsc::UnboundedReceiver<BuildingEvent>,
+    /// Events read so far by `next_event`.
+    pub log: Vec<BuildingEvent>,
+}
+
+impl Harness {
+    /// Start `building` under a controller with `config`.
+    pub fn start(building: Building, config: ControllerConfig) -> Self {
+        Self::start_with(building, |events_rx, building_cmd_tx, queries_rx| {
+            (events_rx, building_cmd_tx, queries_rx, config)
+        })
+    }
+
+    /// Start `building` under a controller with `config`, which hears the events sent on the
+    /// returned channel as well as the building's, for the test to make up events.
+    pub fn start_injecting(
+        building: Building,
+        config: ControllerConfig,
+    ) -> (Self, broadcast::Sender<BuildingEvent>) {
+        let mut inject = None;
+        let harness = Self::start_with(building, |mut events_rx, building_cmd_tx, queries_rx| {
+            let (events_tx, controller_events_rx) = broadcast::channel(64);
+            inject = Some(events_tx.clone());
+            tokio::spawn(async move {
+                while let Ok(evt) = events_rx.recv().await {
+                    if events_tx.send(evt).is_err() {
+                        break;
+                    }
+                }
+            });
+            (controller_events_rx, building_cmd_tx, queries_rx, config)
+        });
+        (harness, inject.expect("the controller was started"))
+    }
+
+    /// Start `building` under a controller, letting `wrap` change the channels and the
+    /// configuration the controller gets, for example to inject faults.
+    pub fn start_with(
+        mut building: Building,
+        wrap: impl FnOnce(
+            broadcast::Receiver<BuildingEvent>,
+            mpsc::Sender<BuildingCommand>,
+            mpsc::Receiver<ControllerQuery>,
+        ) -> (
+            broadcast::Receiver<BuildingEvent>,
+            mpsc::Sender<BuildingCommand>,
+            mpsc::Receiver<ControllerQuery>,
+            ControllerConfig,
+        ),
+    ) -> Self {
+        let num_floors = building.num_floors();
+        let elevator_configs = building.elevator_configs();
+        let mut recorded = building.subscribe(Backpressure::Block, 1024);
+        let (task, events_rx, building_cmd_tx, driver_cmd_tx) = building.start();
+        let (recorded_tx, recorded_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(StreamItem::Event(evt)) = recorded.next().await {
+                if recorded_tx.send(evt).is_err() {
+                    break;
+                }
+            }
+        });
+        let (queries_tx, queries_rx) = mpsc::channel(16);
+        let (controller_events_rx, controller_cmd_tx, queries_rx, config) =
+            wrap(events_rx, building_cmd_tx.clone(), queries_rx);
+        tokio::spawn(controller::controller(
+            elevator_configs,
+            num_floors,
+            controller_events_rx,
+            controller_cmd_tx,
+            queries_rx,
+            config,
+        ));
+        Self {
+            task,
+            driver_cmd_tx,
+            queries_tx,
+            building_cmd_tx,
+            recorded_rx,
+            log: Vec::new(),
+        }
+    }
+
+    /// Have a passenger arrive at `at` going to `destination`.
+    pub async fn arrive(&self, at: FloorId, destination: FloorId) {
+        self.drive(DriverCommand::PassengerArrived {
+            at,
+            destination,
+            reduced_mobility: false,
+            entrance: 0,
+        })
+        .await;
+    }
+
+    /// Send the building a command as the driver.
+    pub async fn drive(&self, cmd: DriverCommand) {
+        self.driver_cmd_tx
+            .send(cmd)
+            .await
+            .expect("building stopped");
+    }
+
+    /// Send the building a command.
+    pub async fn command(&self, cmd: BuildingCommand) {
+        self.building_cmd_tx
+            .send(cmd)
+            .await
+            .expect("building stopped");
+    }
+
+    /// The next event the building sent, or `None` once it has stopped.
+    pub async fn next_event(&mut self) -> Option<BuildingEvent> {
+        let evt = tokio::time::timeout(TIMEOUT, self.recorded_rx.recv())
+            .await
+            .expect("timed out waiting for an event")?;
+        self.log.push(evt.clone());
+        Some(evt)
+    }
+
+    /// Read events until one matches `matches`, and return it.
+    pub async fn wait_for(&mut self, matches: impl Fn(&BuildingEvent) -> bool) -> BuildingEvent {
+        loop {
+            let evt = self.next_event().await.expect("building stopped");
+            if matches(&evt) {
+                return evt;
+            }
+        }
+    }
+
+    /// Read events until `count` passengers have been delivered.
+    pub async fn wait_for_deliveries(&mut self, count: usize) {
+        let mut delivered = 0;
+        while delivered < count {
+            self.wait_for(|evt| matches!(evt, BuildingEvent::PassengerDelivered(_)))
+                .await;
+            delivered += 1;
+        }
+    }
+
+    /// Let the simulation run for `duration` of simulated time, reading its events.
+    pub async fn run_for(&mut self, duration: Duration) {
+        let deadline = tokio::time::Instant::now() + duration;
+        while let Ok(Some(evt)) = tokio::time::timeout_at(deadline, self.recorded_rx.recv()).await {
+            self.log.push(evt);
+        }
+    }
+
+    /// Ask the controller the query made by `query` with the reply channel, and return its
+    /// reply.
+    pub async fn query<T>(&self, query: impl FnOnce(oneshot::Sender<T>) -> ControllerQuery) -> T {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.queries_tx
+            .send(query(reply_tx))
+            .await
+            .expect("controller stopped");
+        reply_rx.await.expect("controller stopped")
+    }
+
+    /// Tell the controller something it doesn't reply to.
+    pub async fn tell(&self, query: ControllerQuery) {
+        self.queries_tx
+            .send(query)
+            .await
+            .expect("controller stopped");
+    }
+
+    /// The controller's view of the building.
+    pub async fn state(&self) -> ControllerState {
+        self.query(ControllerQuery::State).await
+    }
+
+    /// Halt the building, and return its summary once every event it sent has been read.
+    pub async fn halt(mut self) -> (Vec<BuildingEvent>, SimulationSummary) {
+        // The building may have stopped already.
+        let _ = self.driver_cmd_tx.send(DriverCommand::Halt).await;
+        while self.next_event().await.is_some() {}
+        let summary = self
+            .task
+            .await
+            .expect("building task panicked")
+            .expect("building stopped with an error");
+        (self.log, summary)
+    }
+}
+
+/// Run `building` under a controller with `config` until the passengers of `arrivals`, given
+/// as the floor they arrive at and the floor they go to, arriving one after the other, have
+/// all been delivered. Returns every event and the summary.
+pub async fn run(
+    building: Building,
+    config: ControllerConfig,
+    arrivals: &[(FloorId, FloorId)],
+) -> (Vec<BuildingEvent>, SimulationSummary) {
+    let mut harness = Harness::start(building, config);
+    for &(at, destination) in arrivals {
+        harness.arrive(at, destination).await;
+    }
+    harness.wait_for_deliveries(arrivals.len()).await;
+    harness.halt().await
+}
@@ -0,0 +1,391 @@
+//! Traffic patterns decide when and where passengers arrive, for the driver to play out.
+
+use crate::building::FloorId;
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// A TrafficPattern generates passenger arrivals one after the other.
+pub trait TrafficPattern {
+    /// The next passenger to arrive after the one at simulated time `now`: the floor they
+    /// arrive at, the floor they are going to and how long after `now` they arrive. Returns
+    /// `None` once nobody else comes.
+    fn next_arrival(
+        &mut self,
+        now: Duration,
+        rng: &mut impl Rng,
+    ) -> Option<(FloorId, FloorId, Duration)>;
+}
+
+/// Uniform traffic has passengers arrive at random floors, going to random floors, up to
+/// 300ms apart. Now and then a group of ten arrives together.
+#[derive(Debug, Clone)]
+pub struct Uniform {
+    num_floors: usize,
+    /// The group arriving together: their floor, their destination and how many are left.
+    group: Option<(FloorId, FloorId, usize)>,
+}
+
+/// Number of passengers in a group arriving together.
+const GROUP_SIZE: usize = 10;
+
+impl Uniform {
+    pub fn new(num_floors: usize) -> Self {
+        assert!(num_floors > 1, "passengers need another floor to go to");
+        Self {
+            num_floors,
+            group: None,
+        }
+    }
+}
+
+impl TrafficPattern for Uniform {
+    fn next_arrival(
+        &mut self,
+        _now: Duration,
+        rng: &mut impl Rng,
+    ) -> Option<(FloorId, FloorId, Duration)> {
+        if let Some((at, destination, left)) = self.group {
+            self.group = (left > 1).then_some((at, destination, left - 1));
+            return Some((at, destination, Duration::ZERO));
+        }
+        let at = rng.gen_range(0..self.num_floors);
+        let destination = random_destination(rng, at, self.num_floors);
+        let wait_time = Duration::from_millis(rng.gen_range(1..=300));
+        // 5% chance of high traffic.
+        if rng.gen_range(0..100) >= 95 {
+            self.group = Some((at, destination, GROUP_SIZE - 1));
+        }
+        Some((at, destination, wait_time))
+    }
+}
+
+/// Scheduled traffic has passengers come through the lobby turnstile at the rate given by a
+/// `RateSchedule`, going to random floors. Nobody comes once the schedule drops to zero for
+/// good.
+#[derive(Debug, Clone)]
+pub struct Scheduled {
+    num_floors: usize,
+    schedule: RateSchedule,
+}
+
+impl Scheduled {
+    pub fn new(num_floors: usize, schedule: RateSchedule) -> Self {
+        assert!(num_floors > 1, "passengers need another floor to go to");
+        Self {
+            num_floors,
+            schedule,
+        }
+    }
+}
+
+impl TrafficPattern for Scheduled {
+    fn next_arrival(
+        &mut self,
+        now: Duration,
+        rng: &mut impl Rng,
+    ) -> Option<(FloorId, FloorId, Duration)> {
+        let gap = self.schedule.next_gap(now, rng)?;
+        Some((0, random_destination(rng, 0, self.num_floors), gap))
+    }
+}
+
+/// Recorded traffic replays a fixed list of arrivals, so that several simulations can see
+/// exactly the same passengers.
+#[derive(Debug, Clone, Default)]
+pub struct Recorded {
+    /// Arrivals still to come: floor, destination and time since the start.
+    arrivals: VecDeque<(FloorId, FloorId, Duration)>,
+}
+
+impl Recorded {
+    /// Replay passengers arriving at the given floors, going to the given floors, at the
+    /// given times since the start.
+    pub fn new(mut arrivals: Vec<(FloorId, FloorId, Duration)>) -> Self {
+        arrivals.sort_by_key(|&(_, _, at)| at);
+        Self {
+            arrivals: arrivals.into(),
+        }
+    }
+
+    /// Record the first `count` arrivals of `pattern`.
+    pub fn record(pattern: &mut impl TrafficPattern, count: usize, rng: &mut impl Rng) -> Self {
+        let mut now = Duration::ZERO;
+        let mut arrivals = VecDeque::with_capacity(count);
+        while arrivals.len() < count {
+            let Some((at, destination, delay)) = pattern.next_arrival(now, rng) else {
+                break;
+            };
+            now += delay;
+            arrivals.push_back((at, destination, now));
+        }
+        Self { arrivals }
+    }
+
+    /// Number of arrivals still to come.
+    pub fn len(&self) -> usize {
+        self.arrivals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.arrivals.is_empty()
+    }
+}
+
+impl TrafficPattern for Recorded {
+    fn next_arrival(
+        &mut self,
+        now: Duration,
+        _rng: &mut impl Rng,
+    ) -> Option<(FloorId, FloorId, Duration)> {
+        let (at, destination, time) = self.arrivals.pop_front()?;
+        Some((at, destination, time.saturating_sub(now)))
+    }
+}
+
+/// Until follows another pattern up to a given simulated time, after which nobody else comes.
+#[derive(Debug, Clone)]
+pub struct Until<P> {
+    pattern: P,
+    end: Duration,
+}
+
+impl<P> Until<P> {
+    /// Follow `pattern` for `duration` of simulated time since the start.
+    pub fn new(pattern: P, duration: Duration) -> Self {
+        Self {
+            pattern,
+            end: duration,
+        }
+    }
+}
+
+impl<P: TrafficPattern> TrafficPattern for Until<P> {
+    fn next_arrival(
+        &mut self,
+        now: Duration,
+        rng: &mut impl Rng,
+    ) -> Option<(FloorId, FloorId, Duration)> {
+        let (at, destination, delay) = self.pattern.next_arrival(now, rng)?;
+        (now.saturating_add(delay) <= self.end).then_some((at, destination, delay))
+    }
+}
+
+/// RoundTrips sends passengers back where they came from. A passenger arriving at a floor that
+/// an earlier passenger went to may be that passenger coming back, going to the floor they
+/// came from, as when people who went up from the lobby in the morning go home. The arrivals
+/// otherwise follow another pattern.
+#[derive(Debug, Clone)]
+pub struct RoundTrips<P> {
+    pattern: P,
+    correlation: f64,
+    /// Floors that passengers who haven't come back yet came from, by the floor they went to,
+    /// earliest first.
+    outbound: HashMap<FloorId, VecDeque<FloorId>>,
+}
+
+impl<P> RoundTrips<P> {
+    /// Follow `pattern`, except that each passenger arriving at a floor that someone went to
+    /// earlier makes that trip back with probability `correlation`, from 0 for independent
+    /// trips to 1 for every trip mirroring an earlier one where it can.
+    pub fn new(pattern: P, correlation: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&correlation),
+            "correlation must be between 0 and 1"
+        );
+        Self {
+            pattern,
+            correlation,
+            outbound: HashMap::new(),
+        }
+    }
+}
+
+impl<P: TrafficPattern> TrafficPattern for RoundTrips<P> {
+    fn next_arrival(
+        &mut self,
+        now: Duration,
+        rng: &mut impl Rng,
+    ) -> Option<(FloorId, FloorId, Duration)> {
+        let (at, destination, delay) = self.pattern.next_arrival(now, rng)?;
+        let returning = self
+            .outbound
+            .get_mut(&at)
+            .filter(|origins| !origins.is_empty() && rng.gen_bool(self.correlation))
+            .and_then(VecDeque::pop_front);
+        match returning {
+            Some(origin) => Some((at, origin, delay)),
+            None => {
+                self.outbound.entry(destination).or_default().push_back(at);
+                Some((at, destination, delay))
+            }
+        }
+    }
+}
+
+/// RateSchedule is a piecewise-linear arrival rate over simulated time, as counted by a
+/// turnstile at the lobby. For example, arrivals may ramp up over the first minute, plateau,
+/// then ramp down.
+#[derive(Debug, Clone)]
+pub struct RateSchedule {
+    /// Arrival rates in passengers per second at times since the start, sorted by time.
+    points: Vec<(Duration, f64)>,
+}
+
+impl RateSchedule {
+    /// Create a schedule from arrival rates, in passengers per second, at given times since
+    /// the start. The rate is interpolated between points and held at the last rate after the
+    /// last point.
+    pub fn new(mut points: Vec<(Duration, f64)>) -> Self {
+        points.sort_by_key(|&(at, _)| at);
+        Self { points }
+    }
+
+    /// The arrival rate, in passengers per second, at time `t`.
+    pub fn rate_at(&self, t: Duration) -> f64 {
+        let after = self.points.partition_point(|&(at, _)| at <= t);
+        match (
+            after.checked_sub(1).map(|i| self.points[i]),
+            self.points.get(after),
+        ) {
+            (Some((t0, r0)), Some(&(t1, r1))) => {
+                let progress = (t - t0).as_secs_f64() / (t1 - t0).as_secs_f64();
+                r0 + (r1 - r0) * progress
+            }
+            (Some((_, rate)), None) | (None, Some(&(_, rate))) => rate,
+            (None, None) => 0.0,
+        }
+    }
+
+    /// Sample the gap between an arrival at time `t` and the next one. Returns `None` if
+    /// nobody arrives after `t`.
+    pub fn next_gap(&self, t: Duration, rng: &mut impl Rng) -> Option<Duration> {
+        let max_rate = self
+            .points
+            .iter()
+            .map(|&(_, rate)| rate)
+            .fold(0.0, f64::max);
+        if max_rate <= 0.0 {
+            return None;
+        }
+        // Draw arrivals at the highest rate and keep each with probability proportional to
+        // the rate at its time, which follows the schedule exactly.
+        let mut next = t;
+        loop {
+            let past_last_point = self.points.last().is_some_and(|&(at, _)| next >= at);
+            if past_last_point && self.rate_at(next) <= 0.0 {
+                return None;
+            }
+            let uniform: f64 = rng.gen_range(f64::EPSILON..1.0);
+            next += Duration::from_secs_f64(-uniform.ln() / max_rate);
+            if rng.gen_bool((self.rate_at(next) / max_rate).clamp(0.0, 1.0)) {
+                return Some(next - t);
+            }
+        }
+    }
+}
+
+/// Pick a floor other than `at` for a passenger to go to.
+fn random_destination(rng: &mut impl Rng, at: usize, num_floors: usize) -> usize {
+    let destination = rng.gen_range(0..num_floors - 1);
+    if destination >= at {
+        destination + 1
+    } else {
+        destination
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn nobody_goes_to_the_floor_they_arrive_at() {
+        let mut pattern = Uniform::new(3);
+        let mut rng = StdRng::seed_from_u64(125);
+        for _ in 0..10_000 {
+            let (at, destination, _) = pattern.next_arrival(Duration::ZERO, &mut rng).unwrap();
+            assert_ne!(at, destination);
+            assert!(destination < 3);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "another floor")]
+    fn single_floor_traffic_is_refused() {
+        Uniform::new(1);
+    }
+
+    #[test]
+    fn arrivals_follow_the_rate_schedule() {
+        let secs = Duration::from_secs;
+        // Ramp up over a minute, plateau for a minute, ramp down over a minute.
+        let schedule = RateSchedule::new(vec![
+            (secs(0), 0.0),
+            (secs(60), 10.0),
+            (secs(120), 10.0),
+            (secs(180), 0.0),
+        ]);
+        assert_eq!(schedule.rate_at(secs(30)), 5.0);
+        assert_eq!(schedule.rate_at(secs(90)), 10.0);
+        assert_eq!(schedule.rate_at(secs(200)), 0.0);
+
+        let mut pattern = Scheduled::new(10, schedule);
+        let mut rng = StdRng::seed_from_u64(112);
+        let mut now = Duration::ZERO;
+        let mut counts = [0; 3];
+        while let Some((at, destination, gap)) = pattern.next_arrival(now, &mut rng) {
+            assert_eq!(at, 0);
+            assert_ne!(destination, 0);
+            now += gap;
+            counts[(now.as_secs() / 60) as usize] += 1;
+        }
+        // The area under each segment of the schedule.
+        for (count, expected) in counts.into_iter().zip([300.0, 600.0, 300.0]) {
+            assert!(
+                (count as f64 - expected).abs() < expected * 0.15,
+                "{} arrivals where {} were expected",
+                count,
+                expected
+            );
+        }
+    }
+
+    /// The floors passengers of `pattern` arrive at and go to, until nobody else comes.
+    fn trips(mut pattern: impl TrafficPattern, rng: &mut impl Rng) -> Vec<(FloorId, FloorId)> {
+        let mut now = Duration::ZERO;
+        let mut trips = Vec::new();
+        while let Some((at, destination, delay)) = pattern.next_arrival(now, rng) {
+            now += delay;
+            trips.push((at, destination));
+        }
+        trips
+    }
+
+    #[test]
+    fn fully_correlated_trips_come_back_where_they_came_from() {
+        let secs = Duration::from_secs;
+        let recorded = Recorded::new(vec![
+            // The morning.
+            (0, 5, secs(1)),
+            (0, 7, secs(2)),
+            (2, 5, secs(3)),
+            // The evening, when the destinations are ignored if someone comes back.
+            (5, 9, secs(100)),
+            (7, 9, secs(101)),
+            (5, 9, secs(102)),
+            (5, 9, secs(103)),
+        ]);
+        let mut rng = StdRng::seed_from_u64(176);
+        let correlated = trips(RoundTrips::new(recorded.clone(), 1.0), &mut rng);
+        assert_eq!(
+            correlated,
+            [(0, 5), (0, 7), (2, 5), (5, 0), (7, 0), (5, 2), (5, 9)]
+        );
+        // Without correlation, everyone goes where they were going.
+        let independent = trips(RoundTrips::new(recorded.clone(), 0.0), &mut rng);
+        assert_eq!(independent, trips(recorded, &mut rng));
+    }
+}